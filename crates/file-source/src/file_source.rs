@@ -79,6 +79,12 @@ impl FileSource {
     }
 }
 
+impl std::fmt::Display for FileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.as_str())
+    }
+}
+
 impl<'de> Deserialize<'de> for FileSource {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -0,0 +1,241 @@
+//! Cached outputs of the functions of the nodes opting in with `cache: true`.
+//!
+//! The output of a call is cached under a key hashing the digest of the
+//! component, the name of the function and the parameters of the call, so
+//! that a later call of the same function with the same parameters reuses it
+//! instead of running, until it expires after the node's `cache_ttl`. Changing
+//! a component therefore invalidates the outputs of its functions, which
+//! `CacheStore::remove` clears along with the expired ones.
+//!
+//! Outputs holding the value of a secret are never cached, so that no secret
+//! is written to the store, nor are the `err` cases of results, which are
+//! often transient.
+//!
+//! Tasks running in deterministic mode or recording a trace neither reuse nor
+//! cache outputs, so that every call they make runs and is recorded.
+
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use wasmtime::component::Val;
+use workflow::{ComponentName, FunctionName};
+
+use crate::{checkpoint::sha256, secrets::Redactor, trace::TracedVal};
+
+/// A cached output.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    /// Name of the component of the function, as its dependency is named
+    pub component: String,
+    /// Milliseconds since the Unix epoch after which the output is stale,
+    /// never when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    pub function: String,
+    pub output: TracedVal,
+}
+
+impl Entry {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now())
+    }
+}
+
+/// Where cached outputs live, by key.
+pub trait CacheStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<Entry>, Error>;
+    /// Replaces the previous entry of the key, if any.
+    fn insert(&self, key: &str, entry: &Entry) -> Result<(), Error>;
+    /// Removes the entries for which `remove` holds, returning their number.
+    fn remove(&self, remove: &dyn Fn(&Entry) -> bool) -> Result<usize, Error>;
+}
+
+/// Cached outputs kept in a directory, as a JSON file for every key.
+#[derive(Debug)]
+pub struct FileCache {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+}
+
+impl CacheStore for FileCache {
+    fn get(&self, key: &str) -> Result<Option<Entry>, Error> {
+        match fs::read_to_string(self.path(key)) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    fn insert(&self, key: &str, entry: &Entry) -> Result<(), Error> {
+        let _lock = self.lock.lock().unwrap();
+        // Written aside first, so that the file is never left half written
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        let written = path.with_extension("json.tmp");
+        fs::write(&written, serde_json::to_vec_pretty(entry)?)?;
+        fs::rename(written, path)?;
+        Ok(())
+    }
+
+    fn remove(&self, remove: &dyn Fn(&Entry) -> bool) -> Result<usize, Error> {
+        let _lock = self.lock.lock().unwrap();
+        let files = match fs::read_dir(&self.dir) {
+            Ok(files) => files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => Err(e)?,
+        };
+        let mut removed = 0;
+        for file in files {
+            let path = file?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            // Unreadable entries are never used, and removed along the others
+            let entry = fs::read_to_string(&path)
+                .ok()
+                .and_then(|json| serde_json::from_str(&json).ok());
+            if entry.is_none_or(|entry| remove(&entry)) {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// How the outputs of a function are cached.
+#[derive(Clone, Debug)]
+pub(crate) struct Caching {
+    pub(crate) component_name: ComponentName,
+    /// Digest of the component
+    pub(crate) digest: String,
+    pub(crate) function: FunctionName,
+    pub(crate) ttl: Option<Duration>,
+}
+
+impl Caching {
+    /// Returns the key of the output of a call with the given parameters.
+    pub(crate) fn key(&self, params: &[Val]) -> String {
+        let params: Vec<_> = params.iter().map(TracedVal::from).collect();
+        sha256(&serde_json::to_vec(&(&self.digest, &self.function.0, params)).unwrap())
+    }
+}
+
+/// Looks up and saves the cached outputs of the functions of a task.
+pub(crate) struct Cache {
+    redactor: Arc<Redactor>,
+    store: Arc<dyn CacheStore>,
+}
+
+impl Cache {
+    pub(crate) fn new(redactor: Arc<Redactor>, store: Arc<dyn CacheStore>) -> Self {
+        Self { redactor, store }
+    }
+
+    /// Returns the output cached under the key, unless it expired.
+    pub(crate) fn get(&self, key: &str) -> Option<Val> {
+        // A task runs the function rather than failing
+        let entry = match self.store.get(key) {
+            Ok(entry) => entry?,
+            Err(e) => {
+                tracing::warn!(%key, error = %e, "Cannot read the cached output");
+                return None;
+            }
+        };
+        if entry.is_expired() {
+            return None;
+        }
+        match entry.output.to_val() {
+            Ok(val) => Some(val),
+            Err(e) => {
+                tracing::warn!(%key, error = %e, "Invalid cached output");
+                None
+            }
+        }
+    }
+
+    /// Caches the output of a call, unless it holds a secret or an error.
+    pub(crate) fn insert(&self, key: &str, caching: &Caching, val: &Val) {
+        if matches!(val, Val::Result(Err(_))) || self.redactor.redact_val(val) != *val {
+            return;
+        }
+        let entry = Entry {
+            component: caching.component_name.0.clone(),
+            expires_at: caching
+                .ttl
+                .map(|ttl| now().saturating_add(ttl.as_millis() as u64)),
+            function: caching.function.0.clone(),
+            output: TracedVal::from(val),
+        };
+        if let Err(e) = self.store.insert(key, &entry) {
+            tracing::warn!(%key, error = %e, "Cannot cache the output");
+        }
+    }
+}
+
+/// Returns the milliseconds elapsed since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    File(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_outputs_expire_and_are_removed() {
+        let dir = std::env::temp_dir().join(format!("cache-{}", std::process::id()));
+        let store: Arc<dyn CacheStore> = Arc::new(FileCache::new(&dir));
+        let cache = Cache::new(Arc::new(Redactor::new(["secret"])), Arc::clone(&store));
+        let caching = |ttl| Caching {
+            component_name: ComponentName("math".to_string()),
+            digest: sha256(b"math"),
+            function: FunctionName("inc".to_string()),
+            ttl,
+        };
+        let key = caching(None).key(&[Val::U32(1)]);
+        assert_ne!(key, caching(None).key(&[Val::U32(2)]));
+
+        cache.insert(&key, &caching(None), &Val::U32(2));
+        assert_eq!(cache.get(&key), Some(Val::U32(2)));
+        cache.insert(&key, &caching(Some(Duration::ZERO)), &Val::U32(2));
+        assert_eq!(cache.get(&key), None);
+        let secret = caching(None).key(&[Val::U32(3)]);
+        cache.insert(&secret, &caching(None), &Val::String("secret".to_string()));
+        assert_eq!(cache.get(&secret), None);
+
+        assert_eq!(store.remove(&Entry::is_expired).unwrap(), 1);
+        assert!(store.get(&key).unwrap().is_none());
+        fs::remove_dir_all(dir).ok();
+    }
+}
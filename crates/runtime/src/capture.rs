@@ -0,0 +1,126 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use hyper::body::Bytes;
+use wasmtime_wasi::{OutputStream, Pollable, StdoutStream, StreamResult, async_trait};
+
+/// Number of bytes a guest may write at once, however many bytes the capture
+/// still keeps.
+const WRITE_PERMIT: usize = 64 * 1024;
+
+/// What a component writes to its stdout or its stderr, split into lines
+/// passed on as they are written, up to a number of bytes, the rest being
+/// dropped.
+///
+/// Writing never fails, so a guest writing too much is not interrupted.
+#[derive(Clone)]
+pub(crate) struct Capture(Arc<Mutex<Captured>>);
+
+struct Captured {
+    /// Bytes written since the last line break
+    line: Vec<u8>,
+    /// Receives every line written
+    on_line: Box<dyn Fn(String) + Send + Sync>,
+    /// Number of bytes still kept
+    remaining: usize,
+    truncated: bool,
+}
+
+impl Captured {
+    fn push_line(&mut self) {
+        let line = mem::take(&mut self.line);
+        (self.on_line)(String::from_utf8_lossy(&line).into_owned());
+    }
+}
+
+impl Capture {
+    pub(crate) fn new(max_bytes: usize, on_line: impl Fn(String) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(Mutex::new(Captured {
+            line: Vec::new(),
+            on_line: Box::new(on_line),
+            remaining: max_bytes,
+            truncated: false,
+        })))
+    }
+
+    /// Passes on the last line even without line break, followed by a note
+    /// when bytes were dropped.
+    pub(crate) fn finish(&self) {
+        let mut captured = self.0.lock().unwrap();
+        if !captured.line.is_empty() {
+            captured.push_line();
+        }
+        if mem::take(&mut captured.truncated) {
+            (captured.on_line)("[output truncated]".to_string());
+        }
+    }
+}
+
+impl StdoutStream for Capture {
+    fn stream(&self) -> Box<dyn OutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl OutputStream for Capture {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        let mut captured = self.0.lock().unwrap();
+        let kept = bytes.len().min(captured.remaining);
+        captured.remaining -= kept;
+        captured.truncated |= kept < bytes.len();
+        for &byte in &bytes[..kept] {
+            if byte == b'\n' {
+                captured.push_line();
+            } else {
+                captured.line.push(byte);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(WRITE_PERMIT)
+    }
+}
+
+#[async_trait]
+impl Pollable for Capture {
+    async fn ready(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_lines_up_to_max_bytes() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let capture = Capture::new(16, {
+            let lines = Arc::clone(&lines);
+            move |line| lines.lock().unwrap().push(line)
+        });
+        let mut stream = capture.stream();
+        stream.write(Bytes::from("one\ntw")).unwrap();
+        assert_eq!(*lines.lock().unwrap(), ["one"]);
+        stream.write(Bytes::from("o\nthree\nfour")).unwrap();
+        assert_eq!(*lines.lock().unwrap(), ["one", "two", "three"]);
+        capture.finish();
+        assert_eq!(
+            mem::take(&mut *lines.lock().unwrap()),
+            ["one", "two", "three", "fo", "[output truncated]"]
+        );
+        capture.finish();
+        assert!(lines.lock().unwrap().is_empty());
+    }
+}
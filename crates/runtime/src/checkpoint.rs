@@ -0,0 +1,201 @@
+//! Checkpoints of tasks, to resume a task that failed from where it stopped
+//! instead of running it again from the start.
+//!
+//! A task saves a `Checkpoint` to its `CheckpointStore` every time one of its
+//! nodes succeeds, holding its inputs and the outputs of the nodes that
+//! succeeded so far. `Task::resume` rebuilds the task from it, and only runs
+//! the nodes that did not succeed, as long as neither the workflow nor any of
+//! its components changed since.
+//!
+//! The nodes of the workflows that nodes use are never checkpointed: a node
+//! using a workflow runs it again as a whole unless it succeeded. Outputs
+//! holding the value of a secret are not checkpointed either, so that no
+//! secret is written to the store, nor are the nodes whose function returns
+//! nothing: these nodes run again.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use ring::digest::{SHA256, digest};
+use serde::{Deserialize, Serialize};
+use wasmtime::component::Val;
+use workflow::NodeId;
+
+use crate::{secrets::Redactor, task::TaskId, trace::TracedVal};
+
+/// What a task achieved so far.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Checkpoint {
+    pub digests: Digests,
+    /// Values of the workflow's inputs
+    pub inputs: BTreeMap<String, TracedVal>,
+    /// Path of the manifest the workflow was loaded from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<PathBuf>,
+    /// Outputs of the nodes that succeeded, by node ID
+    pub outputs: BTreeMap<String, TracedVal>,
+    pub run_id: TaskId,
+}
+
+/// SHA-256 digests of a workflow and of its components, telling whether they
+/// changed.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Digests {
+    /// Digests of the components, by name, those of the workflows nodes use
+    /// being named `workflow/component`
+    pub components: BTreeMap<String, String>,
+    /// Digest of the workflow, and of the workflows its nodes use
+    pub workflow: String,
+}
+
+/// Where the checkpoints of tasks live.
+pub trait CheckpointStore: Send + Sync {
+    fn load(&self, run_id: &TaskId) -> Result<Option<Checkpoint>, Error>;
+    /// Replaces the previous checkpoint of the task, if any.
+    fn save(&self, checkpoint: &Checkpoint) -> Result<(), Error>;
+}
+
+/// Checkpoints kept in a directory, as a JSON file for every task.
+#[derive(Debug)]
+pub struct FileCheckpoints {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileCheckpoints {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn path(&self, run_id: &TaskId) -> PathBuf {
+        self.dir.join(format!("{run_id}.json"))
+    }
+}
+
+impl CheckpointStore for FileCheckpoints {
+    fn load(&self, run_id: &TaskId) -> Result<Option<Checkpoint>, Error> {
+        // IDs are chosen by users when resuming, and never name other files
+        if !run_id
+            .0
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        {
+            return Ok(None);
+        }
+        match fs::read_to_string(self.path(run_id)) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    fn save(&self, checkpoint: &Checkpoint) -> Result<(), Error> {
+        let _lock = self.lock.lock().unwrap();
+        // Written aside first, so that the file is never left half written
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(&checkpoint.run_id);
+        let written = path.with_extension("json.tmp");
+        fs::write(&written, serde_json::to_vec_pretty(checkpoint)?)?;
+        fs::rename(written, path)?;
+        Ok(())
+    }
+}
+
+/// Saves the checkpoints of a task as its nodes succeed.
+pub(crate) struct Checkpointer {
+    checkpoint: Mutex<Checkpoint>,
+    redactor: Arc<Redactor>,
+    store: Arc<dyn CheckpointStore>,
+}
+
+impl Checkpointer {
+    pub(crate) fn new(
+        checkpoint: Checkpoint,
+        redactor: Arc<Redactor>,
+        store: Arc<dyn CheckpointStore>,
+    ) -> Self {
+        Self {
+            checkpoint: Mutex::new(checkpoint),
+            redactor,
+            store,
+        }
+    }
+
+    /// Saves the output of a node that succeeded, unless it holds a secret.
+    pub(crate) fn succeeded(&self, node_id: &NodeId, val: &Val) {
+        if self.redactor.redact_val(val) != *val {
+            return;
+        }
+        let mut checkpoint = self.checkpoint.lock().unwrap();
+        checkpoint
+            .outputs
+            .insert(node_id.0.clone(), TracedVal::from(val));
+        // A task keeps running without checkpoints rather than failing
+        if let Err(e) = self.store.save(&checkpoint) {
+            tracing::warn!(run_id = %checkpoint.run_id, error = %e, "Cannot save the checkpoint");
+        }
+    }
+}
+
+/// Returns the SHA-256 digest of the bytes, in hexadecimal.
+pub(crate) fn sha256(bytes: &[u8]) -> String {
+    digest(&SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    File(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_checkpoints_keep_the_last_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("checkpoints-{}", std::process::id()));
+        let store = FileCheckpoints::new(&dir);
+        let run_id = TaskId("run-1".to_string());
+        let mut checkpoint = Checkpoint {
+            digests: Digests {
+                components: BTreeMap::from([("math".to_string(), sha256(b"math"))]),
+                workflow: sha256(b"workflow"),
+            },
+            inputs: BTreeMap::new(),
+            manifest: None,
+            outputs: BTreeMap::new(),
+            run_id: run_id.clone(),
+        };
+        store.save(&checkpoint).unwrap();
+        checkpoint
+            .outputs
+            .insert("node".to_string(), TracedVal::U32(1));
+        store.save(&checkpoint).unwrap();
+
+        let loaded = FileCheckpoints::new(&dir).load(&run_id).unwrap().unwrap();
+        assert_eq!(loaded.digests, checkpoint.digests);
+        assert_eq!(loaded.outputs, checkpoint.outputs);
+        assert!(store.load(&TaskId("run-2".to_string())).unwrap().is_none());
+        assert!(
+            store
+                .load(&TaskId("../run-1".to_string()))
+                .unwrap()
+                .is_none()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+//! Deterministic mode, making tasks reproducible: running the same workflow
+//! twice with the same inputs emits the same events.
+//!
+//! In deterministic mode, a component granted clocks sees virtual ones,
+//! starting at `VIRTUAL_EPOCH` and advancing by `CLOCK_STEP` at every reading,
+//! and a component granted random sources sees sources seeded from the
+//! runtime's seed, its node and the element of its list. Its outgoing HTTP
+//! requests never reach the network: they are answered with `HttpFixture`s.
+//!
+//! The task itself runs one node, and one element of a list, at a time, in the
+//! order of their IDs. Its clock stands still while nodes run, only advancing
+//! by the backoff and repeat delays it skips instead of waiting for them, the
+//! jitter of backoff delays being seeded as well.
+//! Timeouts still count real time.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use http_body_util::{BodyExt, Full};
+use hyper::{Request, Response, StatusCode, body::Bytes};
+use rand::{SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+use wasmtime_wasi_http::{
+    bindings::http::types::ErrorCode,
+    body::{HyperIncomingBody, HyperOutgoingBody},
+    types::{HostFutureIncomingResponse, IncomingResponse},
+};
+use workflow::{Capabilities, NodeId};
+
+/// Wall time of the virtual clocks when a call starts: 2024-01-01T00:00:00Z.
+pub const VIRTUAL_EPOCH: Duration = Duration::from_secs(1_704_067_200);
+
+/// Time a virtual clock advances by every time it is read.
+pub const CLOCK_STEP: Duration = Duration::from_millis(1);
+
+/// Settings of the deterministic mode of a `Runtime`.
+#[derive(Clone, Debug, Default)]
+pub struct Deterministic {
+    /// Responses to the outgoing HTTP requests of components, a request
+    /// without fixture failing
+    pub http_fixtures: Vec<HttpFixture>,
+    /// Seed of the random sources of components
+    pub seed: u64,
+}
+
+/// A recorded response to an outgoing HTTP request.
+///
+/// The fixtures of the same request are served in order, the last one being
+/// served again once every one was.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HttpFixture {
+    /// Whether `body` is encoded in base64, for bodies that are not UTF-8
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub base64: bool,
+    /// Body of the response, as text unless `base64` is set
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub method: String,
+    pub status: u16,
+    /// URI of the request, scheme and authority included
+    pub uri: String,
+}
+
+impl HttpFixture {
+    /// Reads a JSON file holding a list of fixtures.
+    pub fn load(path: &Path) -> Result<Vec<Self>, Error> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    fn matches(&self, method: &str, uri: &str) -> bool {
+        self.method.eq_ignore_ascii_case(method) && self.uri == uri
+    }
+
+    fn response(&self) -> Result<Response<HyperIncomingBody>, ErrorCode> {
+        let invalid = |e: String| ErrorCode::InternalError(Some(format!("Invalid fixture: {e}")));
+        let mut response = Response::builder()
+            .status(StatusCode::from_u16(self.status).map_err(|e| invalid(e.to_string()))?);
+        for (name, value) in &self.headers {
+            response = response.header(name, value);
+        }
+        let body = match self.base64 {
+            true => STANDARD
+                .decode(&self.body)
+                .map_err(|e| invalid(e.to_string()))?,
+            false => self.body.clone().into_bytes(),
+        };
+        let body = Full::new(Bytes::from(body))
+            .map_err(|never| match never {})
+            .boxed();
+        response.body(body).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+/// The deterministic mode of a task, shared by the tasks of the workflows its
+/// nodes use.
+#[derive(Debug)]
+pub(crate) struct Determinism {
+    fixtures: Mutex<Fixtures>,
+    seed: u64,
+}
+
+impl Determinism {
+    pub(crate) fn new(deterministic: Deterministic) -> Self {
+        Self {
+            fixtures: Mutex::new(Fixtures::new(deterministic.http_fixtures)),
+            seed: deterministic.seed,
+        }
+    }
+
+    /// Gives the component virtual clocks and seeded random sources, unless it
+    /// is denied them.
+    pub(crate) fn sandbox(
+        &self,
+        builder: &mut WasiCtxBuilder,
+        capabilities: &Capabilities,
+        node_id: &NodeId,
+        index: Option<u32>,
+    ) {
+        if capabilities.clocks {
+            builder
+                .wall_clock(VirtualClock::new(VIRTUAL_EPOCH))
+                .monotonic_clock(VirtualClock::new(Duration::ZERO));
+        }
+        if capabilities.random {
+            builder
+                .secure_random(self.rng(node_id, index))
+                .insecure_random(self.rng(node_id, index))
+                .insecure_random_seed(self.seed(node_id, index).into());
+        }
+    }
+
+    /// Random source of a call of a node.
+    pub(crate) fn rng(&self, node_id: &NodeId, index: Option<u32>) -> StdRng {
+        StdRng::seed_from_u64(self.seed(node_id, index))
+    }
+
+    /// Source of the jitter of the backoff delay after an attempt of a node.
+    pub(crate) fn jitter_rng(&self, node_id: &NodeId, index: Option<u32>, attempt: u32) -> StdRng {
+        StdRng::seed_from_u64(self.seed(node_id, index).wrapping_add(attempt.into()))
+    }
+
+    /// Seed of a call of a node, distinct for every node and element of its
+    /// list, hashed with FNV-1a to stay the same across builds.
+    pub(crate) fn seed(&self, node_id: &NodeId, index: Option<u32>) -> u64 {
+        let index = index.map_or(u64::MAX, u64::from);
+        [
+            &self.seed.to_le_bytes()[..],
+            node_id.0.as_bytes(),
+            &index.to_le_bytes(),
+        ]
+        .concat()
+        .into_iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// Answers the request with its fixture, `shown_uri` being the URI with
+    /// its secrets redacted.
+    pub(crate) fn send_request(
+        &self,
+        request: Request<HyperOutgoingBody>,
+        shown_uri: String,
+        between_bytes_timeout: Duration,
+    ) -> HostFutureIncomingResponse {
+        let method = request.method().to_string();
+        let fixture = self
+            .fixtures
+            .lock()
+            .unwrap()
+            .take(&method, &request.uri().to_string())
+            .cloned();
+        answer(request, fixture, shown_uri, between_bytes_timeout)
+    }
+}
+
+/// Fixtures answering requests, served in order.
+#[derive(Debug)]
+pub(crate) struct Fixtures {
+    fixtures: Vec<HttpFixture>,
+    /// Whether each fixture was served
+    served: Vec<bool>,
+}
+
+impl Fixtures {
+    pub(crate) fn new(fixtures: Vec<HttpFixture>) -> Self {
+        let served = vec![false; fixtures.len()];
+        Self { fixtures, served }
+    }
+
+    /// Takes the fixture answering the request, if any.
+    pub(crate) fn take(&mut self, method: &str, uri: &str) -> Option<&HttpFixture> {
+        let matching: Vec<_> = (0..self.fixtures.len())
+            .filter(|&i| self.fixtures[i].matches(method, uri))
+            .collect();
+        let position = matching
+            .iter()
+            .copied()
+            .find(|&i| !self.served[i])
+            .or(matching.last().copied())?;
+        self.served[position] = true;
+        Some(&self.fixtures[position])
+    }
+}
+
+/// Answers a request with a fixture once the component sent its whole body,
+/// or fails when there is none.
+pub(crate) fn answer(
+    request: Request<HyperOutgoingBody>,
+    fixture: Option<HttpFixture>,
+    shown_uri: String,
+    between_bytes_timeout: Duration,
+) -> HostFutureIncomingResponse {
+    let method = request.method().to_string();
+    let response = match fixture {
+        Some(fixture) => fixture.response(),
+        None => {
+            tracing::warn!(%method, uri = %shown_uri, "No HTTP fixture for the request");
+            Err(ErrorCode::InternalError(Some(format!(
+                "No HTTP fixture for {method} {shown_uri}"
+            ))))
+        }
+    };
+    let handle = wasmtime_wasi::runtime::spawn(async move {
+        // Read whole, so that the component is never blocked writing it
+        if let Err(e) = request.into_body().collect().await {
+            return Ok(Err(e));
+        }
+        Ok(response.map(|resp| IncomingResponse {
+            resp,
+            worker: None,
+            between_bytes_timeout,
+        }))
+    });
+    HostFutureIncomingResponse::pending(handle)
+}
+
+/// Clock of a component in deterministic mode.
+pub(crate) struct VirtualClock {
+    readings: AtomicU64,
+    start: Duration,
+}
+
+impl VirtualClock {
+    pub(crate) fn new(start: Duration) -> Self {
+        Self {
+            readings: AtomicU64::new(0),
+            start,
+        }
+    }
+
+    fn read(&self) -> Duration {
+        let readings = self.readings.fetch_add(1, Ordering::SeqCst);
+        self.start + CLOCK_STEP * readings as u32
+    }
+}
+
+impl HostWallClock for VirtualClock {
+    fn resolution(&self) -> Duration {
+        CLOCK_STEP
+    }
+
+    fn now(&self) -> Duration {
+        self.read()
+    }
+}
+
+impl HostMonotonicClock for VirtualClock {
+    fn resolution(&self) -> u64 {
+        CLOCK_STEP.as_nanos() as u64
+    }
+
+    fn now(&self) -> u64 {
+        self.read().as_nanos() as u64
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    File(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fixtures_are_served_in_order() {
+        let fixture = |uri: &str, body: &str| HttpFixture {
+            base64: false,
+            body: body.to_string(),
+            headers: BTreeMap::from([("x-fixture".to_string(), body.to_string())]),
+            method: "GET".to_string(),
+            status: 200,
+            uri: uri.to_string(),
+        };
+        let determinism = Determinism::new(Deterministic {
+            http_fixtures: vec![
+                fixture("https://a.test/", "first"),
+                fixture("https://b.test/", "other"),
+                fixture("https://a.test/", "second"),
+            ],
+            seed: 7,
+        });
+        let send = async |method: &str, uri: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(
+                    http_body_util::Empty::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap();
+            let HostFutureIncomingResponse::Pending(handle) =
+                determinism.send_request(request, uri.to_string(), Duration::from_secs(1))
+            else {
+                unreachable!("responses are pending until the body is read");
+            };
+            let response = handle.await.unwrap()?.resp;
+            let header = response.headers()["x-fixture"].clone();
+            let body = response.into_body().collect().await?.to_bytes();
+            assert_eq!(header.as_bytes(), body);
+            Ok::<_, ErrorCode>(String::from_utf8(body.to_vec()).unwrap())
+        };
+        assert_eq!(send("GET", "https://a.test/").await.unwrap(), "first");
+        assert_eq!(send("GET", "https://a.test/").await.unwrap(), "second");
+        assert_eq!(send("GET", "https://a.test/").await.unwrap(), "second");
+        assert!(matches!(
+            send("POST", "https://b.test/").await,
+            Err(ErrorCode::InternalError(_))
+        ));
+
+        let node_id = NodeId("node".to_string());
+        assert_ne!(
+            determinism.seed(&node_id, Some(0)),
+            determinism.seed(&node_id, Some(1))
+        );
+    }
+}
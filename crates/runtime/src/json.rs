@@ -0,0 +1,471 @@
+//! Conversion between component values and JSON, and rendering of task
+//! events as JSON.
+//!
+//! Values are mapped by shape:
+//! - records become objects and lists and tuples become arrays;
+//! - enums become strings and flags arrays of strings;
+//! - chars become one-character strings;
+//! - options become `null` or their value;
+//! - results and variants are tagged by their case, as `{"ok": ..}`,
+//!   `{"err": ..}` or `{"<case>": ..}`, and cases without payload become a
+//!   plain string.
+//!
+//! The mapping is reversible given the type of the value, except for nested
+//! options whose `some(none)` becomes `null` like `none`.
+
+use std::{collections::HashMap, hash::Hash, time::Duration};
+
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Number, Value};
+use wasmtime::component::{Type, Val};
+
+/// Converts JSON into a value of the given type.
+///
+/// Record fields of an option type may be omitted, and cases without payload
+/// may also be written as `{"<case>": null}`.
+pub fn json_to_val(value: &Value, ty: &Type) -> Result<Val, Error> {
+    to_val(value, ty, "$")
+}
+
+fn to_val(value: &Value, ty: &Type, path: &str) -> Result<Val, Error> {
+    let mismatch = |expected: &str| Error::Mismatch {
+        expected: expected.to_string(),
+        found: value.clone(),
+        path: path.to_string(),
+    };
+    let int = |expected: &str| value.as_i64().ok_or_else(|| mismatch(expected));
+
+    Ok(match ty {
+        Type::Bool => Val::Bool(value.as_bool().ok_or_else(|| mismatch("a boolean"))?),
+        Type::S8 => Val::S8(int("an s8")?.try_into().map_err(|_| mismatch("an s8"))?),
+        Type::U8 => Val::U8(int("a u8")?.try_into().map_err(|_| mismatch("a u8"))?),
+        Type::S16 => Val::S16(int("an s16")?.try_into().map_err(|_| mismatch("an s16"))?),
+        Type::U16 => Val::U16(int("a u16")?.try_into().map_err(|_| mismatch("a u16"))?),
+        Type::S32 => Val::S32(int("an s32")?.try_into().map_err(|_| mismatch("an s32"))?),
+        Type::U32 => Val::U32(int("a u32")?.try_into().map_err(|_| mismatch("a u32"))?),
+        Type::S64 => Val::S64(int("an s64")?),
+        Type::U64 => Val::U64(value.as_u64().ok_or_else(|| mismatch("a u64"))?),
+        Type::Float32 => Val::Float32(value.as_f64().ok_or_else(|| mismatch("a float32"))? as f32),
+        Type::Float64 => Val::Float64(value.as_f64().ok_or_else(|| mismatch("a float64"))?),
+        Type::Char => {
+            let s = value.as_str().ok_or_else(|| mismatch("a char"))?;
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Val::Char(c),
+                _ => Err(mismatch("a char"))?,
+            }
+        }
+        Type::String => Val::String(
+            value
+                .as_str()
+                .ok_or_else(|| mismatch("a string"))?
+                .to_string(),
+        ),
+        Type::List(list) => {
+            let values = value.as_array().ok_or_else(|| mismatch("an array"))?;
+            let ty = list.ty();
+            Val::List(
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| to_val(value, &ty, &format!("{path}[{i}]")))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        Type::Record(record) => {
+            let object = value.as_object().ok_or_else(|| mismatch("an object"))?;
+            if let Some(name) = object
+                .keys()
+                .find(|name| !record.fields().any(|field| field.name == name.as_str()))
+            {
+                Err(Error::Unknown {
+                    kind: "field",
+                    name: name.clone(),
+                    path: path.to_string(),
+                })?;
+            }
+            let fields = record
+                .fields()
+                .map(|field| {
+                    let val = match (object.get(field.name), &field.ty) {
+                        (Some(value), ty) => to_val(value, ty, &format!("{path}.{}", field.name))?,
+                        (None, Type::Option(_)) => Val::Option(None),
+                        (None, _) => Err(Error::MissingField {
+                            name: field.name.to_string(),
+                            path: path.to_string(),
+                        })?,
+                    };
+                    Ok((field.name.to_string(), val))
+                })
+                .collect::<Result<_, Error>>()?;
+            Val::Record(fields)
+        }
+        Type::Tuple(tuple) => {
+            let values = value
+                .as_array()
+                .filter(|values| values.len() == tuple.types().len())
+                .ok_or_else(|| mismatch(&format!("an array of {}", tuple.types().len())))?;
+            Val::Tuple(
+                values
+                    .iter()
+                    .zip(tuple.types())
+                    .enumerate()
+                    .map(|(i, (value, ty))| to_val(value, &ty, &format!("{path}[{i}]")))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        Type::Variant(variant) => {
+            let (name, payload) = case(value, &mismatch)?;
+            let case = variant
+                .cases()
+                .find(|case| case.name == name)
+                .ok_or_else(|| Error::Unknown {
+                    kind: "case",
+                    name: name.to_string(),
+                    path: path.to_string(),
+                })?;
+            let payload = self::payload(payload, case.ty.as_ref(), &format!("{path}.{name}"))?;
+            Val::Variant(name.to_string(), payload)
+        }
+        Type::Enum(enum_) => {
+            let name = value.as_str().ok_or_else(|| mismatch("a string"))?;
+            if !enum_.names().any(|case| case == name) {
+                Err(Error::Unknown {
+                    kind: "case",
+                    name: name.to_string(),
+                    path: path.to_string(),
+                })?;
+            }
+            Val::Enum(name.to_string())
+        }
+        Type::Option(option) => match value {
+            Value::Null => Val::Option(None),
+            value => Val::Option(Some(Box::new(to_val(value, &option.ty(), path)?))),
+        },
+        Type::Result(result) => {
+            let (name, payload) = case(value, &mismatch)?;
+            let path = format!("{path}.{name}");
+            match name {
+                "ok" => Val::Result(Ok(self::payload(payload, result.ok().as_ref(), &path)?)),
+                "err" => Val::Result(Err(self::payload(payload, result.err().as_ref(), &path)?)),
+                name => Err(Error::Unknown {
+                    kind: "case",
+                    name: name.to_string(),
+                    path: path.to_string(),
+                })?,
+            }
+        }
+        Type::Flags(flags) => {
+            let values = value
+                .as_array()
+                .ok_or_else(|| mismatch("an array of strings"))?;
+            let names = values
+                .iter()
+                .map(|value| {
+                    let name = value
+                        .as_str()
+                        .ok_or_else(|| mismatch("an array of strings"))?;
+                    match flags.names().any(|flag| flag == name) {
+                        true => Ok(name.to_string()),
+                        false => Err(Error::Unknown {
+                            kind: "flag",
+                            name: name.to_string(),
+                            path: path.to_string(),
+                        }),
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+            Val::Flags(names)
+        }
+        Type::Own(_) | Type::Borrow(_) => Err(Error::Unsupported {
+            path: path.to_string(),
+        })?,
+    })
+}
+
+/// Splits a case written as `"<case>"` or `{"<case>": <payload>}`.
+fn case<'a>(
+    value: &'a Value,
+    mismatch: &dyn Fn(&str) -> Error,
+) -> Result<(&'a str, Option<&'a Value>), Error> {
+    match value {
+        Value::String(name) => Ok((name, None)),
+        Value::Object(object) if object.len() == 1 => {
+            let (name, payload) = object.iter().next().unwrap();
+            Ok((name, Some(payload).filter(|payload| !payload.is_null())))
+        }
+        _ => Err(mismatch("a case name or an object with a single case")),
+    }
+}
+
+fn payload(
+    value: Option<&Value>,
+    ty: Option<&Type>,
+    path: &str,
+) -> Result<Option<Box<Val>>, Error> {
+    match (value, ty) {
+        (value, Some(ty)) => Ok(Some(Box::new(to_val(
+            value.unwrap_or(&Value::Null),
+            ty,
+            path,
+        )?))),
+        (None, None) => Ok(None),
+        (Some(value), None) => Err(Error::Mismatch {
+            expected: "no payload".to_string(),
+            found: value.clone(),
+            path: path.to_string(),
+        }),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid JSON at {path}: expected {expected}, found {found}")]
+    Mismatch {
+        expected: String,
+        found: Value,
+        path: String,
+    },
+    #[error("Missing field {name:?} at {path}")]
+    MissingField { name: String, path: String },
+    #[error("Unknown {kind} {name:?} at {path}")]
+    Unknown {
+        kind: &'static str,
+        name: String,
+        path: String,
+    },
+    #[error("Resources cannot be converted from JSON, at {path}")]
+    Unsupported { path: String },
+}
+
+/// Renders a value as JSON, without needing its type.
+pub fn val_to_json(val: &Val) -> Value {
+    match val {
+        Val::Bool(b) => Value::Bool(*b),
+        Val::S8(n) => Value::from(*n),
+        Val::U8(n) => Value::from(*n),
+        Val::S16(n) => Value::from(*n),
+        Val::U16(n) => Value::from(*n),
+        Val::S32(n) => Value::from(*n),
+        Val::U32(n) => Value::from(*n),
+        Val::S64(n) => Value::from(*n),
+        Val::U64(n) => Value::from(*n),
+        // NaN and infinities have no JSON representation
+        Val::Float32(n) => Number::from_f64(f64::from(*n)).map_or(Value::Null, Value::Number),
+        Val::Float64(n) => Number::from_f64(*n).map_or(Value::Null, Value::Number),
+        Val::Char(c) => Value::String(c.to_string()),
+        Val::String(s) => Value::String(s.clone()),
+        Val::List(vals) | Val::Tuple(vals) => Value::Array(vals.iter().map(val_to_json).collect()),
+        Val::Record(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, val)| (name.clone(), val_to_json(val)))
+                .collect(),
+        ),
+        Val::Variant(case, None) | Val::Enum(case) => Value::String(case.clone()),
+        Val::Variant(case, Some(payload)) => tagged(case, Some(payload)),
+        Val::Option(val) => val.as_deref().map_or(Value::Null, val_to_json),
+        Val::Result(Ok(payload)) => tagged("ok", payload.as_deref()),
+        Val::Result(Err(payload)) => tagged("err", payload.as_deref()),
+        Val::Flags(flags) => Value::Array(flags.iter().cloned().map(Value::String).collect()),
+        Val::Resource(_) => Value::String("<resource>".to_string()),
+    }
+}
+
+fn tagged(case: &str, payload: Option<&Val>) -> Value {
+    let payload = payload.map_or(Value::Null, val_to_json);
+    Value::Object(Map::from_iter([(case.to_string(), payload)]))
+}
+
+/// A value rendered both as WAVE, for humans, and as JSON, for scripts.
+#[derive(Serialize)]
+struct Rendered {
+    json: Value,
+    wave: String,
+}
+
+impl From<&Val> for Rendered {
+    fn from(val: &Val) -> Self {
+        Self {
+            json: val_to_json(val),
+            wave: val.to_wave().unwrap_or_else(|_| format!("{val:?}")),
+        }
+    }
+}
+
+pub(crate) fn serialize_option_val<S: Serializer>(
+    val: &Option<Val>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    val.as_ref().map(Rendered::from).serialize(serializer)
+}
+
+pub(crate) fn serialize_val<S: Serializer>(val: &Val, serializer: S) -> Result<S::Ok, S::Error> {
+    Rendered::from(val).serialize(serializer)
+}
+
+pub(crate) fn serialize_val_map<K: Eq + Hash + Serialize, S: Serializer>(
+    vals: &HashMap<K, Val>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(vals.iter().map(|(key, val)| (key, Rendered::from(val))))
+}
+
+pub(crate) fn serialize_vals<S: Serializer>(
+    vals: &[Val],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(vals.iter().map(Rendered::from))
+}
+
+/// Serializes a duration as a number of milliseconds, with microsecond
+/// precision.
+pub(crate) fn serialize_millis<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_micros() as f64 / 1_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_val_to_json() {
+        let val = Val::Record(vec![
+            ("id".to_string(), Val::U64(7)),
+            (
+                "tags".to_string(),
+                Val::List(vec![Val::String("a".to_string())]),
+            ),
+            ("parent".to_string(), Val::Option(None)),
+            ("color".to_string(), Val::Enum("red".to_string())),
+            (
+                "shape".to_string(),
+                Val::Variant("circle".to_string(), Some(Box::new(Val::Float64(1.5)))),
+            ),
+            ("status".to_string(), Val::Result(Err(None))),
+        ]);
+        assert_eq!(
+            val_to_json(&val),
+            json!({
+                "id": 7,
+                "tags": ["a"],
+                "parent": null,
+                "color": "red",
+                "shape": { "circle": 1.5 },
+                "status": { "err": null },
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_to_val_round_trips() {
+        // Types are only known from components, here the elements of `items`
+        let wat = r#"(component
+          (type $shape' (variant (case "circle" float64) (case "dot")))
+          (export $shape "shape" (type $shape'))
+          (type $item' (record
+            (field "id" u64)
+            (field "tags" (list string))
+            (field "parent" (option u32))
+            (field "shape" $shape)
+            (field "status" (result u32 (error string)))))
+          (export $item "item" (type $item'))
+          (core module $m
+            (memory (export "memory") 1)
+            (func (export "realloc") (param i32 i32 i32 i32) (result i32) i32.const 0)
+            (func (export "f") (param i32 i32)))
+          (core instance $i (instantiate $m))
+          (func (export "f") (param "items" (list $item))
+            (canon lift (core func $i "f") (memory $i "memory") (realloc (func $i "realloc"))))
+        )"#;
+        let engine = wasmtime::Engine::default();
+        let component =
+            wasmtime::component::Component::new(&engine, wat::parse_str(wat).unwrap()).unwrap();
+        let wasmtime::component::types::ComponentItem::ComponentFunc(func) =
+            component.component_type().get_export(&engine, "f").unwrap()
+        else {
+            unreachable!("f is a function");
+        };
+        let ty = func.params().next().unwrap().1;
+
+        let value = json!([
+            {
+                "id": 7,
+                "tags": ["a"],
+                "parent": 1,
+                "shape": { "circle": 1.5 },
+                "status": { "ok": 2 },
+            },
+            {
+                "id": 8,
+                "tags": [],
+                "parent": null,
+                "shape": "dot",
+                "status": { "err": "no" },
+            },
+        ]);
+        let val = json_to_val(&value, &ty).unwrap();
+        assert_eq!(val_to_json(&val), value);
+
+        // Omitted options and cases written as objects without payload
+        let Val::List(items) = json_to_val(
+            &json!([{ "id": 9, "tags": [], "shape": { "dot": null }, "status": { "ok": 3 } }]),
+            &ty,
+        )
+        .unwrap() else {
+            unreachable!("items are a list");
+        };
+        let Val::Record(fields) = &items[0] else {
+            unreachable!("items are records");
+        };
+        assert_eq!(fields[2], ("parent".to_string(), Val::Option(None)));
+        assert_eq!(
+            fields[3],
+            ("shape".to_string(), Val::Variant("dot".to_string(), None))
+        );
+
+        let error = |value: Value| json_to_val(&value, &ty).unwrap_err().to_string();
+        let item = |field: &str, value: Value| {
+            let mut item = json!({ "id": 1, "tags": [], "shape": "dot", "status": { "ok": 1 } });
+            item[field] = value;
+            json!([item])
+        };
+        assert_eq!(
+            error(item("id", json!("7"))),
+            r#"Invalid JSON at $[0].id: expected a u64, found "7""#
+        );
+        assert_eq!(
+            error(item("parent", json!(-1))),
+            "Invalid JSON at $[0].parent: expected a u32, found -1"
+        );
+        assert_eq!(
+            error(item("shape", json!("square"))),
+            r#"Unknown case "square" at $[0].shape"#
+        );
+        assert_eq!(
+            error(item("shape", json!({ "dot": 1 }))),
+            "Invalid JSON at $[0].shape.dot: expected no payload, found 1"
+        );
+        assert_eq!(
+            error(item("status", json!({ "ok": 1, "err": "no" }))),
+            r#"Invalid JSON at $[0].status: expected a case name or an object with a single case, found {"err":"no","ok":1}"#
+        );
+        assert_eq!(
+            error(item("color", json!("red"))),
+            r#"Unknown field "color" at $[0]"#
+        );
+        assert_eq!(
+            error(json!([{ "id": 1 }])),
+            r#"Missing field "tags" at $[0]"#
+        );
+        assert_eq!(
+            error(json!({})),
+            r#"Invalid JSON at $: expected an array, found {}"#
+        );
+    }
+}
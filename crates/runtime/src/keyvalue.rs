@@ -0,0 +1,410 @@
+//! The `wasi:keyvalue` store and atomics interfaces, letting components keep
+//! state beyond their return values.
+//!
+//! Buckets are scoped by workflow: the same bucket opened by components of
+//! different workflows holds different keys. Where the buckets live depends on
+//! the `Runtime`: in memory for every task by default, or in a
+//! `KeyValueStore` shared by every task, such as a `FileStore` keeping them
+//! across runs.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use wasmtime::component::{Linker, Resource};
+use wasmtime_wasi::IoView;
+
+use crate::state::State;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit/keyvalue",
+        world: "imports",
+        trappable_imports: true,
+        with: {
+            "wasi:keyvalue/store/bucket": super::Bucket,
+        },
+    });
+}
+
+use bindings::wasi::keyvalue::{
+    atomics,
+    store::{self, KeyResponse},
+};
+
+/// Values of the keys of a bucket.
+type Entries = BTreeMap<String, Vec<u8>>;
+
+/// A bucket opened by a component.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Bucket {
+    /// The identifier the component opened the bucket with
+    pub name: String,
+    /// The workflow of the component
+    pub scope: String,
+}
+
+/// Where the buckets live.
+///
+/// Counters updated by `increment` are stored as decimal text.
+pub trait KeyValueStore: Send + Sync {
+    fn get(&self, bucket: &Bucket, key: &str) -> Result<Option<Vec<u8>>, Error>;
+    fn set(&self, bucket: &Bucket, key: &str, value: Vec<u8>) -> Result<(), Error>;
+    fn delete(&self, bucket: &Bucket, key: &str) -> Result<(), Error>;
+    /// Returns the keys of the bucket in order.
+    fn keys(&self, bucket: &Bucket) -> Result<Vec<String>, Error>;
+    /// Adds `delta` to the counter of the key atomically, returning its new
+    /// value.
+    fn increment(&self, bucket: &Bucket, key: &str, delta: u64) -> Result<u64, Error>;
+}
+
+/// Buckets kept in memory, lost once dropped.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<Bucket, Entries>>,
+}
+
+impl MemoryStore {
+    fn modify<R>(&self, bucket: &Bucket, f: impl FnOnce(&mut Entries) -> R) -> R {
+        let mut buckets = self.buckets.lock().unwrap();
+        f(buckets.entry(bucket.clone()).or_default())
+    }
+}
+
+impl KeyValueStore for MemoryStore {
+    fn get(&self, bucket: &Bucket, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.modify(bucket, |entries| entries.get(key).cloned()))
+    }
+
+    fn set(&self, bucket: &Bucket, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.modify(bucket, |entries| entries.insert(key.to_string(), value));
+        Ok(())
+    }
+
+    fn delete(&self, bucket: &Bucket, key: &str) -> Result<(), Error> {
+        self.modify(bucket, |entries| entries.remove(key));
+        Ok(())
+    }
+
+    fn keys(&self, bucket: &Bucket) -> Result<Vec<String>, Error> {
+        Ok(self.modify(bucket, |entries| entries.keys().cloned().collect()))
+    }
+
+    fn increment(&self, bucket: &Bucket, key: &str, delta: u64) -> Result<u64, Error> {
+        self.modify(bucket, |entries| increment(entries, key, delta))
+    }
+}
+
+/// Buckets kept in a directory across runs, as a JSON file for every bucket
+/// in a directory for every workflow.
+///
+/// Every operation reads the bucket's file, and rewrites it when the bucket
+/// changes. Operations are atomic within a process only.
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn path(&self, bucket: &Bucket) -> PathBuf {
+        self.dir
+            .join(file_name(&bucket.scope))
+            .join(format!("{}.json", file_name(&bucket.name)))
+    }
+
+    fn modify<R>(
+        &self,
+        bucket: &Bucket,
+        f: impl FnOnce(&mut Entries) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let _lock = self.lock.lock().unwrap();
+        let path = self.path(bucket);
+        let mut entries = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str::<BTreeMap<String, String>>(&json)?
+                .into_iter()
+                .map(|(key, value)| Ok((key, STANDARD.decode(value)?)))
+                .collect::<Result<_, Error>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Entries::new(),
+            Err(e) => Err(e)?,
+        };
+        let before = entries.clone();
+        let result = f(&mut entries)?;
+        if entries != before {
+            let encoded: BTreeMap<_, _> = entries
+                .iter()
+                .map(|(key, value)| (key, STANDARD.encode(value)))
+                .collect();
+            // Written aside first, so that the file is never left half written
+            fs::create_dir_all(path.parent().unwrap())?;
+            let written = path.with_extension("json.tmp");
+            fs::write(&written, serde_json::to_vec_pretty(&encoded)?)?;
+            fs::rename(written, path)?;
+        }
+        Ok(result)
+    }
+}
+
+impl KeyValueStore for FileStore {
+    fn get(&self, bucket: &Bucket, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.modify(bucket, |entries| Ok(entries.get(key).cloned()))
+    }
+
+    fn set(&self, bucket: &Bucket, key: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.modify(bucket, |entries| {
+            entries.insert(key.to_string(), value);
+            Ok(())
+        })
+    }
+
+    fn delete(&self, bucket: &Bucket, key: &str) -> Result<(), Error> {
+        self.modify(bucket, |entries| {
+            entries.remove(key);
+            Ok(())
+        })
+    }
+
+    fn keys(&self, bucket: &Bucket) -> Result<Vec<String>, Error> {
+        self.modify(bucket, |entries| Ok(entries.keys().cloned().collect()))
+    }
+
+    fn increment(&self, bucket: &Bucket, key: &str, delta: u64) -> Result<u64, Error> {
+        self.modify(bucket, |entries| increment(entries, key, delta))
+    }
+}
+
+fn increment(entries: &mut Entries, key: &str, delta: u64) -> Result<u64, Error> {
+    let value = match entries.get(key) {
+        Some(value) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|text| text.parse::<u64>().ok())
+            .ok_or_else(|| Error::NotACounter(key.to_string()))?,
+        None => 0,
+    };
+    let value = value
+        .checked_add(delta)
+        .ok_or_else(|| Error::Overflow(key.to_string()))?;
+    entries.insert(key.to_string(), value.to_string().into_bytes());
+    Ok(value)
+}
+
+/// Names a file after a bucket or a workflow, percent-encoding every byte but
+/// ASCII letters, digits, `-` and `_`.
+fn file_name(name: &str) -> String {
+    let mut file_name = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => file_name.push(byte as char),
+            byte => file_name.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    file_name
+}
+
+/// The buckets a component opens, in its workflow's scope.
+#[derive(Clone)]
+pub(crate) struct KeyValue {
+    pub(crate) scope: String,
+    pub(crate) store: Arc<dyn KeyValueStore>,
+}
+
+pub(crate) fn add_to_linker(linker: &mut Linker<State>) -> wasmtime::Result<()> {
+    bindings::Imports::add_to_linker(linker, |state| state)
+}
+
+impl From<Error> for store::Error {
+    fn from(error: Error) -> Self {
+        store::Error::Other(error.to_string())
+    }
+}
+
+impl store::Host for State {
+    fn open(
+        &mut self,
+        identifier: String,
+    ) -> wasmtime::Result<Result<Resource<Bucket>, store::Error>> {
+        let bucket = Bucket {
+            name: identifier,
+            scope: self.keyvalue.scope.clone(),
+        };
+        Ok(Ok(self.table().push(bucket)?))
+    }
+}
+
+impl store::HostBucket for State {
+    fn get(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<Option<Vec<u8>>, store::Error>> {
+        let bucket = self.table.get(&bucket)?;
+        Ok(self.keyvalue.store.get(bucket, &key).map_err(Into::into))
+    }
+
+    fn set(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+        value: Vec<u8>,
+    ) -> wasmtime::Result<Result<(), store::Error>> {
+        let bucket = self.table.get(&bucket)?;
+        Ok(self
+            .keyvalue
+            .store
+            .set(bucket, &key, value)
+            .map_err(Into::into))
+    }
+
+    fn delete(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<(), store::Error>> {
+        let bucket = self.table.get(&bucket)?;
+        Ok(self.keyvalue.store.delete(bucket, &key).map_err(Into::into))
+    }
+
+    fn exists(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<bool, store::Error>> {
+        let bucket = self.table.get(&bucket)?;
+        Ok(self
+            .keyvalue
+            .store
+            .get(bucket, &key)
+            .map(|value| value.is_some())
+            .map_err(Into::into))
+    }
+
+    /// Returns every key at once, without cursor.
+    fn list_keys(
+        &mut self,
+        bucket: Resource<Bucket>,
+        _cursor: Option<u64>,
+    ) -> wasmtime::Result<Result<KeyResponse, store::Error>> {
+        let bucket = self.table.get(&bucket)?;
+        Ok(self
+            .keyvalue
+            .store
+            .keys(bucket)
+            .map(|keys| KeyResponse { keys, cursor: None })
+            .map_err(Into::into))
+    }
+
+    fn drop(&mut self, bucket: Resource<Bucket>) -> wasmtime::Result<()> {
+        self.table().delete(bucket)?;
+        Ok(())
+    }
+}
+
+impl atomics::Host for State {
+    fn increment(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+        delta: u64,
+    ) -> wasmtime::Result<Result<u64, store::Error>> {
+        let bucket = self.table.get(&bucket)?;
+        Ok(self
+            .keyvalue
+            .store
+            .increment(bucket, &key, delta)
+            .map_err(Into::into))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid value in the store: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error(transparent)]
+    File(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("The value of {0:?} is not a counter")]
+    NotACounter(String),
+    #[error("The counter {0:?} overflowed")]
+    Overflow(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::component::Val;
+    use workflow::OutputName;
+
+    use super::*;
+    use crate::{Runtime, testing};
+
+    #[test]
+    fn test_file_store_keeps_buckets_by_scope() {
+        let dir = std::env::temp_dir().join(format!("keyvalue-{}", std::process::id()));
+        let bucket = |scope: &str| Bucket {
+            name: "../cache".to_string(),
+            scope: scope.to_string(),
+        };
+        let store = FileStore::new(&dir);
+        store.set(&bucket("a"), "key", b"value".to_vec()).unwrap();
+        assert_eq!(store.increment(&bucket("a"), "hits", 2).unwrap(), 2);
+
+        let store = FileStore::new(&dir);
+        assert_eq!(store.increment(&bucket("a"), "hits", 3).unwrap(), 5);
+        assert_eq!(store.keys(&bucket("a")).unwrap(), ["hits", "key"]);
+        assert_eq!(store.get(&bucket("b"), "key").unwrap(), None);
+        assert!(matches!(
+            store.increment(&bucket("a"), "key", 1),
+            Err(Error::NotACounter(_))
+        ));
+        assert!(dir.join("a").join("%2E%2E%2Fcache.json").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_workflows_do_not_share_keys() {
+        let store = Arc::new(MemoryStore::default());
+        let mut runtime = Runtime::new().unwrap();
+        runtime.keyvalue = Some(Arc::clone(&store) as Arc<dyn KeyValueStore>);
+        let mut count = async |name: &str| {
+            let yaml = format!(
+                "
+                name: {name}
+                dependencies: {{ counter: $counter }}
+                edges: []
+                nodes: {{ count: {{ run: flaky, use: counter, with: {{ key: '\"hits\"', failures: 0 }} }} }}
+                outputs: {{ count: count }}
+                "
+            );
+            let (report, _) = testing::run(&mut runtime, &yaml).await;
+            report.outputs[&OutputName("count".to_string())].clone()
+        };
+        let hits = |n| Val::Result(Ok(Some(Box::new(Val::U64(n)))));
+        assert_eq!(count("a").await, hits(1));
+        assert_eq!(count("a").await, hits(2));
+        assert_eq!(count("b").await, hits(1));
+
+        let bucket = |scope: &str| Bucket {
+            name: "counts".to_string(),
+            scope: scope.to_string(),
+        };
+        assert_eq!(
+            store.get(&bucket("a"), "hits").unwrap(),
+            Some(b"2".to_vec())
+        );
+        assert_eq!(
+            store.get(&bucket("b"), "hits").unwrap(),
+            Some(b"1".to_vec())
+        );
+    }
+}
@@ -1,6 +1,20 @@
+pub mod cache;
+mod capture;
+pub mod checkpoint;
+pub mod deterministic;
+pub mod json;
+pub mod keyvalue;
+mod logging;
+mod outgoing;
 pub mod prototype;
 mod runtime;
+pub mod secrets;
 mod state;
 pub mod task;
+mod template;
+#[cfg(test)]
+mod testing;
+pub mod trace;
+mod wit;
 
 pub use runtime::*;
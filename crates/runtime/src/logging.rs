@@ -0,0 +1,236 @@
+//! The `wasi:logging` interface, turning the messages components log into
+//! `tracing` events of the `guest` target and into `Event::GuestLog`.
+
+use tracing::Level;
+use wasmtime::component::Linker;
+use workflow::{ComponentName, NodeId};
+
+use crate::{
+    capture::Capture,
+    state::State,
+    task::{Event, LogLevel, TaskId},
+};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit/logging",
+        world: "imports",
+        trappable_imports: true,
+    });
+}
+
+use bindings::wasi::logging::logging;
+
+/// Where the messages of a call of a component go, and what it writes to its
+/// stdout and stderr.
+pub(crate) struct Logger {
+    pub(crate) component_name: ComponentName,
+    /// Appends an event to the task's events
+    pub(crate) emit: Box<dyn Fn(Event) + Send + Sync>,
+    pub(crate) index: Option<u32>,
+    pub(crate) node_id: NodeId,
+    /// ID of the node in the events, nested in the nodes running the ancestors
+    /// of its task
+    pub(crate) nested_id: NodeId,
+    pub(crate) stderr: Capture,
+    pub(crate) stdout: Capture,
+    pub(crate) task_id: TaskId,
+}
+
+pub(crate) fn add_to_linker(linker: &mut Linker<State>) -> wasmtime::Result<()> {
+    bindings::Imports::add_to_linker(linker, |state| state)
+}
+
+impl logging::Host for State {
+    fn log(
+        &mut self,
+        level: logging::Level,
+        context: String,
+        message: String,
+    ) -> wasmtime::Result<()> {
+        let context = self.redactor.redact(&context);
+        let message = self.redactor.redact(&message);
+        let logger = &self.logger;
+        macro_rules! trace {
+            ($level:expr) => {
+                tracing::event!(
+                    target: "guest",
+                    $level,
+                    task_id = %logger.task_id,
+                    node_id = %logger.nested_id.0,
+                    index = logger.index,
+                    component = %logger.component_name.0,
+                    context = %context,
+                    "{message}"
+                )
+            };
+        }
+        let level = match level {
+            logging::Level::Trace => {
+                trace!(Level::TRACE);
+                LogLevel::Trace
+            }
+            logging::Level::Debug => {
+                trace!(Level::DEBUG);
+                LogLevel::Debug
+            }
+            logging::Level::Info => {
+                trace!(Level::INFO);
+                LogLevel::Info
+            }
+            logging::Level::Warn => {
+                trace!(Level::WARN);
+                LogLevel::Warn
+            }
+            logging::Level::Error => {
+                trace!(Level::ERROR);
+                LogLevel::Error
+            }
+            // Beyond what `tracing` distinguishes
+            logging::Level::Critical => {
+                trace!(Level::ERROR);
+                LogLevel::Critical
+            }
+        };
+        (logger.emit)(Event::GuestLog {
+            context,
+            index: logger.index,
+            level,
+            message,
+            node_id: logger.node_id.clone(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fmt::Debug,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{
+        Level, Subscriber,
+        field::{Field, Visit},
+    };
+    use tracing_subscriber::{Layer, layer::Context, prelude::*};
+
+    use crate::{
+        Runtime,
+        task::{Event, LogLevel, TaskStatus},
+        testing,
+    };
+
+    /// The fields of a `tracing` event, by name.
+    type Fields = BTreeMap<String, String>;
+
+    /// The level and fields of the `tracing` events of the `guest` target.
+    #[derive(Clone, Default)]
+    struct Traces(Arc<Mutex<Vec<(Level, Fields)>>>);
+
+    impl<S: Subscriber> Layer<S> for Traces {
+        fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+            struct Visitor(Fields);
+            impl Visit for Visitor {
+                fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                    self.0
+                        .insert(field.name().to_string(), format!("{value:?}"));
+                }
+            }
+            let metadata = event.metadata();
+            if metadata.target() == "guest" {
+                let mut fields = Visitor(Fields::new());
+                event.record(&mut fields);
+                self.0.lock().unwrap().push((*metadata.level(), fields.0));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guest_logs_become_events_and_traces() {
+        let greeter = testing::component(
+            r#"(component
+              (import "wasi:logging/logging@0.1.0-draft" (instance $logging
+                (type $l (enum "trace" "debug" "info" "warn" "error" "critical"))
+                (export $level "level" (type (eq $l)))
+                (export "log"
+                  (func (param "level" $level) (param "context" string) (param "message" string)))))
+              (core module $mem
+                (memory (export "memory") 1)
+                (data (i32.const 100) "cache")
+                (data (i32.const 120) "hello from the guest")
+                (data (i32.const 160) "disk almost full"))
+              (core instance $m (instantiate $mem))
+              (core func $log (canon lower (func $logging "log") (memory $m "memory")))
+              (core module $main
+                (import "host" "log" (func $log (param i32 i32 i32 i32 i32)))
+                (func (export "greet") (result i32)
+                  (call $log (i32.const 2) (i32.const 100) (i32.const 5) (i32.const 120) (i32.const 20))
+                  (call $log (i32.const 5) (i32.const 0) (i32.const 0) (i32.const 160) (i32.const 16))
+                  (i32.const 1)))
+              (core instance $i (instantiate $main
+                (with "host" (instance (export "log" (func $log))))))
+              (func (export "greet") (result u32) (canon lift (core func $i "greet")))
+            )"#,
+        );
+        let traces = Traces::default();
+        let _default = tracing_subscriber::registry()
+            .with(traces.clone())
+            .set_default();
+        let mut runtime = Runtime::new().unwrap();
+        let (report, events) = testing::run(
+            &mut runtime,
+            &format!(
+                "
+                dependencies: {{ greeter: {greeter} }}
+                edges: []
+                nodes:
+                  greet: {{ run: greet, use: greeter }}
+                "
+            ),
+        )
+        .await;
+        assert_eq!(report.status, TaskStatus::Succeeded);
+        let logs: Vec<_> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::GuestLog {
+                    context,
+                    index: None,
+                    level,
+                    message,
+                    node_id,
+                } if node_id.0 == "greet" => Some((level, context, message)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            logs,
+            [
+                (
+                    LogLevel::Info,
+                    "cache".to_string(),
+                    "hello from the guest".to_string()
+                ),
+                (
+                    LogLevel::Critical,
+                    String::new(),
+                    "disk almost full".to_string()
+                ),
+            ]
+        );
+
+        let traces = traces.0.lock().unwrap();
+        let field = |index: usize, name: &str| traces[index].1[name].as_str();
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].0, Level::INFO);
+        assert_eq!(field(0, "message"), "hello from the guest");
+        assert_eq!(field(0, "context"), "cache");
+        assert_eq!(field(0, "node_id"), "greet");
+        // Beyond what `tracing` distinguishes
+        assert_eq!(traces[1].0, Level::ERROR);
+        assert_eq!(field(1, "message"), "disk almost full");
+    }
+}
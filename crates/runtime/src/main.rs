@@ -1,12 +1,25 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf, process::ExitCode, sync::Arc};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use runtime::{
-    Runtime,
+    DEFAULT_MAX_LOG_BYTES, Runtime, RuntimeConfig,
+    cache::{CacheStore, Entry, FileCache},
+    checkpoint::{Checkpoint, CheckpointStore, FileCheckpoints},
+    deterministic::{Deterministic, HttpFixture},
+    keyvalue::FileStore,
     prototype::Prototype,
-    task::{Event, Task},
+    secrets::EncryptedFile,
+    task::{Event, EventRecord, LogLevel, LogStream, Task, TaskId, TaskReport, TaskStatus},
+    trace::Trace,
 };
-use workflow::Workflow;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{Level, level_filters::LevelFilter};
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+use workflow::{Argument, InputName, NodeId, SecretName, Workflow};
+
+/// Environment variable holding the passphrase of the encrypted secrets file.
+const PASSPHRASE_VAR: &str = "RUNTIME_SECRETS_PASSPHRASE";
 
 /// A CLI tool for executing workflows
 #[derive(Debug, Parser)]
@@ -14,73 +27,800 @@ use workflow::Workflow;
 struct Args {
     #[command(subcommand)]
     command: Commands,
+    /// Directory keeping the outputs of the nodes with `cache: true` across runs
+    #[arg(long, global = true, default_value = ".cache")]
+    cache_dir: PathBuf,
+    /// Directory keeping the checkpoints of `run --checkpoint` and `resume`
+    #[arg(long, global = true, default_value = ".checkpoints")]
+    checkpoint_dir: PathBuf,
+    /// Meter the fuel consumed by components, enforcing the workflow's fuel budgets
+    #[arg(long, global = true)]
+    consume_fuel: bool,
+    /// Run reproducibly: components get virtual clocks and seeded random sources, nodes
+    /// run one at a time and backoff and repeat delays are skipped; always set by `replay`
+    #[arg(long, global = true)]
+    deterministic: bool,
+    /// Also trace what components log through `wasi:logging`, as events of the `guest`
+    /// target on stderr, besides printing it from the events with `--logs`
+    #[arg(long, global = true)]
+    guest_traces: bool,
+    /// JSON file of responses to the HTTP requests of components in deterministic mode,
+    /// with `--deterministic` or `replay`, requests without response failing; ignored
+    /// otherwise
+    #[arg(long, global = true)]
+    http_fixtures: Option<PathBuf>,
+    /// Number of bytes kept of the stdout and of the stderr of every call of a node,
+    /// unless the node sets its own `max_log_bytes`
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_LOG_BYTES)]
+    max_log_bytes: usize,
+    /// Seed of the random sources of components in deterministic mode, with
+    /// `--deterministic` or `replay`; ignored otherwise
+    #[arg(long, global = true, default_value_t = 0)]
+    seed: u64,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
+    /// Removes cached outputs of nodes
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// Encrypts a JSON file mapping secret names to values into a file
+    /// `run --secrets-file` reads, with the passphrase of RUNTIME_SECRETS_PASSPHRASE
+    EncryptSecrets {
+        /// Path to the JSON file holding the secrets
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Path to the encrypted file to write
+        #[arg(short, long)]
+        output: PathBuf,
+    },
     Parse {
         /// Path to the workflow manifest file
         #[arg(short, long)]
         workflow: PathBuf,
     },
+    /// Resumes a run of `run --checkpoint` that failed, only running the nodes
+    /// that did not succeed, unless its workflow or components changed since
+    Resume {
+        /// Directory keeping the buckets components open through wasi:keyvalue across
+        /// runs, unnamed workflows being scoped by their file name; buckets are otherwise
+        /// kept in memory during the run
+        #[arg(long)]
+        keyvalue_dir: Option<PathBuf>,
+        /// Print what the components write to stdout and stderr and what they log,
+        /// prefixed by their node, with the `pretty` output
+        #[arg(long)]
+        logs: bool,
+        /// Format of the events and of the final summary
+        #[arg(long, value_enum, default_value_t)]
+        output: Output,
+        /// ID of the run, as in the summary of `run --checkpoint`
+        run_id: String,
+        /// Encrypted file holding secrets, decrypted with the passphrase of
+        /// RUNTIME_SECRETS_PASSPHRASE; secrets are otherwise read from SECRET_<NAME>
+        /// environment variables
+        #[arg(long)]
+        secrets_file: Option<PathBuf>,
+    },
     Run {
+        /// Save the outputs of the nodes as they succeed, for `resume` to continue the
+        /// run if it fails
+        #[arg(long)]
+        checkpoint: bool,
+        /// Value of a workflow input, as `name=value` with a WAVE or JSON value
+        #[arg(long = "input", value_name = "NAME=VALUE", value_parser = parse_input)]
+        inputs: Vec<(InputName, Argument)>,
+        /// JSON or YAML file holding values of workflow inputs, overridden by `--input`
+        #[arg(long)]
+        inputs_file: Option<PathBuf>,
+        /// Directory keeping the buckets components open through wasi:keyvalue across
+        /// runs, unnamed workflows being scoped by their file name; buckets are otherwise
+        /// kept in memory during the run
+        #[arg(long)]
+        keyvalue_dir: Option<PathBuf>,
+        /// Print what the components write to stdout and stderr and what they log,
+        /// prefixed by their node, with the `pretty` output
+        #[arg(long)]
+        logs: bool,
+        /// Run every node, neither reusing nor caching the outputs of those with
+        /// `cache: true`
+        #[arg(long)]
+        no_cache: bool,
+        /// Format of the events and of the final summary
+        #[arg(long, value_enum, default_value_t)]
+        output: Output,
+        /// File to write a trace of the run to, with the inputs, the outputs of the
+        /// nodes, and the calls of components with what their clocks, random sources
+        /// and HTTP requests returned
+        #[arg(long)]
+        record: Option<PathBuf>,
+        /// Encrypted file holding secrets, decrypted with the passphrase of
+        /// RUNTIME_SECRETS_PASSPHRASE; secrets are otherwise read from SECRET_<NAME>
+        /// environment variables
+        #[arg(long)]
+        secrets_file: Option<PathBuf>,
+        /// Path to the workflow manifest file
+        #[arg(short, long)]
+        workflow: PathBuf,
+    },
+    /// Runs a workflow again in deterministic mode against a trace `run --record`
+    /// wrote, with its inputs, reporting the calls of components that diverge from it;
+    /// exits with 2 when one does
+    Replay {
+        /// Only run this node and the nodes after it, the others taking their
+        /// recorded outputs
+        #[arg(long, value_name = "NODE_ID")]
+        from: Option<String>,
+        /// Print what the components write to stdout and stderr and what they log,
+        /// prefixed by their node, with the `pretty` output
+        #[arg(long)]
+        logs: bool,
+        /// Format of the events and of the final summary
+        #[arg(long, value_enum, default_value_t)]
+        output: Output,
+        /// File to write a trace of the replay to
+        #[arg(long)]
+        record: Option<PathBuf>,
+        /// Encrypted file holding secrets, decrypted with the passphrase of
+        /// RUNTIME_SECRETS_PASSPHRASE; secrets are otherwise read from SECRET_<NAME>
+        /// environment variables
+        #[arg(long)]
+        secrets_file: Option<PathBuf>,
+        /// Path to the trace to replay
+        #[arg(short, long)]
+        trace: PathBuf,
         /// Path to the workflow manifest file
         #[arg(short, long)]
         workflow: PathBuf,
     },
 }
 
-impl Commands {
-    fn workflow(&self) -> &PathBuf {
-        match self {
-            Commands::Parse { workflow } => workflow,
-            Commands::Run { workflow } => workflow,
-        }
-    }
+#[derive(Debug, Subcommand)]
+enum CacheCommands {
+    /// Removes every cached output, or those of the functions of a component
+    Clear {
+        /// Only remove the outputs of the functions of this component, as named in
+        /// the dependencies of the workflow
+        #[arg(long)]
+        component: Option<String>,
+    },
+    /// Removes the cached outputs that expired
+    Prune,
+}
+
+/// How the task starts.
+enum Start {
+    New(HashMap<InputName, Argument>),
+    /// From the trace, only running the given node and those after it, if any
+    Replay(Trace, Option<NodeId>),
+    Resume(Checkpoint),
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum Output {
+    /// A single JSON document with every event and the summary, once the task completed
+    Json,
+    /// One JSON object per line for every event as it happens, then the summary
+    Ndjson,
+    /// Human-readable lines
+    #[default]
+    Pretty,
+}
+
+fn parse_input(s: &str) -> Result<(InputName, Argument), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected NAME=VALUE, found {s:?}"))?;
+    let Ok(argument) = value.parse::<Argument>();
+    Ok((InputName(name.to_string()), argument))
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt::init();
+async fn main() -> Result<ExitCode, Error> {
     let args = Args::parse();
-    let mut runtime = Runtime::new()?;
-    let workflow = Workflow::load(args.command.workflow())?;
+    // Logs go to stderr, keeping stdout parseable. What components log is
+    // printed from the events instead, unless traced as well
+    let guest_level = match args.guest_traces {
+        true => LevelFilter::TRACE,
+        false => LevelFilter::OFF,
+    };
+    let targets = Targets::new()
+        .with_default(Level::INFO)
+        .with_target("guest", guest_level);
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(targets)
+        .init();
+    let checkpoints = Arc::new(FileCheckpoints::new(&args.checkpoint_dir));
+    let mut resumed = None;
+    let (mut workflow, path) = match &args.command {
+        Commands::Cache { command } => {
+            let cache = FileCache::new(&args.cache_dir);
+            let removed = match command {
+                CacheCommands::Clear { component: None } => cache.remove(&|_| true)?,
+                CacheCommands::Clear {
+                    component: Some(component),
+                } => cache.remove(&|entry| entry.component == *component)?,
+                CacheCommands::Prune => cache.remove(&Entry::is_expired)?,
+            };
+            println!("Removed {removed} cached output(s)");
+            return Ok(ExitCode::SUCCESS);
+        }
+        Commands::EncryptSecrets { input, output } => {
+            let secrets: HashMap<SecretName, String> =
+                serde_json::from_str(&std::fs::read_to_string(input)?)?;
+            let encrypted = EncryptedFile::encrypt(&secrets, &passphrase()?)?;
+            std::fs::write(output, encrypted)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Commands::Parse { workflow }
+        | Commands::Replay { workflow, .. }
+        | Commands::Run { workflow, .. } => {
+            (Workflow::load(workflow)?, std::path::absolute(workflow)?)
+        }
+        Commands::Resume { run_id, .. } => {
+            let checkpoint = checkpoints
+                .load(&TaskId(run_id.clone()))?
+                .ok_or_else(|| Error::UnknownRun(run_id.clone()))?;
+            let path = checkpoint
+                .manifest
+                .clone()
+                .ok_or_else(|| Error::UnknownRun(run_id.clone()))?;
+            resumed = Some(checkpoint);
+            (Workflow::load(&path)?, path)
+        }
+    };
+    // Unnamed workflows keep their buckets by file name, wherever the file is,
+    // and so does their digest
+    workflow.name.get_or_insert_with(|| {
+        path.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    });
+    let replay = matches!(args.command, Commands::Replay { .. });
+    let deterministic = match args.deterministic || replay {
+        true => Some(Deterministic {
+            http_fixtures: match &args.http_fixtures {
+                Some(path) => HttpFixture::load(path)?,
+                None => Vec::new(),
+            },
+            seed: args.seed,
+        }),
+        false => None,
+    };
+    let mut runtime = Runtime::with_config(RuntimeConfig {
+        consume_fuel: args.consume_fuel,
+        deterministic,
+        max_log_bytes: Some(args.max_log_bytes),
+        ..RuntimeConfig::default()
+    })?;
+    // Deterministic runs and traces hold the calls that run
+    let no_cache = matches!(
+        args.command,
+        Commands::Replay { .. }
+            | Commands::Run { no_cache: true, .. }
+            | Commands::Run {
+                record: Some(_),
+                ..
+            }
+    );
+    if !args.deterministic && !no_cache {
+        runtime.cache = Some(Arc::new(FileCache::new(&args.cache_dir)));
+    }
     let prototype = Prototype::new(&mut runtime, &workflow).await?;
 
-    if let Commands::Run { .. } = args.command {
-        let mut task = Task::new(&mut runtime, &prototype).await?;
-        let mut subscribe = task.subscribe();
+    let (start, checkpoint, keyvalue_dir, logs, output, record, secrets_file) = match args.command {
+        Commands::Replay {
+            from,
+            logs,
+            output,
+            record,
+            secrets_file,
+            trace,
+            ..
+        } => {
+            let start = Start::Replay(Trace::load(&trace)?, from.map(NodeId));
+            (start, false, None, logs, output, record, secrets_file)
+        }
+        Commands::Resume {
+            keyvalue_dir,
+            logs,
+            output,
+            secrets_file,
+            ..
+        } => {
+            let start = Start::Resume(resumed.unwrap());
+            (start, true, keyvalue_dir, logs, output, None, secrets_file)
+        }
+        Commands::Run {
+            checkpoint,
+            inputs,
+            inputs_file,
+            keyvalue_dir,
+            logs,
+            output,
+            record,
+            secrets_file,
+            ..
+        } => {
+            let mut arguments = match &inputs_file {
+                Some(path) => workflow::load_arguments(path)?,
+                None => HashMap::new(),
+            };
+            arguments.extend(inputs);
+            let start = Start::New(arguments);
+            (
+                start,
+                checkpoint,
+                keyvalue_dir,
+                logs,
+                output,
+                record,
+                secrets_file,
+            )
+        }
+        _ => return Ok(ExitCode::SUCCESS),
+    };
+    if let Some(dir) = keyvalue_dir {
+        runtime.keyvalue = Some(Arc::new(FileStore::new(dir)));
+    }
+    // Secrets of the file take precedence over those of the environment
+    if let Some(path) = &secrets_file {
+        let file = EncryptedFile::open(path, &passphrase()?)?;
+        runtime.secrets.insert(0, Box::new(file));
+    }
+    let mut task = match start {
+        Start::New(arguments) => Task::new(&mut runtime, &prototype, &arguments).await?,
+        Start::Replay(trace, from) => {
+            let mut task = Task::new(&mut runtime, &prototype, &trace.arguments()?).await?;
+            task.replay(trace, from.as_ref())?;
+            task
+        }
+        Start::Resume(checkpoint) => Task::resume(&mut runtime, &prototype, &checkpoint).await?,
+    };
+    if record.is_some() && !replay {
+        task.record();
+    }
+    let run_id = checkpoint.then(|| task.id().clone());
+    if checkpoint {
+        task.checkpoint(checkpoints, Some(path));
+    }
+    let mut subscription = task.subscribe();
 
-        tokio::spawn(async move {
-            task.run().await;
-        });
+    // Ctrl-C stops the task gracefully, events are still printed until it ends.
+    // A second one exits at once, should a call never yield
+    let cancellation_handle = task.cancellation_handle();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancellation_handle.cancel();
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(exit_code(TaskStatus::Cancelled).into());
+            }
+        }
+    });
+
+    let run = tokio::spawn(async move {
+        let report = task.run().await;
+        (report, task.trace())
+    });
+
+    let mut events = Vec::new();
+    let mut divergences = 0;
+    while let Some(event) = subscription.recv().await {
+        if let Event::ExecutionDiverged { .. } = event.event {
+            divergences += 1;
+        }
+        match output {
+            Output::Json => events.push(event),
+            Output::Ndjson => println!("{}", serde_json::to_string(&event)?),
+            Output::Pretty => print_event(event, logs),
+        }
+    }
+
+    let (report, trace) = run.await?;
+    if let (Some(path), Some(trace)) = (&record, trace) {
+        trace.save(path)?;
+    }
+    let summary = Summary::new(&report, replay.then_some(divergences), run_id.as_ref());
+    match output {
+        Output::Json | Output::Ndjson => println!("{}", output.render(events, &summary)?),
+        Output::Pretty => {
+            for (output_name, val) in &report.outputs {
+                let wave = val.to_wave().unwrap_or_else(|_| format!("{val:?}"));
+                println!("{output_name} = {wave}");
+            }
+            if divergences > 0 {
+                eprintln!("{divergences} call(s) diverged from the trace");
+            }
+            if let Some(run_id) = &run_id
+                && report.status != TaskStatus::Succeeded
+            {
+                eprintln!("Resume the run with: runtime resume {run_id}");
+            }
+        }
+    }
+
+    Ok(ExitCode::from(summary.exit_code))
+}
+
+fn passphrase() -> Result<String, Error> {
+    std::env::var(PASSPHRASE_VAR).map_err(|_| Error::MissingPassphrase)
+}
 
-        while let Ok(event) = subscribe.recv().await {
-            match event {
-                Event::ExecutionStarted(node_id, params) => {
-                    println!("{node_id} started with params: {params:?}")
-                }
-                Event::ExecutionSucceeded(node_id, output) => {
-                    println!("{node_id} succeeded with output: {output:?}")
-                }
-                Event::ExecutionFailed(node_id, error) => {
-                    eprintln!("{node_id} failed with error: {error:?}")
-                }
+/// Final summary of `runtime run`, `runtime replay` and `runtime resume`.
+#[derive(Serialize)]
+struct Summary<'a> {
+    /// Number of calls diverging from the trace, only set by `replay`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    divergences: Option<u32>,
+    /// 2 when a task that succeeded diverged from the trace it replays
+    exit_code: u8,
+    #[serde(flatten)]
+    report: &'a TaskReport,
+    /// ID to resume the run with, only set when it saves checkpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_id: Option<&'a TaskId>,
+}
+
+impl<'a> Summary<'a> {
+    fn new(report: &'a TaskReport, divergences: Option<u32>, run_id: Option<&'a TaskId>) -> Self {
+        Self {
+            divergences,
+            exit_code: match exit_code(report.status) {
+                0 if divergences.is_some_and(|divergences| divergences > 0) => 2,
+                exit_code => exit_code,
+            },
+            report,
+            run_id,
+        }
+    }
+}
+
+impl Output {
+    /// Renders what is printed once the task completed in JSON or NDJSON: the
+    /// events, only kept for JSON, and the summary.
+    fn render(self, events: Vec<EventRecord>, summary: &Summary) -> serde_json::Result<String> {
+        match self {
+            Output::Json => {
+                serde_json::to_string_pretty(&json!({ "events": events, "summary": summary }))
+            }
+            Output::Ndjson | Output::Pretty => {
+                serde_json::to_string(&json!({ "summary": summary }))
             }
         }
     }
+}
 
-    Ok(())
+fn exit_code(status: TaskStatus) -> u8 {
+    match status {
+        // Same as a process interrupted by SIGINT
+        TaskStatus::Cancelled => 130,
+        TaskStatus::Failed => 1,
+        TaskStatus::Succeeded => 0,
+    }
+}
+
+fn print_event(EventRecord { elapsed, event, .. }: EventRecord, logs: bool) {
+    let elapsed = elapsed.as_secs_f64();
+    // Elements of nodes with a `for_each` are shown as `node:id[index]`
+    let node = |node_id: NodeId, index: Option<u32>| match index {
+        Some(index) => format!("{node_id}[{index}]"),
+        None => node_id.to_string(),
+    };
+    let during = |iteration: Option<u32>| match iteration {
+        Some(iteration) => format!(" iteration {iteration}"),
+        None => String::new(),
+    };
+    match event {
+        Event::ExecutionCached {
+            index,
+            node_id,
+            output,
+        } => println!(
+            "[{elapsed:.3}s] {} reused its cached output: {output:?}",
+            node(node_id, index)
+        ),
+        Event::ExecutionCancelled {
+            attempt,
+            index,
+            node_id,
+            ..
+        } => eprintln!(
+            "[{elapsed:.3}s] {} cancelled during attempt {attempt}",
+            node(node_id, index)
+        ),
+        Event::ExecutionDiverged {
+            divergences,
+            index,
+            node_id,
+        } => {
+            let fields: Vec<_> = divergences.keys().map(String::as_str).collect();
+            eprintln!(
+                "[{elapsed:.3}s] {} diverged from the trace: {}",
+                node(node_id, index),
+                fields.join(", ")
+            )
+        }
+        Event::ExecutionFailed {
+            attempt,
+            duration,
+            error,
+            index,
+            node_id,
+        } => eprintln!(
+            "[{elapsed:.3}s] {} failed after {attempt} attempt(s) in {duration:?} with error: {error:?}",
+            node(node_id, index)
+        ),
+        Event::ExecutionFannedOut { items, node_id } => {
+            println!("[{elapsed:.3}s] {node_id} runs for each of {items} element(s)")
+        }
+        Event::ExecutionLimitExceeded {
+            index,
+            limit_exceeded,
+            node_id,
+            ..
+        } => eprintln!(
+            "[{elapsed:.3}s] {} failed: {limit_exceeded}",
+            node(node_id, index)
+        ),
+        Event::ExecutionOutOfFuel {
+            fuel_consumed,
+            index,
+            node_id,
+            ..
+        } => eprintln!(
+            "[{elapsed:.3}s] {} ran out of fuel after consuming {fuel_consumed}",
+            node(node_id, index)
+        ),
+        Event::ExecutionRepeating {
+            delay,
+            index,
+            iteration,
+            node_id,
+            output,
+        } => println!(
+            "[{elapsed:.3}s] {} iteration {iteration} returned {output:?}, repeating in {delay:?}",
+            node(node_id, index)
+        ),
+        Event::ExecutionRetrying {
+            attempt,
+            delay,
+            error,
+            index,
+            iteration,
+            node_id,
+        } => eprintln!(
+            "[{elapsed:.3}s] {}{} attempt {attempt} failed with error: {error:?}, retrying in {delay:?}",
+            node(node_id, index),
+            during(iteration)
+        ),
+        Event::ExecutionSkipped { cause, node_id } if cause == node_id => {
+            println!("[{elapsed:.3}s] {node_id} skipped: its condition is false")
+        }
+        Event::ExecutionSkipped { cause, node_id } => {
+            println!("[{elapsed:.3}s] {node_id} skipped along with {cause}")
+        }
+        Event::ExecutionStarted {
+            attempt,
+            index,
+            iteration,
+            node_id,
+            params,
+        } => println!(
+            "[{elapsed:.3}s] {}{} attempt {attempt} started with params: {params:?}",
+            node(node_id, index),
+            during(iteration)
+        ),
+        Event::ExecutionSucceeded {
+            duration,
+            fuel_consumed,
+            index,
+            node_id,
+            output,
+            ..
+        } => match fuel_consumed {
+            Some(fuel_consumed) => println!(
+                "[{elapsed:.3}s] {} succeeded in {duration:?} with output: {output:?}, consuming {fuel_consumed} fuel",
+                node(node_id, index)
+            ),
+            None => println!(
+                "[{elapsed:.3}s] {} succeeded in {duration:?} with output: {output:?}",
+                node(node_id, index)
+            ),
+        },
+        Event::ExecutionTimedOut {
+            index,
+            node_id,
+            timeout,
+            ..
+        } => eprintln!(
+            "[{elapsed:.3}s] {} timed out after {timeout:?}",
+            node(node_id, index)
+        ),
+        Event::GuestLog { .. } | Event::Log { .. } if !logs => {}
+        Event::GuestLog {
+            context,
+            index,
+            level,
+            message,
+            node_id,
+        } => {
+            let context = match context.is_empty() {
+                true => String::new(),
+                false => format!(" {context}"),
+            };
+            let line = format!(
+                "[{elapsed:.3}s] {} {level:?}{context}: {message}",
+                node(node_id, index)
+            );
+            match level {
+                LogLevel::Critical | LogLevel::Error | LogLevel::Warn => eprintln!("{line}"),
+                LogLevel::Debug | LogLevel::Info | LogLevel::Trace => println!("{line}"),
+            }
+        }
+        Event::Log {
+            index,
+            line,
+            node_id,
+            stream: LogStream::Stderr,
+        } => eprintln!("[{elapsed:.3}s] {} | {line}", node(node_id, index)),
+        Event::Log {
+            index,
+            line,
+            node_id,
+            stream: LogStream::Stdout,
+        } => println!("[{elapsed:.3}s] {} | {line}", node(node_id, index)),
+        Event::TaskCancelled => eprintln!("[{elapsed:.3}s] task cancelled"),
+        Event::TaskCompleted { duration, status } => {
+            println!("[{elapsed:.3}s] task completed in {duration:?}: {status:?}")
+        }
+        Event::TaskStarted => println!("[{elapsed:.3}s] task started"),
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("Cannot remove the cached outputs: {0}")]
+    Cache(#[from] runtime::cache::Error),
+    #[error("Cannot read the checkpoint: {0}")]
+    Checkpoint(#[from] runtime::checkpoint::Error),
+    #[error("Invalid HTTP fixtures: {0}")]
+    Deterministic(#[from] runtime::deterministic::Error),
+    #[error(transparent)]
+    File(#[from] std::io::Error),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("The passphrase of the secrets must be set in {PASSPHRASE_VAR}")]
+    MissingPassphrase,
     #[error(transparent)]
     Prototype(#[from] runtime::prototype::Error),
     #[error(transparent)]
     Runtime(#[from] runtime::Error),
     #[error(transparent)]
+    Secret(#[from] runtime::secrets::Error),
+    #[error(transparent)]
     Task(#[from] runtime::task::Error),
+    #[error("Invalid trace: {0}")]
+    Trace(#[from] runtime::trace::Error),
+    #[error("No checkpoint of run {0:?}")]
+    UnknownRun(String),
     #[error(transparent)]
     Workflow(#[from] workflow::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use runtime::task::{NodeReport, NodeStatus};
+    use wasmtime::component::Val;
+    use workflow::OutputName;
+
+    use super::*;
+
+    fn report(status: TaskStatus) -> TaskReport {
+        TaskReport {
+            duration: Duration::from_millis(12),
+            nodes: HashMap::from([(
+                NodeId("sum".to_string()),
+                NodeReport {
+                    attempts: 1,
+                    duration: Duration::from_micros(1500),
+                    output: Some(Val::Record(vec![("n".to_string(), Val::U32(3))])),
+                    status: NodeStatus::Succeeded,
+                },
+            )]),
+            outputs: HashMap::from([(
+                OutputName("total".to_string()),
+                Val::Record(vec![("n".to_string(), Val::U32(3))]),
+            )]),
+            status,
+        }
+    }
+
+    #[test]
+    fn test_exit_codes_reflect_the_task() {
+        let exit_code =
+            |status, divergences| Summary::new(&report(status), divergences, None).exit_code;
+        assert_eq!(exit_code(TaskStatus::Succeeded, None), 0);
+        assert_eq!(exit_code(TaskStatus::Failed, None), 1);
+        assert_eq!(exit_code(TaskStatus::Cancelled, None), 130);
+        assert_eq!(exit_code(TaskStatus::Succeeded, Some(0)), 0);
+        assert_eq!(exit_code(TaskStatus::Succeeded, Some(2)), 2);
+        assert_eq!(exit_code(TaskStatus::Failed, Some(2)), 1);
+    }
+
+    #[test]
+    fn test_json_output_holds_the_events_and_the_summary() {
+        let report = report(TaskStatus::Failed);
+        let run_id = TaskId("run".to_string());
+        let summary = Summary::new(&report, None, Some(&run_id));
+        let events = vec![EventRecord {
+            elapsed: Duration::ZERO,
+            event: Event::TaskStarted,
+            sequence: 0,
+        }];
+        let summary_json = json!({
+            "duration_ms": 12.0,
+            "exit_code": 1,
+            "nodes": {
+                "sum": {
+                    "attempts": 1,
+                    "duration_ms": 1.5,
+                    "output": { "json": { "n": 3 }, "wave": "{n: 3}" },
+                    "status": "succeeded",
+                },
+            },
+            "outputs": { "total": { "json": { "n": 3 }, "wave": "{n: 3}" } },
+            "run_id": "run",
+            "status": "failed",
+        });
+        let rendered = Output::Json.render(events, &summary).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&rendered).unwrap(),
+            json!({
+                "events": [{ "elapsed_ms": 0.0, "event": "task_started", "sequence": 0 }],
+                "summary": summary_json,
+            })
+        );
+
+        // Every event on a line of its own as it happens, then the summary
+        let event = EventRecord {
+            elapsed: Duration::from_millis(3),
+            event: Event::ExecutionSucceeded {
+                attempt: 1,
+                duration: Duration::from_millis(2),
+                fuel_consumed: None,
+                index: Some(0),
+                node_id: NodeId("sum".to_string()),
+                output: Some(Val::List(vec![Val::String("a".to_string())])),
+            },
+            sequence: 1,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "attempt": 1,
+                "duration_ms": 2.0,
+                "elapsed_ms": 3.0,
+                "event": "execution_succeeded",
+                "fuel_consumed": null,
+                "index": 0,
+                "node_id": "sum",
+                "output": { "json": ["a"], "wave": "[\"a\"]" },
+                "sequence": 1,
+            })
+        );
+        let ndjson = Output::Ndjson.render(vec![event], &summary).unwrap();
+        assert!(!ndjson.contains('\n'));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&ndjson).unwrap(),
+            json!({ "summary": summary_json })
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use http_body_util::{BodyExt, Limited};
+use hyper::{
+    Request,
+    header::{CONTENT_LENGTH, HeaderMap, HeaderName, HeaderValue},
+};
+use wasmtime_wasi_http::{
+    bindings::http::types::ErrorCode, body::HyperOutgoingBody, types::OutgoingRequestConfig,
+};
+use workflow::{HttpPolicy, HttpRule};
+
+use crate::secrets::Redactor;
+
+/// Applies a component's `HttpPolicy` to one of its outgoing requests.
+///
+/// Requests no rule allows are denied with `ErrorCode::HttpRequestDenied`.
+/// The others get the policy's headers, body size limit and timeouts.
+pub(crate) fn prepare(
+    policy: &HttpPolicy,
+    redactor: &Redactor,
+    mut request: Request<HyperOutgoingBody>,
+    mut config: OutgoingRequestConfig,
+) -> Result<(Request<HyperOutgoingBody>, OutgoingRequestConfig), ErrorCode> {
+    let uri = request.uri();
+    let scheme = uri
+        .scheme_str()
+        .unwrap_or(if config.use_tls { "https" } else { "http" });
+    let host = uri.host().ok_or(ErrorCode::HttpRequestUriInvalid)?;
+    let port = uri.port_u16();
+    let method = request.method().as_str();
+    // The URI may hold secrets passed to the component
+    let shown_uri = || redactor.redact(&uri.to_string());
+    let is_allowed = policy
+        .allow
+        .iter()
+        .any(|rule| allows(rule, scheme, host, port, method));
+    if !is_allowed {
+        tracing::warn!(%method, uri = %shown_uri(), "HTTP request denied by the component's policy");
+        return Err(ErrorCode::HttpRequestDenied);
+    }
+
+    if let Some(max_body_bytes) = policy.max_body_bytes {
+        let content_length = request
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+        if content_length.is_some_and(|length| length > max_body_bytes) {
+            tracing::warn!(%method, uri = %shown_uri(), "HTTP request denied, its body is too large");
+            return Err(ErrorCode::HttpRequestBodySize(content_length));
+        }
+        request = request.map(|body| {
+            Limited::new(body, max_body_bytes as usize)
+                .map_err(|e| match e.downcast::<ErrorCode>() {
+                    Ok(code) => *code,
+                    Err(_) => ErrorCode::HttpRequestBodySize(None),
+                })
+                .boxed()
+        });
+    }
+
+    let headers = headers(policy).map_err(|e| ErrorCode::InternalError(Some(e)))?;
+    request.headers_mut().extend(headers);
+
+    if let Some(timeout) = policy.timeout {
+        let shorten = |limit: &mut Duration| *limit = (*limit).min(*timeout);
+        shorten(&mut config.connect_timeout);
+        shorten(&mut config.first_byte_timeout);
+        shorten(&mut config.between_bytes_timeout);
+    }
+    Ok((request, config))
+}
+
+/// Parses the headers a policy adds to every request.
+pub(crate) fn headers(policy: &HttpPolicy) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &policy.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| format!("Invalid header name {name:?}: {e}"))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|e| format!("Invalid value of header {name}: {e}"))?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+fn allows(rule: &HttpRule, scheme: &str, host: &str, port: Option<u16>, method: &str) -> bool {
+    let default_port = match scheme {
+        "http" => 80,
+        "https" => 443,
+        _ => return false,
+    };
+    let port = port.unwrap_or(default_port);
+    let host = host.to_ascii_lowercase();
+    let rule_host = rule.host.to_ascii_lowercase();
+    let host_matches = match rule_host.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.') && subdomain.len() > 1),
+        None => host == rule_host,
+    };
+    let port_matches = match rule.ports.is_empty() {
+        true => port == default_port,
+        false => rule.ports.contains(&port),
+    };
+    host_matches
+        && port_matches
+        && rule.schemes.iter().any(|s| s.eq_ignore_ascii_case(scheme))
+        && (rule.methods.is_empty() || rule.methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::Empty;
+    use hyper::body::Bytes;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use wasmtime_wasi_http::types::default_send_request_handler;
+
+    use super::*;
+
+    fn request(method: &str, uri: &str) -> Request<HyperOutgoingBody> {
+        let body = Empty::<Bytes>::new()
+            .map_err(|never| match never {})
+            .boxed();
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(body)
+            .unwrap()
+    }
+
+    fn config() -> OutgoingRequestConfig {
+        OutgoingRequestConfig {
+            use_tls: false,
+            connect_timeout: Duration::from_secs(600),
+            first_byte_timeout: Duration::from_secs(600),
+            between_bytes_timeout: Duration::from_secs(600),
+        }
+    }
+
+    #[test]
+    fn test_deny_requests_without_rule() {
+        let policy: HttpPolicy = serde_json::from_value(serde_json::json!({
+            "allow": [
+                { "host": "*.example.com", "methods": ["GET"] },
+                { "host": "localhost", "ports": [8080], "schemes": ["http"] },
+            ]
+        }))
+        .unwrap();
+        let redactor = Redactor::default();
+        let allowed =
+            |method, uri| prepare(&policy, &redactor, request(method, uri), config()).is_ok();
+        assert!(allowed("GET", "https://api.example.com/status"));
+        assert!(allowed("GET", "https://API.example.com:443/"));
+        assert!(!allowed("POST", "https://api.example.com/status"));
+        assert!(!allowed("GET", "https://example.com/"));
+        assert!(!allowed("GET", "https://evilexample.com/"));
+        assert!(!allowed("GET", "http://api.example.com/"));
+        assert!(!allowed("GET", "https://api.example.com:8443/"));
+        assert!(allowed("POST", "http://localhost:8080/"));
+        assert!(!allowed("POST", "http://localhost/"));
+
+        let request = request("GET", "https://example.com/");
+        assert!(matches!(
+            prepare(&HttpPolicy::default(), &redactor, request, config()),
+            Err(ErrorCode::HttpRequestDenied)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_send_allowed_request_with_policy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let stub = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = vec![0; 4096];
+            let length = stream.read(&mut received).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&received[..length]).to_lowercase()
+        });
+
+        let policy: HttpPolicy = serde_json::from_value(serde_json::json!({
+            "allow": [{ "host": "127.0.0.1", "ports": [port], "schemes": ["http"] }],
+            "headers": { "authorization": "Bearer secret" },
+            "timeout": "5s",
+        }))
+        .unwrap();
+        let uri = format!("http://127.0.0.1:{port}/status");
+        let redactor = Redactor::default();
+        let (request, config) =
+            prepare(&policy, &redactor, request("GET", &uri), config()).unwrap();
+        assert_eq!(config.first_byte_timeout, Duration::from_secs(5));
+
+        let response = default_send_request_handler(request, config).await.unwrap();
+        assert_eq!(response.resp.status(), 204);
+        assert!(stub.await.unwrap().contains("authorization: bearer secret"));
+    }
+}
@@ -1,146 +1,950 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    num::NonZeroU32,
+    ops::Deref,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
 
-use petgraph::{Graph, acyclic::Acyclic, graph::NodeIndex};
-use wasmtime::component::{Component, ComponentExportIndex, Val, types::ComponentItem};
-use workflow::{ComponentName, Edge, FunctionName, InputName, Node, NodeId, Workflow};
+use file_source::FileSource;
 
-use crate::runtime::Runtime;
+use petgraph::{Graph, Incoming, acyclic::Acyclic, graph::NodeIndex};
+use wasmtime::component::{Component, ComponentExportIndex, Type, Val, types::ComponentItem};
+use workflow::{
+    Argument, Capabilities, ComponentName, Edge, FunctionName, InputName, NodeId, OutputName,
+    Retry, SecretName, Workflow,
+};
+
+use crate::{
+    cache::Caching,
+    checkpoint::{Digests, sha256},
+    json, outgoing,
+    runtime::Runtime,
+    state::{self, Limiter},
+    template::{Reference, Scope, Template},
+    wit,
+};
 
 /// A `Prototype` represents a compiled, static workflow definition.
 ///
 /// It holds:
-/// - A list of compiled WebAssembly `Component`.
-/// - A graph of nodes (functions and values) and edges (inputs) representing the workflow.
+/// - A list of compiled WebAssembly `Component`, with their resource limits and
+///   WASI capabilities.
+/// - A graph of nodes (functions, values, templates, conditions and lists) and
+///   edges (inputs, conditions, lists and references) representing the
+///   workflow.
+/// - The type and default value of the workflow's inputs, and the nodes
+///   providing its outputs.
+/// - An optional fuel budget and time limit for a whole execution.
+/// - The names of the secrets of the workflow and of the workflows its nodes use.
+/// - The scope of the buckets its components open in the key-value store.
+/// - The digests of the workflow and of its components.
 ///
 /// `Prototype` instances are created once and can be executed many times
 /// by spawning new `Task` instances. Compilation is cached by the
 /// `Program`'s `Engine`, making `Prototype` instantiation cheap.
 pub struct Prototype {
+    pub(crate) capabilities: HashMap<ComponentName, Capabilities>,
     pub(crate) components: HashMap<ComponentName, Component>,
-    pub(crate) graph: Acyclic<Graph<NodeType, InputName>>,
+    pub(crate) digests: Digests,
+    pub(crate) fuel: Option<u64>,
+    pub(crate) graph: Dag,
+    pub(crate) inputs: HashMap<InputName, WorkflowInput>,
+    pub(crate) limiters: HashMap<ComponentName, Limiter>,
+    pub(crate) outputs: HashMap<OutputName, NodeId>,
+    /// The workflow's name, or the source of a workflow used by a node
+    pub(crate) scope: String,
+    pub(crate) secrets: HashSet<SecretName>,
+    pub(crate) timeout: Option<Duration>,
 }
 
 impl Prototype {
     pub async fn new(runtime: &mut Runtime, workflow: &Workflow) -> Result<Self, Error> {
-        // Compiled components
-        let mut components = HashMap::new();
-
-        // Graph of nodes and edges
-        let mut graph = Graph::new();
-        let mut node_indices = HashMap::new();
-
-        for (node_id, node) in &workflow.nodes {
-            // First, we get the component or we load it
-            let component = match components.get(&node.r#use) {
-                Some(component) => component,
-                None => {
-                    let file_source = workflow
-                        .dependencies
-                        .get(&node.r#use)
-                        .ok_or(Error::DependencyNotFound(node.r#use.clone()))?;
-                    let bytes = file_source.load().await?;
-                    let component = Component::from_binary(&runtime.engine, &bytes)?;
-                    components.insert(node.r#use.clone(), component);
-                    components.get(&node.r#use).unwrap()
-                }
+        Self::compile(runtime, workflow, &mut Vec::new()).await
+    }
+
+    pub fn digests(&self) -> &Digests {
+        &self.digests
+    }
+
+    /// Compiles the workflow and, recursively, the workflows its nodes use,
+    /// `sources` holding the sources of the workflows being compiled.
+    fn compile<'a>(
+        runtime: &'a mut Runtime,
+        workflow: &'a Workflow,
+        sources: &'a mut Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = Result<Self, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            // Fuel budgets can only be enforced by a runtime metering fuel
+            let has_fuel_budget =
+                workflow.fuel.is_some() || workflow.nodes.values().any(|node| node.fuel.is_some());
+            if has_fuel_budget && !runtime.config.consume_fuel {
+                Err(Error::FuelNotEnabled)?;
+            }
+
+            // Compiled components and workflows
+            let mut capabilities = HashMap::new();
+            let mut components = HashMap::new();
+            let mut component_digests = BTreeMap::new();
+            let mut limiters = HashMap::new();
+            let mut workflows = HashMap::new();
+
+            // Graph of nodes and edges
+            let mut graph = Graph::new();
+            let mut node_indices = HashMap::new();
+
+            // Parameters and output type of every function
+            let mut signatures = Vec::new();
+            let mut output_types = HashMap::new();
+
+            // Secrets are strings, and the only references of capabilities
+            let secret_type = Some(Type::String);
+            let secret_scope = Scope {
+                index: None,
+                inputs: HashMap::new(),
+                item: None,
+                nodes: HashMap::new(),
+                secrets: workflow
+                    .secrets
+                    .iter()
+                    .map(|name| (name.clone(), &secret_type))
+                    .collect(),
             };
 
-            // Then, we lookup the corresponding function
-            let (item, index) = component.export_index(None, &node.run).ok_or({
-                Error::MissingFunction(
-                    node.run.clone(),
-                    component
-                        .component_type()
-                        .exports(&runtime.engine)
-                        .map(|(name, _)| name.to_string())
-                        .collect(),
-                )
-            })?;
+            for (node_id, node) in &workflow.nodes {
+                let (callee, params, output_type) = match workflow.workflows.get(&node.r#use) {
+                    Some(source) => {
+                        if workflow.dependencies.contains_key(&node.r#use) {
+                            Err(Error::AmbiguousDependency(node.r#use.clone()))?;
+                        }
+                        if node.run.is_some() {
+                            Err(Error::UnexpectedFunction(node_id.clone()))?;
+                        }
+                        if node.cache {
+                            Err(Error::CachedWorkflow(node_id.clone()))?;
+                        }
+
+                        // First, we get the workflow or we compile it
+                        let prototype = match workflows.get(&node.r#use) {
+                            Some(prototype) => Arc::clone(prototype),
+                            None => {
+                                let prototype =
+                                    Self::load(runtime, source, sources).await.map_err(|e| {
+                                        Error::Workflow(node.r#use.clone(), Box::new(e))
+                                    })?;
+                                let prototype = Arc::new(prototype);
+                                workflows.insert(node.r#use.clone(), Arc::clone(&prototype));
+                                prototype
+                            }
+                        };
+
+                        // Then, its inputs are the node's parameters, those
+                        // with a default value only when provided
+                        let is_provided = |input_name: &InputName| {
+                            node.with.contains_key(input_name)
+                                || workflow.edges.iter().any(|edge| {
+                                    edge.target == *node_id && edge.input == *input_name
+                                })
+                        };
+                        let params: Vec<_> = prototype
+                            .inputs
+                            .iter()
+                            .filter(|(input_name, input)| {
+                                input.default.is_none() || is_provided(input_name)
+                            })
+                            .map(|(input_name, input)| (input_name.clone(), input.ty.clone()))
+                            .collect();
+
+                        // Its outputs are gathered in a record no component types
+                        let output_type = (!prototype.outputs.is_empty()).then_some(None);
+                        (Callee::Workflow(prototype), params, output_type)
+                    }
+                    None => {
+                        // First, we get the component or we load it
+                        let component = match components.get(&node.r#use) {
+                            Some(component) => component,
+                            None => {
+                                let dependency = workflow
+                                    .dependencies
+                                    .get(&node.r#use)
+                                    .ok_or(Error::DependencyNotFound(node.r#use.clone()))?;
+                                // Invalid capabilities fail early, denied ones
+                                // when the component calls them
+                                let invalid_capabilities =
+                                    |e| Error::InvalidCapabilities(node.r#use.clone(), e);
+                                state::wasi_builder(&dependency.capabilities)
+                                    .map_err(|e| invalid_capabilities(format!("{e:#}")))?;
+                                outgoing::headers(&dependency.capabilities.http)
+                                    .map_err(invalid_capabilities)?;
+                                check_secrets(&dependency.capabilities, &secret_scope)
+                                    .map_err(invalid_capabilities)?;
+                                capabilities
+                                    .insert(node.r#use.clone(), dependency.capabilities.clone());
+                                let bytes = dependency.source.load().await?;
+                                component_digests.insert(node.r#use.0.clone(), sha256(&bytes));
+                                let component = Component::from_binary(&runtime.engine, &bytes)?;
+                                let denied = state::denied_imports(
+                                    &runtime.engine,
+                                    &component,
+                                    &dependency.capabilities,
+                                );
+                                if !denied.is_empty() {
+                                    tracing::warn!(
+                                        component = %node.r#use.0,
+                                        ?denied,
+                                        "The component imports interfaces its capabilities deny, \
+                                        which trap when called"
+                                    );
+                                }
+                                components.insert(node.r#use.clone(), component);
+                                let limits = match &dependency.limits {
+                                    Some(overrides) => {
+                                        runtime.config.limits.overridden_by(overrides)
+                                    }
+                                    None => runtime.config.limits.clone(),
+                                };
+                                let limiter =
+                                    Limiter::new(limits, &bytes).map_err(wasmtime::Error::from)?;
+                                limiters.insert(node.r#use.clone(), limiter);
+                                components.get(&node.r#use).unwrap()
+                            }
+                        };
+
+                        // Then, we lookup the corresponding function
+                        let run = node
+                            .run
+                            .as_ref()
+                            .ok_or(Error::MissingRun(node_id.clone()))?;
+                        let (item, index) = component.export_index(None, run).ok_or({
+                            Error::MissingFunction(
+                                run.clone(),
+                                component
+                                    .component_type()
+                                    .exports(&runtime.engine)
+                                    .map(|(name, _)| name.to_string())
+                                    .collect(),
+                            )
+                        })?;
+                        let ComponentItem::ComponentFunc(func) = item else {
+                            Err(Error::InvalidNode(node_id.clone()))?
+                        };
+
+                        let params: Vec<_> = func
+                            .params()
+                            .map(|(name, ty)| (InputName(name.to_string()), ty))
+                            .collect();
+                        // Lists of outputs are typed by no component, so left unknown
+                        let output_type = func
+                            .results()
+                            .next()
+                            .map(|ty| node.for_each.is_none().then_some(ty));
+                        let callee = Callee::Component {
+                            component_name: node.r#use.clone(),
+                            index,
+                        };
+                        (callee, params, output_type)
+                    }
+                };
 
-            if let ComponentItem::ComponentFunc(func) = item {
                 // We add the node's function to the graph
                 let node_index = graph.add_node(NodeType::Function(Function {
-                    component_name: node.r#use.clone(),
-                    index,
+                    cache: match (&callee, &node.run) {
+                        (Callee::Component { component_name, .. }, Some(run)) if node.cache => {
+                            Some(Box::new(Caching {
+                                component_name: component_name.clone(),
+                                digest: component_digests[&component_name.0].clone(),
+                                function: run.clone(),
+                                ttl: node.cache_ttl.map(|ttl| *ttl),
+                            }))
+                        }
+                        _ => None,
+                    },
+                    callee,
+                    fuel: node.fuel,
+                    max_log_bytes: node.max_log_bytes,
                     node_id: node_id.clone(),
+                    params: params.iter().map(|(name, _)| name.clone()).collect(),
+                    repeat: None,
+                    retry: node.retry.clone(),
+                    skip_dependents: node.skip_dependents,
+                    timeout: node.timeout.map(|timeout| *timeout),
                     val: None,
                 }));
                 node_indices.insert(node_id, node_index);
+                signatures.push((node_index, node_id, node, params));
+                if let Some(output_type) = output_type {
+                    output_types.insert(node_id.clone(), output_type);
+                }
+            }
+
+            // Then, we parse the templates of the manual inputs
+            let mut arguments = Vec::new();
+            for (node_index, node_id, node, params) in &signatures {
+                for (input_name, ty) in params {
+                    let Some(argument) = node.with.get(input_name) else {
+                        continue;
+                    };
+                    let template = match argument {
+                        Argument::Wave(wave) => Template::parse(wave).map_err(|e| {
+                            Error::InvalidTemplate((*node_id).clone(), input_name.clone(), e)
+                        })?,
+                        Argument::Json(_) => None,
+                    };
+                    arguments.push((*node_index, *node_id, input_name, ty, argument, template));
+                }
+            }
+
+            // Inputs of a builtin type are typed by their declaration, the others
+            // by the parameters they are passed to as a whole
+            let mut input_types = HashMap::new();
+            for (input_name, input) in &workflow.inputs {
+                let ty = wit::builtin_type(&input.r#type, &runtime.engine)
+                    .map_err(|e| Error::InvalidInputType(input_name.clone(), e))?;
+                input_types.insert(input_name.clone(), ty);
+            }
+            for (_, node_id, input_name, ty, _, template) in &arguments {
+                let Some(reference) = template.as_ref().and_then(Template::input) else {
+                    continue;
+                };
+                let input = workflow
+                    .inputs
+                    .get(reference)
+                    .ok_or(Error::UnknownInput(reference.clone()))?;
+                let matches = wit::matches(&input.r#type, ty)
+                    .map_err(|e| Error::InvalidInputType(reference.clone(), e))?;
+                if !matches {
+                    Err(Error::InputTypeMismatch(
+                        reference.clone(),
+                        (*node_id).clone(),
+                        (*input_name).clone(),
+                    ))?;
+                }
+                input_types
+                    .get_mut(reference)
+                    .unwrap()
+                    .get_or_insert_with(|| (*ty).clone());
+            }
 
-                // We add the node's inputs to the graph
-                for (name, ty) in func.params() {
-                    let input_name = InputName(name.to_string());
-                    if let Some(value) = node.with.get(&input_name) {
-                        let val = Val::from_wave(&ty, value)?;
-                        let input_index = graph.add_node(NodeType::Value(val.clone()));
-                        graph.add_edge(node_index, input_index, input_name.clone());
-                    } else {
-                        Err(Error::MissingInput(input_name.clone()))?;
+            let mut scope = Scope {
+                index: None,
+                inputs: input_types
+                    .iter()
+                    .map(|(name, ty)| (name.clone(), ty))
+                    .collect(),
+                item: None,
+                nodes: output_types
+                    .iter()
+                    .map(|(id, ty)| (id.clone(), ty))
+                    .collect(),
+                secrets: secret_scope.secrets.clone(),
+            };
+
+            // We add the lists the nodes run for each element of to the graph,
+            // depending on the nodes they refer to like templates
+            let mut item_types = HashMap::new();
+            for (node_index, node_id, node, _) in &signatures {
+                let Some(for_each) = &node.for_each else {
+                    continue;
+                };
+                let items = Template::parse_expression(&for_each.items)
+                    .and_then(|items| Ok((items.check_items(&scope)?, items)))
+                    .map_err(|e| Error::InvalidForEach((*node_id).clone(), e));
+                let (item_type, items) = items?;
+                item_types.insert(*node_index, item_type);
+                let items_index = add_template(&mut graph, &node_indices, items, |items| {
+                    NodeType::ForEach(items, for_each.concurrency)
+                });
+                graph.add_edge(items_index, *node_index, EdgeKind::ForEach);
+            }
+
+            // We add the node's manual inputs to the graph, templates depending on
+            // the nodes they refer to
+            let index_type = Some(Type::U32);
+            for (node_index, node_id, input_name, ty, argument, template) in arguments {
+                let input_index = match template {
+                    Some(template) => {
+                        scope.index = item_types.contains_key(&node_index).then_some(&index_type);
+                        scope.item = item_types.get(&node_index);
+                        template.check(&scope, ty).map_err(|e| {
+                            Error::InvalidTemplate(node_id.clone(), input_name.clone(), e)
+                        })?;
+                        add_template(&mut graph, &node_indices, template, |template| {
+                            NodeType::Template(template, ty.clone())
+                        })
                     }
+                    None => {
+                        let val = argument_to_val(argument, ty)
+                            .map_err(|e| Error::InvalidArgument(input_name.clone(), e))?;
+                        graph.add_node(NodeType::Value(val))
+                    }
+                };
+                graph.add_edge(input_index, node_index, EdgeKind::Input(input_name.clone()));
+            }
+
+            // We add the nodes' conditions to the graph, depending on the nodes
+            // they refer to like templates
+            scope.index = None;
+            scope.item = None;
+            for (node_index, node_id, node, _) in &signatures {
+                let Some(source) = &node.r#if else {
+                    continue;
+                };
+                let condition = Template::parse_expression(source)
+                    .and_then(|condition| condition.check(&scope, &Type::Bool).map(|()| condition))
+                    .map_err(|e| Error::InvalidCondition((*node_id).clone(), e))?;
+                let condition_index =
+                    add_template(&mut graph, &node_indices, condition, NodeType::Condition);
+                graph.add_edge(condition_index, *node_index, EdgeKind::Condition);
+            }
+
+            // We parse the conditions ending the repetition of the nodes, which
+            // refer to the node's own output and to the workflow's inputs
+            for (node_index, node_id, node, _) in &signatures {
+                let Some(repeat) = &node.repeat else {
+                    continue;
+                };
+                let own_scope = Scope {
+                    index: None,
+                    inputs: scope.inputs.clone(),
+                    item: None,
+                    nodes: scope
+                        .nodes
+                        .get(*node_id)
+                        .map(|ty| ((*node_id).clone(), *ty))
+                        .into_iter()
+                        .collect(),
+                    secrets: scope.secrets.clone(),
+                };
+                let until = Template::parse_expression(&repeat.until)
+                    .and_then(|until| until.check(&own_scope, &Type::Bool).map(|()| until))
+                    .map_err(|e| Error::InvalidRepeat((*node_id).clone(), e))?;
+                let NodeType::Function(function) = &mut graph[*node_index] else {
+                    unreachable!("nodes are functions");
+                };
+                function.repeat = Some(Box::new(Repetition {
+                    delay: repeat.delay.map(|delay| *delay),
+                    max_iterations: repeat.max_iterations,
+                    until,
+                }));
+            }
+
+            // Finally, we connect the nodes' function to each other
+            for edge in &workflow.edges {
+                if let (Some(source_idx), Some(target_idx)) = (
+                    node_indices.get(&edge.source),
+                    node_indices.get(&edge.target),
+                ) {
+                    graph.add_edge(
+                        *source_idx,
+                        *target_idx,
+                        EdgeKind::Input(edge.input.clone()),
+                    );
+                } else {
+                    Err(Error::InvalidEdge(edge.clone()))?;
                 }
-            } else {
-                Err(Error::InvalidNode(node.clone()))?;
             }
-        }
 
-        // Finally, we connect the nodes' function to each other
-        for edge in &workflow.edges {
-            if let (Some(source_idx), Some(target_idx)) = (
-                node_indices.get(&edge.source),
-                node_indices.get(&edge.target),
-            ) {
-                graph.add_edge(*source_idx, *target_idx, edge.input.clone());
-            } else {
-                Err(Error::InvalidEdge(edge.clone()))?;
+            // Every input must be provided, either manually or by an edge
+            for node_index in graph.node_indices() {
+                if let NodeType::Function(function) = &graph[node_index] {
+                    for input_name in &function.params {
+                        if !graph.edges_directed(node_index, Incoming).any(
+                            |edge| matches!(edge.weight(), EdgeKind::Input(name) if name == input_name),
+                        ) {
+                            Err(Error::MissingInput(input_name.clone()))?;
+                        }
+                    }
+                }
+            }
+
+            let graph = Dag::try_from_graph(graph).map_err(Error::Cycle)?;
+
+            let mut inputs = HashMap::new();
+            for (input_name, input) in &workflow.inputs {
+                let ty = input_types
+                    .remove(input_name)
+                    .flatten()
+                    .ok_or(Error::UntypedInput(input_name.clone()))?;
+                let default = match &input.default {
+                    Some(argument) => Some(
+                        argument_to_val(argument, &ty)
+                            .map_err(|e| Error::InvalidArgument(input_name.clone(), e))?,
+                    ),
+                    None => None,
+                };
+                inputs.insert(input_name.clone(), WorkflowInput { default, ty });
+            }
+
+            for (output_name, node_id) in &workflow.outputs {
+                if !node_indices.contains_key(node_id) {
+                    Err(Error::InvalidOutput(output_name.clone(), node_id.clone()))?;
+                }
+            }
+
+            let mut secrets: HashSet<_> = workflow.secrets.iter().cloned().collect();
+            for prototype in workflows.values() {
+                secrets.extend(prototype.secrets.iter().cloned());
+            }
+
+            // Maps of the workflow are ordered once turned into JSON values
+            let mut workflow_digests = BTreeMap::new();
+            for (workflow_name, prototype) in &workflows {
+                for (component_name, digest) in &prototype.digests.components {
+                    let component_name = format!("{}/{component_name}", workflow_name.0);
+                    component_digests.insert(component_name, digest.clone());
+                }
+                workflow_digests.insert(&workflow_name.0, &prototype.digests.workflow);
             }
+            let manifest = serde_json::to_value(workflow).expect("workflows are JSON");
+            let digests = Digests {
+                components: component_digests,
+                workflow: sha256(&serde_json::to_vec(&(manifest, workflow_digests)).unwrap()),
+            };
+
+            Ok(Self {
+                capabilities,
+                components,
+                digests,
+                fuel: workflow.fuel,
+                graph,
+                inputs,
+                limiters,
+                outputs: workflow.outputs.clone(),
+                scope: workflow.name.clone().unwrap_or_default(),
+                secrets,
+                timeout: workflow.timeout.map(|timeout| *timeout),
+            })
+        })
+    }
+
+    /// Loads and compiles a workflow used by a node, failing when it uses
+    /// itself, directly or not.
+    async fn load(
+        runtime: &mut Runtime,
+        source: &FileSource,
+        sources: &mut Vec<String>,
+    ) -> Result<Self, Error> {
+        let name = source.to_string();
+        if sources.contains(&name) {
+            let mut cycle = sources.clone();
+            cycle.push(name);
+            return Err(Error::RecursiveWorkflow(cycle));
+        }
+        let bytes = source.load().await?;
+        let workflow = Workflow::parse(&bytes)?;
+        sources.push(name.clone());
+        let mut prototype = Self::compile(runtime, &workflow, sources).await;
+        sources.pop();
+        if let Ok(prototype) = &mut prototype
+            && workflow.name.is_none()
+        {
+            prototype.scope = name;
+        }
+        prototype
+    }
+}
+
+impl fmt::Debug for Prototype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Prototype")
+            .field("inputs", &self.inputs)
+            .field("outputs", &self.outputs)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Adds the node evaluating a template to the graph, depending on the
+/// functions whose output the template refers to.
+fn add_template(
+    graph: &mut Graph<NodeType, EdgeKind>,
+    node_indices: &HashMap<&NodeId, NodeIndex>,
+    template: Template,
+    node: impl FnOnce(Template) -> NodeType,
+) -> NodeIndex {
+    let references: Vec<_> = template
+        .references()
+        .into_iter()
+        .filter_map(|reference| match reference {
+            Reference::Node(node_id) => Some(node_id.clone()),
+            _ => None,
+        })
+        .collect();
+    let template_index = graph.add_node(node(template));
+    for node_id in references {
+        graph.add_edge(node_indices[&node_id], template_index, EdgeKind::Reference);
+    }
+    template_index
+}
+
+/// Checks the templates of the environment variables and HTTP headers of a
+/// component, which may only refer to secrets.
+fn check_secrets(capabilities: &Capabilities, scope: &Scope<&Option<Type>>) -> Result<(), String> {
+    for (name, value) in capabilities.env.iter().chain(&capabilities.http.headers) {
+        if let Some(template) = Template::parse(value).map_err(|e| format!("{name}: {e}"))? {
+            template
+                .check(scope, &Type::String)
+                .map_err(|e| format!("{name}: {e}"))?;
         }
+    }
+    Ok(())
+}
+
+/// Converts an argument written in a workflow to a value of the given type.
+pub(crate) fn argument_to_val(argument: &Argument, ty: &Type) -> Result<Val, ArgumentError> {
+    match argument {
+        Argument::Json(value) => Ok(json::json_to_val(value, ty)?),
+        Argument::Wave(wave) => Val::from_wave(ty, wave).map_err(ArgumentError::Wave),
+    }
+}
+
+/// A graph of nodes checked to be acyclic.
+///
+/// Unlike `Acyclic`, which keeps the graph's order in a `RefCell`, it can be
+/// shared by the tasks of a `Prototype`.
+#[derive(Clone, Debug)]
+pub(crate) struct Dag(Graph<NodeType, EdgeKind>);
 
-        let graph =
-            Acyclic::try_from_graph(graph).map_err(|cycle| Error::Cycle(cycle.node_id()))?;
+impl Dag {
+    fn try_from_graph(graph: Graph<NodeType, EdgeKind>) -> Result<Self, NodeIndex> {
+        Acyclic::try_from_graph(graph)
+            .map(|graph| Self(graph.into_inner()))
+            .map_err(|cycle| cycle.node_id())
+    }
+}
 
-        Ok(Self { components, graph })
+impl Deref for Dag {
+    type Target = Graph<NodeType, EdgeKind>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
     }
 }
 
+/// What a node provides to the node an edge points to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EdgeKind {
+    /// The condition of a function
+    Condition,
+    /// The list a function runs for each element of
+    ForEach,
+    /// An argument of a function
+    Input(InputName),
+    /// The output of a function a template refers to
+    Reference,
+}
+
 #[derive(Clone, Debug)]
 pub enum NodeType {
+    /// The condition of the function it points to, evaluated once the nodes
+    /// it refers to ran
+    Condition(Template),
+    /// The list the function it points to runs for each element of, with the
+    /// maximum number of elements processed at once
+    ForEach(Template, Option<NonZeroU32>),
     Function(Function),
+    /// A template evaluated into a value of the type of the parameter it is
+    /// passed to, once the nodes it refers to ran
+    Template(Template, Type),
     Value(Val),
 }
 
 impl NodeType {
     pub fn set_val(&mut self, val: Val) {
         match self {
+            NodeType::Condition(_) | NodeType::ForEach(..) => {}
             NodeType::Function(function) => function.val = Some(val),
-            NodeType::Value(val) => *val = val.clone(),
+            NodeType::Template(..) => *self = NodeType::Value(val),
+            NodeType::Value(value) => *value = val,
         }
     }
 }
 
+/// An input of the workflow, typed by the parameters it is passed to.
+#[derive(Clone, Debug)]
+pub(crate) struct WorkflowInput {
+    pub(crate) default: Option<Val>,
+    pub(crate) ty: Type,
+}
+
 #[derive(Clone, Debug)]
 pub struct Function {
-    pub(crate) component_name: ComponentName,
-    pub(crate) index: ComponentExportIndex,
+    pub(crate) cache: Option<Box<Caching>>,
+    pub(crate) callee: Callee,
+    pub(crate) fuel: Option<u64>,
+    /// Number of bytes kept of the stdout and of the stderr of every call,
+    /// replacing the runtime's one
+    pub(crate) max_log_bytes: Option<usize>,
     pub(crate) node_id: NodeId,
+    pub(crate) params: Vec<InputName>,
+    pub(crate) repeat: Option<Box<Repetition>>,
+    pub(crate) retry: Option<Retry>,
+    pub(crate) skip_dependents: bool,
+    pub(crate) timeout: Option<Duration>,
     pub(crate) val: Option<Val>,
 }
 
+/// How a function runs again until its output satisfies a condition.
+#[derive(Clone, Debug)]
+pub(crate) struct Repetition {
+    pub(crate) delay: Option<Duration>,
+    pub(crate) max_iterations: NonZeroU32,
+    /// Refers to the output of the last iteration as the function's output
+    pub(crate) until: Template,
+}
+
+/// What a function node calls.
+#[derive(Clone, Debug)]
+pub(crate) enum Callee {
+    /// A function exported by a component
+    Component {
+        component_name: ComponentName,
+        index: ComponentExportIndex,
+    },
+    /// Another workflow, run as a task of its own taking the node's parameters
+    /// as inputs and returning a record of its outputs
+    Workflow(Arc<Prototype>),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("Both a dependency and a workflow are named {0:?}")]
+    AmbiguousDependency(ComponentName),
+    #[error("Node {0:?} uses a workflow, whose outputs cannot be cached")]
+    CachedWorkflow(NodeId),
     #[error("Cycle detected: {0:?}")]
     Cycle(NodeIndex),
     #[error("Dependency not found: {0:?}")]
     DependencyNotFound(ComponentName),
     #[error("File source error: {0}")]
     FileSource(#[from] file_source::Error),
+    #[error("Fuel budgets require a runtime consuming fuel")]
+    FuelNotEnabled,
+    #[error("Input {0:?} does not match the type of parameter {2:?} of node {1:?}")]
+    InputTypeMismatch(InputName, NodeId, InputName),
+    #[error("Invalid argument {0:?}: {1}")]
+    InvalidArgument(InputName, ArgumentError),
+    #[error("Invalid capabilities of {0:?}: {1}")]
+    InvalidCapabilities(ComponentName, String),
+    #[error("Invalid condition of node {0:?}: {1}")]
+    InvalidCondition(NodeId, String),
     #[error("Invalid edge: {0:?}")]
     InvalidEdge(Edge),
+    #[error("Invalid for_each of node {0:?}: {1}")]
+    InvalidForEach(NodeId, String),
+    #[error("Invalid type of input {0:?}: {1}")]
+    InvalidInputType(InputName, String),
     #[error("Invalid node: {0:?}")]
-    InvalidNode(Node),
+    InvalidNode(NodeId),
+    #[error("Output {0:?} refers to an unknown node: {1:?}")]
+    InvalidOutput(OutputName, NodeId),
+    #[error("Invalid repeat of node {0:?}: {1}")]
+    InvalidRepeat(NodeId, String),
+    #[error("Invalid template of {1:?} of node {0:?}: {2}")]
+    InvalidTemplate(NodeId, InputName, String),
     #[error("Missing function: {0:?}, available: {1:?}")]
     MissingFunction(FunctionName, Vec<String>),
     #[error("Missing input: {0:?}")]
     MissingInput(InputName),
+    #[error("Node {0:?} uses a component without running a function of it")]
+    MissingRun(NodeId),
+    #[error("Invalid workflow: {0}")]
+    ParseWorkflow(#[from] workflow::Error),
+    #[error("Workflow uses itself: {}", .0.join(" -> "))]
+    RecursiveWorkflow(Vec<String>),
+    #[error("Node {0:?} uses a workflow, which has no function to run")]
+    UnexpectedFunction(NodeId),
+    #[error("Unknown input: {0:?}")]
+    UnknownInput(InputName),
+    #[error("Inputs of a type defined by a component must be passed as a whole to a node: {0:?}")]
+    UntypedInput(InputName),
     #[error("Wasmtime error: {0}")]
     Wasmtime(#[from] wasmtime::Error),
+    #[error("In workflow {0:?}: {1}")]
+    Workflow(ComponentName, Box<Error>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArgumentError {
+    #[error(transparent)]
+    Json(#[from] json::Error),
+    #[error("Invalid WAVE value: {0}")]
+    Wave(wasmtime::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        task::{self, Task, TaskStatus},
+        testing,
+    };
+
+    #[tokio::test]
+    async fn test_recursive_workflows_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("recursive-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (ping, pong) = (dir.join("ping.yaml"), dir.join("pong.yaml"));
+        let uses = |other: &std::path::Path| {
+            format!(
+                "
+                dependencies: {{}}
+                edges: []
+                nodes: {{ again: {{ use: other }} }}
+                workflows: {{ other: {} }}
+                ",
+                other.display()
+            )
+        };
+        std::fs::write(&ping, uses(&pong)).unwrap();
+        std::fs::write(&pong, uses(&ping)).unwrap();
+
+        let mut runtime = Runtime::new().unwrap();
+        let mut error = Prototype::new(&mut runtime, &testing::workflow(&uses(&ping)))
+            .await
+            .unwrap_err();
+        // Along the nodes using ping, pong then ping again
+        while let Error::Workflow(_, nested) = error {
+            error = *nested;
+        }
+        let (ping, pong) = (ping.display().to_string(), pong.display().to_string());
+        assert!(matches!(
+            error,
+            Error::RecursiveWorkflow(cycle) if cycle == [ping.clone(), pong, ping]
+        ));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_inputs_are_typed_defaulted_and_required() {
+        let mut runtime = Runtime::new().unwrap();
+        let workflow = testing::workflow(
+            "
+            dependencies: { math: $math }
+            edges: []
+            inputs:
+              base: { type: u32, default: 1 }
+              step: { type: u32 }
+            nodes:
+              first: { run: inc, use: math, with: { x: '${{ inputs.base }}' } }
+              second: { run: inc, use: math, with: { x: '${{ inputs.step }}' } }
+            outputs: { first: first, second: second }
+            ",
+        );
+        let prototype = Prototype::new(&mut runtime, &workflow).await.unwrap();
+        let base = &prototype.inputs[&InputName("base".to_string())];
+        assert!(matches!(base.ty, Type::U32));
+        assert_eq!(base.default, Some(Val::U32(1)));
+
+        // As `--input` parses them, in JSON or WAVE
+        let arguments = |arguments: &[(&str, &str)]| -> HashMap<_, _> {
+            arguments
+                .iter()
+                .map(|(name, value)| (InputName(name.to_string()), value.parse().unwrap()))
+                .collect()
+        };
+        let outputs = |report: task::TaskReport| {
+            let output = |name: &str| report.outputs[&OutputName(name.to_string())].clone();
+            (output("first"), output("second"))
+        };
+        let mut task = Task::new(&mut runtime, &prototype, &arguments(&[("step", "7")]))
+            .await
+            .unwrap();
+        let (report, _) = testing::events(&mut task).await;
+        assert_eq!(report.status, TaskStatus::Succeeded);
+        assert_eq!(outputs(report), (Val::U32(2), Val::U32(8)));
+        let overridden = arguments(&[("base", "41"), ("step", "7")]);
+        let mut task = Task::new(&mut runtime, &prototype, &overridden)
+            .await
+            .unwrap();
+        let (report, _) = testing::events(&mut task).await;
+        assert_eq!(outputs(report), (Val::U32(42), Val::U32(8)));
+
+        let mut task_error = async |arguments: &HashMap<_, _>| {
+            Task::new(&mut runtime, &prototype, arguments)
+                .await
+                .err()
+                .unwrap()
+        };
+        assert!(matches!(
+            task_error(&arguments(&[])).await,
+            task::Error::MissingInput(input_name) if input_name.0 == "step"
+        ));
+        assert!(matches!(
+            task_error(&arguments(&[("step", "\"seven\"")])).await,
+            task::Error::InvalidInput(input_name, _) if input_name.0 == "step"
+        ));
+        assert!(matches!(
+            task_error(&arguments(&[("step", "7"), ("other", "1")])).await,
+            task::Error::UnknownInput(input_name) if input_name.0 == "other"
+        ));
+
+        let mut prototype_error = async |yaml: &str| {
+            Prototype::new(&mut runtime, &testing::workflow(yaml))
+                .await
+                .err()
+                .unwrap()
+        };
+        assert!(matches!(
+            prototype_error(
+                "
+                dependencies: { math: $math }
+                edges: []
+                inputs: { base: { type: string } }
+                nodes: { inc: { run: inc, use: math, with: { x: '${{ inputs.base }}' } } }
+                "
+            )
+            .await,
+            Error::InputTypeMismatch(input_name, node_id, param)
+                if input_name.0 == "base" && node_id.0 == "inc" && param.0 == "x"
+        ));
+        assert!(matches!(
+            prototype_error(
+                "
+                dependencies: { math: $math }
+                edges: []
+                inputs: { base: { type: u32, default: '\"one\"' } }
+                nodes: { inc: { run: inc, use: math, with: { x: '${{ inputs.base }}' } } }
+                "
+            )
+            .await,
+            Error::InvalidArgument(input_name, _) if input_name.0 == "base"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_conditions_and_lists_are_not_inputs() {
+        let mut runtime = Runtime::new().unwrap();
+        let workflow = |node: &str| {
+            testing::workflow(&format!(
+                "
+                dependencies: {{ math: $math }}
+                edges: []
+                nodes:
+                  range: {{ run: range, use: math }}
+                  guard: {{ run: guard, use: math, {node} }}
+                outputs: {{ guard: guard }}
+                "
+            ))
+        };
+        for node in ["if: 'true'", "for_each: { items: nodes.range.output }"] {
+            let error = Prototype::new(&mut runtime, &workflow(node)).await.err();
+            assert!(
+                matches!(&error, Some(Error::MissingInput(input_name)) if input_name.0 == "if"),
+                "{node}: {error:?}"
+            );
+        }
+
+        let workflow = workflow("if: 'true', with: { if: 1 }");
+        let prototype = Prototype::new(&mut runtime, &workflow).await.unwrap();
+        let mut task = Task::new(&mut runtime, &prototype, &HashMap::new())
+            .await
+            .unwrap();
+        let (report, _) = testing::events(&mut task).await;
+        assert_eq!(
+            report.outputs[&OutputName("guard".to_string())],
+            Val::U32(2)
+        );
+    }
 }
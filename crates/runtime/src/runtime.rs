@@ -1,7 +1,17 @@
+use std::{sync::Arc, thread, time::Duration};
+
 pub use wasmtime::Error;
 use wasmtime::{Config, Engine, component::Linker};
+use workflow::Limits;
 
-use crate::state::State;
+use crate::{
+    cache::CacheStore,
+    deterministic::Deterministic,
+    keyvalue::{self, KeyValueStore},
+    logging,
+    secrets::{EnvSecrets, SecretProvider},
+    state::State,
+};
 
 /// The `Runtime` owns the global execution context for workflows.
 ///
@@ -9,22 +19,91 @@ use crate::state::State;
 /// - A single `Engine`, which caches compiled modules and components.
 /// - A shared `Linker`, which registers host functions, capabilities, and
 ///   shared components available to all workflows.
+/// - The providers of the secrets of the workflows, `EnvSecrets` by default.
+/// - The store of the buckets components open through `wasi:keyvalue`.
+/// - The store of the cached outputs of functions.
+///
+/// Guest code is interrupted at every epoch tick, letting the executor run
+/// other nodes and enforce timeouts while a node is busy.
 ///
 /// The `Runtime` can compile multiple `Prototype` instances (static workflows)
 /// and spawn multiple independent `Task` executions from them.
 pub struct Runtime {
+    /// Shared by every task, no output being cached when `None`
+    pub cache: Option<Arc<dyn CacheStore>>,
+    pub config: RuntimeConfig,
     pub engine: Engine,
+    /// Shared by every task, each task keeping its buckets in memory when
+    /// `None`
+    pub keyvalue: Option<Arc<dyn KeyValueStore>>,
     pub linker: Linker<State>,
+    /// Asked in order for every secret, the first one holding it providing its
+    /// value
+    pub secrets: Vec<Box<dyn SecretProvider>>,
 }
 
+/// Settings of a `Runtime`, fixed once its `Engine` is created.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeConfig {
+    /// Meters the instructions executed by guests, which enables fuel budgets
+    /// and reports the fuel consumed by every call
+    pub consume_fuel: bool,
+    /// Makes tasks reproducible, see `deterministic`
+    pub deterministic: Option<Deterministic>,
+    /// Resource limits of every component, unless its dependency replaces them
+    pub limits: Limits,
+    /// Number of bytes kept of the stdout and of the stderr of every call of
+    /// a node not setting its own, `DEFAULT_MAX_LOG_BYTES` by default
+    pub max_log_bytes: Option<usize>,
+}
+
+/// Number of bytes kept of every output of a call, unless configured.
+pub const DEFAULT_MAX_LOG_BYTES: usize = 1024 * 1024;
+
+/// Interval between two epoch increments, and so between two points where a
+/// busy guest yields back to the executor.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
 impl Runtime {
     pub fn new() -> Result<Self, Error> {
+        Self::with_config(RuntimeConfig::default())
+    }
+
+    pub fn with_config(runtime_config: RuntimeConfig) -> Result<Self, Error> {
         let mut config = Config::new();
         config.async_support(true);
+        config.consume_fuel(runtime_config.consume_fuel);
+        config.epoch_interruption(true);
         let engine = Engine::new(&config)?;
+        Self::spawn_epoch_ticker(&engine)?;
         let mut linker = Linker::<State>::new(&engine);
         wasmtime_wasi::add_to_linker_async(&mut linker)?;
         wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
-        Ok(Self { engine, linker })
+        keyvalue::add_to_linker(&mut linker)?;
+        logging::add_to_linker(&mut linker)?;
+        Ok(Self {
+            cache: None,
+            config: runtime_config,
+            engine,
+            keyvalue: None,
+            linker,
+            secrets: vec![Box::new(EnvSecrets::default())],
+        })
+    }
+
+    /// Increments the engine's epoch every `EPOCH_TICK` until the engine is
+    /// dropped.
+    fn spawn_epoch_ticker(engine: &Engine) -> Result<(), Error> {
+        let engine = engine.weak();
+        thread::Builder::new()
+            .name("epoch-ticker".to_string())
+            .spawn(move || {
+                while let Some(engine) = engine.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    thread::sleep(EPOCH_TICK);
+                }
+            })?;
+        Ok(())
     }
 }
@@ -0,0 +1,284 @@
+//! Values a workflow refers to as `${{ secrets.<name> }}` without holding
+//! them, such as API keys.
+//!
+//! The `Runtime` asks its `SecretProvider`s for the secrets of a workflow when
+//! a `Task` is created, the first provider holding a secret providing its
+//! value. A secret is then injected wherever the workflow refers to it: in the
+//! `with` values of nodes, and in the environment variables and HTTP headers
+//! of components. The values of the secrets are replaced by `***` in the
+//! events and the report of the task.
+
+use std::{collections::HashMap, env, num::NonZeroU32, path::Path};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures::future::BoxFuture;
+use ring::{
+    aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use wasmtime::component::Val;
+use workflow::SecretName;
+
+/// What redacted secrets are replaced by.
+const REDACTED: &str = "***";
+
+/// Iterations of PBKDF2 deriving the key of a new `EncryptedFile`.
+const KEY_ITERATIONS: NonZeroU32 = NonZeroU32::new(600_000).unwrap();
+
+/// Provides the values of secrets.
+pub trait SecretProvider: Send + Sync {
+    /// Looks a secret up, `None` when the provider does not hold it.
+    fn get<'a>(&'a self, name: &'a SecretName) -> BoxFuture<'a, Result<Option<String>, Error>>;
+}
+
+/// Secrets held by environment variables, named after the secret prefixed by
+/// `prefix`, in uppercase with dashes replaced by underscores: the secret
+/// `api-key` is read from `SECRET_API_KEY` by default.
+#[derive(Clone, Debug)]
+pub struct EnvSecrets {
+    pub prefix: String,
+}
+
+impl Default for EnvSecrets {
+    fn default() -> Self {
+        Self {
+            prefix: "SECRET_".to_string(),
+        }
+    }
+}
+
+impl EnvSecrets {
+    pub fn var_name(&self, name: &SecretName) -> String {
+        format!("{}{}", self.prefix, name.0.to_uppercase().replace('-', "_"))
+    }
+}
+
+impl SecretProvider for EnvSecrets {
+    fn get<'a>(&'a self, name: &'a SecretName) -> BoxFuture<'a, Result<Option<String>, Error>> {
+        let value = match env::var(self.var_name(name)) {
+            Ok(value) => Ok(Some(value)),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(env::VarError::NotUnicode(_)) => Err(Error::InvalidValue(name.clone())),
+        };
+        Box::pin(async { value })
+    }
+}
+
+/// Secrets held by a JSON file encrypted with a passphrase, the key being
+/// derived from it with PBKDF2-HMAC-SHA256 and the secrets sealed with
+/// ChaCha20-Poly1305.
+#[derive(Clone)]
+pub struct EncryptedFile {
+    secrets: HashMap<SecretName, String>,
+}
+
+/// Content of an `EncryptedFile`, binary fields being encoded in base64.
+#[derive(Deserialize, Serialize)]
+struct Sealed {
+    ciphertext: String,
+    iterations: NonZeroU32,
+    nonce: String,
+    salt: String,
+}
+
+impl EncryptedFile {
+    /// Reads and decrypts a file written by `EncryptedFile::encrypt`.
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, Error> {
+        let sealed: Sealed = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let decode = |field: &str| STANDARD.decode(field).map_err(|_| Error::Decrypt);
+        let salt = decode(&sealed.salt)?;
+        let nonce = Nonce::try_assume_unique_for_key(&decode(&sealed.nonce)?)
+            .map_err(|_| Error::Decrypt)?;
+        let mut ciphertext = decode(&sealed.ciphertext)?;
+        let plaintext = key(passphrase, &salt, sealed.iterations)
+            .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+            .map_err(|_| Error::Decrypt)?;
+        Ok(Self {
+            secrets: serde_json::from_slice(plaintext)?,
+        })
+    }
+
+    /// Encrypts secrets with a passphrase, returning the content of a file
+    /// `EncryptedFile::open` reads.
+    pub fn encrypt(
+        secrets: &HashMap<SecretName, String>,
+        passphrase: &str,
+    ) -> Result<String, Error> {
+        let random = SystemRandom::new();
+        let mut salt = [0; 16];
+        let mut nonce = [0; NONCE_LEN];
+        random.fill(&mut salt).map_err(|_| Error::Encrypt)?;
+        random.fill(&mut nonce).map_err(|_| Error::Encrypt)?;
+        let mut ciphertext = serde_json::to_vec(secrets)?;
+        key(passphrase, &salt, KEY_ITERATIONS)
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut ciphertext,
+            )
+            .map_err(|_| Error::Encrypt)?;
+        let sealed = Sealed {
+            ciphertext: STANDARD.encode(ciphertext),
+            iterations: KEY_ITERATIONS,
+            nonce: STANDARD.encode(nonce),
+            salt: STANDARD.encode(salt),
+        };
+        Ok(serde_json::to_string_pretty(&sealed)?)
+    }
+}
+
+impl SecretProvider for EncryptedFile {
+    fn get<'a>(&'a self, name: &'a SecretName) -> BoxFuture<'a, Result<Option<String>, Error>> {
+        Box::pin(async { Ok(self.secrets.get(name).cloned()) })
+    }
+}
+
+fn key(passphrase: &str, salt: &[u8], iterations: NonZeroU32) -> LessSafeKey {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap())
+}
+
+/// Replaces the values of secrets in what a task shows.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Redactor {
+    /// Longest first, so that a secret containing another is redacted whole
+    values: Vec<String>,
+}
+
+impl Redactor {
+    pub(crate) fn new<'a>(values: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut values: Vec<_> = values
+            .into_iter()
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect();
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        values.dedup();
+        Self { values }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub(crate) fn redact(&self, text: &str) -> String {
+        self.values.iter().fold(text.to_string(), |text, value| {
+            text.replace(value, REDACTED)
+        })
+    }
+
+    /// Redacts the strings of a value, however deep.
+    pub(crate) fn redact_val(&self, val: &Val) -> Val {
+        let redact =
+            |val: &Option<Box<Val>>| val.as_ref().map(|val| Box::new(self.redact_val(val)));
+        match val {
+            Val::String(text) => Val::String(self.redact(text)),
+            Val::List(vals) => Val::List(vals.iter().map(|val| self.redact_val(val)).collect()),
+            Val::Tuple(vals) => Val::Tuple(vals.iter().map(|val| self.redact_val(val)).collect()),
+            Val::Record(fields) => Val::Record(
+                fields
+                    .iter()
+                    .map(|(name, val)| (name.clone(), self.redact_val(val)))
+                    .collect(),
+            ),
+            Val::Variant(case, payload) => Val::Variant(case.clone(), redact(payload)),
+            Val::Option(val) => Val::Option(redact(val)),
+            Val::Result(Ok(val)) => Val::Result(Ok(redact(val))),
+            Val::Result(Err(val)) => Val::Result(Err(redact(val))),
+            val => val.clone(),
+        }
+    }
+
+    /// Redacts the strings of a JSON value, however deep.
+    pub(crate) fn redact_json(&self, json: &serde_json::Value) -> serde_json::Value {
+        match json {
+            serde_json::Value::String(text) => serde_json::Value::String(self.redact(text)),
+            serde_json::Value::Array(jsons) => {
+                serde_json::Value::Array(jsons.iter().map(|json| self.redact_json(json)).collect())
+            }
+            serde_json::Value::Object(fields) => serde_json::Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, json)| (name.clone(), self.redact_json(json)))
+                    .collect(),
+            ),
+            json => json.clone(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Cannot decrypt the secrets: wrong passphrase or corrupted file")]
+    Decrypt,
+    #[error("Cannot encrypt the secrets")]
+    Encrypt,
+    #[error(transparent)]
+    File(#[from] std::io::Error),
+    #[error("The value of secret {0:?} is not valid UTF-8")]
+    InvalidValue(SecretName),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Provider(Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_open_encrypted_file() {
+        let name = SecretName("api-key".to_string());
+        let secrets = HashMap::from([(name.clone(), "s3cr3t".to_string())]);
+        let path = env::temp_dir().join(format!("secrets-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            EncryptedFile::encrypt(&secrets, "passphrase").unwrap(),
+        )
+        .unwrap();
+
+        let file = EncryptedFile::open(&path, "passphrase").unwrap();
+        assert_eq!(file.get(&name).await.unwrap().as_deref(), Some("s3cr3t"));
+        assert!(matches!(
+            EncryptedFile::open(&path, "wrong"),
+            Err(Error::Decrypt)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_redact_nested_values() {
+        let redactor = Redactor::new(["abc", "abcdef", ""]);
+        let val = Val::Record(vec![
+            ("key".to_string(), Val::String("key=abcdef;".to_string())),
+            (
+                "keys".to_string(),
+                Val::List(vec![Val::String("abc".to_string()), Val::U32(7)]),
+            ),
+        ]);
+        assert_eq!(
+            redactor.redact_val(&val),
+            Val::Record(vec![
+                ("key".to_string(), Val::String("key=***;".to_string())),
+                (
+                    "keys".to_string(),
+                    Val::List(vec![Val::String("***".to_string()), Val::U32(7)]),
+                ),
+            ])
+        );
+        assert_eq!(
+            redactor.redact_json(&serde_json::json!({ "keys": ["abc", 7], "key": null })),
+            serde_json::json!({ "keys": ["***", 7], "key": null })
+        );
+    }
+}
@@ -1,27 +1,185 @@
-use wasmtime::component::ResourceTable;
-use wasmtime_wasi::{IoView, WasiCtx, WasiCtxBuilder, WasiView};
-use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
+use std::{borrow::Cow, sync::Arc};
+
+use serde::Serialize;
+use wasmparser::{BinaryReaderError, Instance, Parser, Payload};
+use wasmtime::{
+    DEFAULT_INSTANCE_LIMIT, Engine, ResourceLimiter,
+    component::{Component, Linker, ResourceTable},
+};
+use wasmtime_wasi::{DirPerms, FilePerms, IoView, WasiCtx, WasiCtxBuilder, WasiView};
+use wasmtime_wasi_http::{
+    HttpResult, WasiHttpCtx, WasiHttpView,
+    body::HyperOutgoingBody,
+    types::{HostFutureIncomingResponse, OutgoingRequestConfig, default_send_request},
+};
+use workflow::{Capabilities, DirMode, HttpPolicy, Limits};
+
+use crate::{
+    deterministic::Determinism, keyvalue::KeyValue, logging::Logger, outgoing, secrets::Redactor,
+    trace::CallTrace,
+};
 
 pub struct State {
     ctx: WasiCtx,
-    table: ResourceTable,
+    /// Set in deterministic mode, answering HTTP requests with fixtures
+    determinism: Option<Arc<Determinism>>,
     http: WasiHttpCtx,
+    http_policy: HttpPolicy,
+    pub(crate) keyvalue: KeyValue,
+    pub(crate) limiter: Limiter,
+    pub(crate) logger: Logger,
+    /// Hides the secrets from what the host logs
+    pub(crate) redactor: Arc<Redactor>,
+    pub(crate) table: ResourceTable,
+    /// Set when the task records a trace, replaying a recorded call
+    trace: Option<CallTrace>,
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self::new()
+impl State {
+    /// Fails when one of the component's directories cannot be opened.
+    pub(crate) fn new(
+        limiter: Limiter,
+        capabilities: &Capabilities,
+        redactor: Arc<Redactor>,
+        keyvalue: KeyValue,
+        logger: Logger,
+        determinism: Option<Arc<Determinism>>,
+        trace: Option<CallTrace>,
+    ) -> wasmtime::Result<Self> {
+        let mut builder = wasi_builder(capabilities)?;
+        if !capabilities.inherit_stdio {
+            builder
+                .stdout(logger.stdout.clone())
+                .stderr(logger.stderr.clone());
+        }
+        match (&trace, &determinism) {
+            (Some(trace), determinism) => trace.sandbox(
+                &mut builder,
+                capabilities,
+                determinism.as_deref(),
+                &logger.nested_id,
+                logger.index,
+            ),
+            (None, Some(determinism)) => {
+                determinism.sandbox(&mut builder, capabilities, &logger.nested_id, logger.index)
+            }
+            (None, None) => {}
+        }
+        Ok(Self {
+            ctx: builder.build(),
+            determinism,
+            http: WasiHttpCtx::new(),
+            http_policy: capabilities.http.clone(),
+            keyvalue,
+            limiter,
+            logger,
+            redactor,
+            table: ResourceTable::new(),
+            trace,
+        })
     }
 }
 
-impl State {
-    pub fn new() -> Self {
-        Self {
-            ctx: WasiCtxBuilder::new().build(),
-            table: ResourceTable::new(),
-            http: WasiHttpCtx::new(),
+/// Prepares the WASI context of a component, granting it its capabilities
+/// only.
+pub(crate) fn wasi_builder(capabilities: &Capabilities) -> wasmtime::Result<WasiCtxBuilder> {
+    let mut builder = WasiCtxBuilder::new();
+    builder.args(&capabilities.args);
+    for (key, value) in &capabilities.env {
+        builder.env(key, value);
+    }
+    for dir in &capabilities.dirs {
+        let (dir_perms, file_perms) = match dir.mode {
+            DirMode::ReadOnly => (DirPerms::READ, FilePerms::READ),
+            DirMode::ReadWrite => (DirPerms::all(), FilePerms::all()),
+        };
+        builder
+            .preopened_dir(&dir.host, &dir.guest, dir_perms, file_perms)
+            .map_err(|e| e.context(format!("Cannot open directory {:?}", dir.host)))?;
+    }
+    if capabilities.inherit_stdio {
+        builder.inherit_stdio();
+    }
+    Ok(builder)
+}
+
+/// Interfaces of the clocks, with their functions.
+const CLOCKS: &[(&str, &[&str])] = &[
+    (
+        "wasi:clocks/monotonic-clock@0.2.3",
+        &[
+            "now",
+            "resolution",
+            "subscribe-duration",
+            "subscribe-instant",
+        ],
+    ),
+    ("wasi:clocks/wall-clock@0.2.3", &["now", "resolution"]),
+];
+
+/// Interfaces of the random generators, with their functions.
+const RANDOM: &[(&str, &[&str])] = &[
+    (
+        "wasi:random/insecure@0.2.3",
+        &["get-insecure-random-bytes", "get-insecure-random-u64"],
+    ),
+    ("wasi:random/insecure-seed@0.2.3", &["insecure-seed"]),
+    (
+        "wasi:random/random@0.2.3",
+        &["get-random-bytes", "get-random-u64"],
+    ),
+];
+
+/// Returns the interfaces the capabilities deny, with their functions.
+fn denied(
+    capabilities: &Capabilities,
+) -> impl Iterator<Item = &'static (&'static str, &'static [&'static str])> + use<> {
+    let clocks = (!capabilities.clocks).then_some(CLOCKS);
+    let random = (!capabilities.random).then_some(RANDOM);
+    clocks.into_iter().chain(random).flatten()
+}
+
+/// Returns the linker of a component, in which the functions of the
+/// interfaces its capabilities deny trap.
+pub(crate) fn linker<'a>(
+    linker: &'a Linker<State>,
+    capabilities: &Capabilities,
+) -> wasmtime::Result<Cow<'a, Linker<State>>> {
+    if denied(capabilities).next().is_none() {
+        return Ok(Cow::Borrowed(linker));
+    }
+    let mut linker = linker.clone();
+    linker.allow_shadowing(true);
+    for (interface, functions) in denied(capabilities) {
+        let mut instance = linker.instance(interface)?;
+        for function in *functions {
+            instance.func_new(function, move |_, _, _| {
+                Err(wasmtime::Error::msg(format!(
+                    "{interface}#{function} is denied by the capabilities"
+                )))
+            })?;
         }
     }
+    Ok(Cow::Owned(linker))
+}
+
+/// Returns the interfaces the component imports although its capabilities
+/// deny them, which trap when called.
+pub(crate) fn denied_imports(
+    engine: &Engine,
+    component: &Component,
+    capabilities: &Capabilities,
+) -> Vec<String> {
+    component
+        .component_type()
+        .imports(engine)
+        .map(|(name, _)| name)
+        .filter(|name| {
+            denied(capabilities)
+                .any(|(interface, _)| interface.split('@').next() == name.split('@').next())
+        })
+        .map(str::to_string)
+        .collect()
 }
 
 impl IoView for State {
@@ -40,4 +198,246 @@ impl WasiHttpView for State {
     fn ctx(&mut self) -> &mut WasiHttpCtx {
         &mut self.http
     }
+
+    /// Sends the request only when the component's `HttpPolicy` allows it, to
+    /// its fixture in deterministic mode, and records the exchange when the
+    /// task records a trace.
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        let (request, config) =
+            outgoing::prepare(&self.http_policy, &self.redactor, request, config)?;
+        if let Some(trace) = &self.trace {
+            return Ok(trace.send_request(request, config, self.determinism.as_deref()));
+        }
+        match &self.determinism {
+            Some(determinism) => {
+                let shown_uri = self.redactor.redact(&request.uri().to_string());
+                Ok(determinism.send_request(request, shown_uri, config.between_bytes_timeout))
+            }
+            None => Ok(default_send_request(request, config)),
+        }
+    }
+}
+
+/// Enforces `Limits` on the instances, memories and tables of a store.
+///
+/// Growing a memory or a table beyond its limit traps with a `LimitExceeded`
+/// error instead of letting the guest handle the failure, and so does
+/// instantiating a component creating more instances than its limit.
+#[derive(Clone, Debug)]
+pub(crate) struct Limiter {
+    /// Number of core instances the component creates
+    core_instances: usize,
+    limits: Limits,
+}
+
+impl Limiter {
+    /// Fails when the component's bytes cannot be parsed.
+    pub(crate) fn new(limits: Limits, component: &[u8]) -> Result<Self, BinaryReaderError> {
+        Ok(Self {
+            core_instances: core_instances(component)?,
+            limits,
+        })
+    }
+
+    /// Checks that instantiating the component stays within the instance
+    /// limit, before Wasmtime enforces it with an untyped error.
+    pub(crate) fn instantiating(&self) -> Result<(), LimitExceeded> {
+        match self.instances() {
+            limit if self.core_instances > limit => Err(LimitExceeded::Instances(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Tells whether the error was raised by one of the limits.
+    pub(crate) fn limit_exceeded(&self, error: &wasmtime::Error) -> Option<LimitExceeded> {
+        error.downcast_ref::<LimitExceeded>().cloned()
+    }
+}
+
+/// Counts the core instances a component creates, those of the components it
+/// nests included, each of them being assumed to be instantiated once.
+fn core_instances(component: &[u8]) -> Result<usize, BinaryReaderError> {
+    let mut instances = 0;
+    for payload in Parser::new(0).parse_all(component) {
+        if let Payload::InstanceSection(section) = payload? {
+            for instance in section {
+                if let Instance::Instantiate { .. } = instance? {
+                    instances += 1;
+                }
+            }
+        }
+    }
+    Ok(instances)
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        match self.limits.memory_bytes {
+            Some(limit) if desired > limit => Err(LimitExceeded::MemoryBytes {
+                limit,
+                requested: desired,
+            }
+            .into()),
+            _ => Ok(true),
+        }
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        match self.limits.table_elements {
+            Some(limit) if desired > limit => Err(LimitExceeded::TableElements {
+                limit,
+                requested: desired,
+            }
+            .into()),
+            _ => Ok(true),
+        }
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances.unwrap_or(DEFAULT_INSTANCE_LIMIT)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, thiserror::Error)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitExceeded {
+    #[error("Instance limit of {0} exceeded")]
+    Instances(usize),
+    #[error("Memory limit of {limit} bytes exceeded, {requested} bytes requested")]
+    MemoryBytes { limit: usize, requested: usize },
+    #[error("Table limit of {limit} elements exceeded, {requested} elements requested")]
+    TableElements { limit: usize, requested: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use workflow::{ComponentName, NodeId};
+
+    use crate::{
+        Runtime,
+        prototype::Prototype,
+        task::{Event, NodeStatus},
+        testing,
+    };
+
+    #[tokio::test]
+    async fn test_limits_are_reported_typed() {
+        let three = testing::component(
+            r#"(component
+              (core module $empty)
+              (core instance (instantiate $empty))
+              (core instance (instantiate $empty))
+              (core module $m (func (export "one") (result i32) i32.const 1))
+              (core instance $i (instantiate $m))
+              (func (export "one") (result u32) (canon lift (core func $i "one")))
+            )"#,
+        );
+        let yaml = format!(
+            "
+            dependencies:
+              math: {{ source: $math, limits: {{ memory_bytes: 131072 }} }}
+              three: {{ source: {three}, limits: {{ instances: 2 }} }}
+            edges: []
+            nodes:
+              grow: {{ run: grow, use: math, with: {{ pages: 2 }} }}
+              one: {{ run: one, use: three }}
+            "
+        );
+        let mut runtime = Runtime::new().unwrap();
+        let (report, events) = testing::run(&mut runtime, &yaml).await;
+        let limit_exceeded = |id: &str| {
+            events.iter().find_map(|event| match event {
+                Event::ExecutionLimitExceeded {
+                    limit_exceeded,
+                    node_id,
+                    ..
+                } if node_id.0 == id => Some(limit_exceeded.to_string()),
+                _ => None,
+            })
+        };
+        assert_eq!(
+            limit_exceeded("grow").unwrap(),
+            LimitExceeded::MemoryBytes {
+                limit: 131072,
+                requested: 196608
+            }
+            .to_string()
+        );
+        assert_eq!(
+            limit_exceeded("one").unwrap(),
+            LimitExceeded::Instances(2).to_string()
+        );
+        let node = &report.nodes[&NodeId("one".to_string())];
+        assert_eq!(node.status, NodeStatus::LimitExceeded);
+
+        let limiter = Limiter::new(Limits::default(), &wat::parse_str(testing::MATH).unwrap());
+        assert_eq!(limiter.unwrap().core_instances, 1);
+    }
+
+    #[tokio::test]
+    async fn test_denied_interfaces_trap() {
+        let clock = testing::component(
+            r#"(component
+              (import "wasi:clocks/monotonic-clock@0.2.0"
+                (instance $clock (export "now" (func (result u64)))))
+              (core func $now (canon lower (func $clock "now")))
+              (core module $m
+                (import "clock" "now" (func $now (result i64)))
+                (func (export "now") (result i64) call $now))
+              (core instance $i
+                (instantiate $m (with "clock" (instance (export "now" (func $now))))))
+              (func (export "now") (result u64) (canon lift (core func $i "now")))
+            )"#,
+        );
+        let yaml = format!(
+            "
+            dependencies:
+              granted: {clock}
+              denied: {{ source: {clock}, capabilities: {{ clocks: false }} }}
+            edges: []
+            nodes:
+              granted: {{ run: now, use: granted }}
+              denied: {{ run: now, use: denied }}
+            "
+        );
+        let mut runtime = Runtime::new().unwrap();
+        let prototype = Prototype::new(&mut runtime, &testing::workflow(&yaml))
+            .await
+            .unwrap();
+        let denied = |name: &str| {
+            let component_name = ComponentName(name.to_string());
+            denied_imports(
+                &runtime.engine,
+                &prototype.components[&component_name],
+                &prototype.capabilities[&component_name],
+            )
+        };
+        assert!(denied("granted").is_empty());
+        assert_eq!(denied("denied"), ["wasi:clocks/monotonic-clock@0.2.0"]);
+
+        let (report, events) = testing::run(&mut runtime, &yaml).await;
+        let node = |id: &str| report.nodes[&NodeId(id.to_string())].status;
+        assert_eq!(node("granted"), NodeStatus::Succeeded);
+        assert_eq!(node("denied"), NodeStatus::Failed);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::ExecutionFailed { error, node_id, .. } if node_id.0 == "denied"
+                && error.contains("wasi:clocks/monotonic-clock@0.2.3#now is denied")
+        )));
+    }
 }
@@ -1,146 +1,2403 @@
-use std::collections::HashMap;
+mod backoff;
+mod clock;
+mod event;
+mod fuel;
+mod report;
 
-use petgraph::{Graph, algo::toposort, graph::NodeIndex, visit::EdgeRef};
-use tokio::sync::broadcast::{Receiver, Sender, channel};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::{StreamExt, stream::FuturesUnordered};
+use petgraph::{Graph, Incoming, Outgoing, graph::NodeIndex, visit::EdgeRef};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 use wasmtime::{
-    Result, Store,
-    component::{Instance, Val},
+    Engine, Result, Store, Trap,
+    component::{ComponentExportIndex, InstancePre, Type, Val},
+};
+use workflow::{
+    Argument, Capabilities, ComponentName, InputName, NodeId, OutputName, RetryOn, SecretName,
 };
-use workflow::{ComponentName, InputName, NodeId};
 
+use self::clock::Clock;
+pub use self::{event::*, report::*};
+pub use crate::state::LimitExceeded;
 use crate::{
-    prototype::{NodeType, Prototype},
+    DEFAULT_MAX_LOG_BYTES,
+    cache::Cache,
+    capture::Capture,
+    checkpoint::{Checkpoint, CheckpointStore, Checkpointer, Digests},
+    deterministic::Determinism,
+    keyvalue::{KeyValue, MemoryStore},
+    logging::Logger,
+    prototype::{ArgumentError, Callee, EdgeKind, Function, NodeType, Prototype, argument_to_val},
     runtime::Runtime,
-    state::State,
+    secrets::{self, Redactor},
+    state::{self, Limiter, State},
+    template::{self, Scope, Template},
+    trace::{self, Recorder, Replayer, Trace, TracedVal},
 };
 
 /// A `Task` represents a single, isolated execution of a workflow prototype.
 ///
 /// It holds:
-/// - A new `Store`, providing isolated memory, globals, tables, and state.
-/// - An `Instance` of a `Prototype` within that store.
+/// - An `InstancePre` of every component of the `Prototype`, and of the
+///   workflows its nodes use, already linked against the `Runtime`'s `Linker`.
+/// - The resource limits and the WASI capabilities of every component, with
+///   the values of the secrets they refer to.
+/// - The store of the buckets its components open, in memory unless the
+///   `Runtime` shares one.
+/// - A copy of the prototype's graph, filled with outputs as nodes run.
+///
+/// Every call of a node (and every retry of it) runs in a new `Store` with a
+/// fresh instance of its component, providing isolated memory, globals, tables
+/// and state. A trapped instance is therefore never reused, and nodes whose
+/// inputs are ready run concurrently. Each `Task` runs independently from
+/// others, even if derived from the same `Prototype`. Any shared state must be
+/// managed externally via host functions or global services.
+///
+/// A node using another workflow runs it as a child task, sharing the events,
+/// the cancellation, the fuel budget, the secrets and the key-value store of
+/// its parent, its buckets being scoped by its own workflow.
 ///
-/// Each `Task` runs independently from others, even if derived from the same
-/// `Prototype`. They may share capabilities via the `Program`'s `Linker`, but
-/// do not share memory or instances directly. Any shared state must be managed
-/// externally via host functions or global services.
+/// The values of the secrets are redacted from the events and the report.
+///
+/// In deterministic mode, a task emits the same events every time it runs, as
+/// described in `deterministic`. A task may also record a `Trace` of its run,
+/// or replay one, and save checkpoints to be resumed, as described in
+/// `checkpoint`.
+///
+/// The functions of nodes with `cache: true` reuse the outputs of previous
+/// calls with the same parameters, as described in `cache`, unless the task
+/// runs in deterministic mode or records a trace, every call then running.
 pub struct Task {
-    sender: Sender<Event>,
-    graph: Graph<NodeType, InputName>,
-    instances: HashMap<ComponentName, Instance>,
-    store: Store<State>,
+    /// Only set when the runtime caches outputs, outside of deterministic mode
+    /// and when the task records no trace
+    cache: Option<Arc<Cache>>,
+    cancellation: CancellationToken,
+    capabilities: HashMap<ComponentName, Capabilities>,
+    /// Only set when the task saves checkpoints
+    checkpointer: Option<Checkpointer>,
+    clock: Clock,
+    /// Only set in deterministic mode
+    determinism: Option<Arc<Determinism>>,
+    digests: Digests,
+    engine: Engine,
+    events: EventLog,
+    fuel: Option<fuel::Tank>,
+    graph: Graph<NodeType, EdgeKind>,
+    id: TaskId,
+    inputs: HashMap<InputName, Val>,
+    instances: Instances,
+    keyvalue: KeyValue,
+    limiters: HashMap<ComponentName, Limiter>,
+    max_log_bytes: usize,
+    outputs: HashMap<OutputName, NodeId>,
+    /// Only set for the task of a node using another workflow
+    parent: Option<Parent>,
+    /// Only set when the task records a trace
+    recorder: Option<Arc<Recorder>>,
+    redactor: Arc<Redactor>,
+    /// Only set when the task replays a trace
+    replayer: Option<Arc<Replayer>>,
+    secrets: Arc<HashMap<SecretName, Val>>,
+    started_at: Instant,
+    timeout: Option<Duration>,
 }
 
 impl Task {
-    pub async fn new(runtime: &mut Runtime, prototype: &Prototype) -> Result<Self, Error> {
-        let mut store = Store::new(&runtime.engine, State::new());
+    /// Creates a task of the prototype, binding the workflow's inputs to the
+    /// given arguments or to their default value, and its secrets to the
+    /// values the runtime's providers hold.
+    pub async fn new(
+        runtime: &mut Runtime,
+        prototype: &Prototype,
+        arguments: &HashMap<InputName, Argument>,
+    ) -> Result<Self, Error> {
+        let mut inputs = HashMap::new();
+        for (input_name, argument) in arguments {
+            let input = prototype
+                .inputs
+                .get(input_name)
+                .ok_or(Error::UnknownInput(input_name.clone()))?;
+            let val = argument_to_val(argument, &input.ty)
+                .map_err(|e| Error::InvalidInput(input_name.clone(), e))?;
+            inputs.insert(input_name.clone(), val);
+        }
+        let inputs = with_defaults(prototype, inputs)?;
+        let instances = Instances::new(runtime, prototype)?;
 
-        let mut instances = HashMap::new();
-        for (component_name, component) in &prototype.components {
-            let instance = runtime
-                .linker
-                .instantiate_async(&mut store, component)
-                .await?;
-            instances.insert(component_name.clone(), instance);
+        let mut values = HashMap::new();
+        for secret_name in &prototype.secrets {
+            let mut value = None;
+            for provider in &runtime.secrets {
+                value = provider.get(secret_name).await?;
+                if value.is_some() {
+                    break;
+                }
+            }
+            let value = value.ok_or(Error::MissingSecret(secret_name.clone()))?;
+            values.insert(secret_name.clone(), value);
         }
+        let redactor = Arc::new(Redactor::new(values.values().map(String::as_str)));
+        let secrets: HashMap<_, _> = values
+            .into_iter()
+            .map(|(secret_name, value)| (secret_name, Val::String(value)))
+            .collect();
 
-        let (sender, _) = channel(32);
+        // Without a task budget, the tank only meters the fuel consumed
+        let fuel = runtime
+            .config
+            .consume_fuel
+            .then(|| fuel::Tank::new(prototype.fuel.unwrap_or(u64::MAX)));
+
+        let determinism = runtime.config.deterministic.clone().map(Determinism::new);
+        let clock = Clock::new(determinism.is_some());
 
         Ok(Self {
-            sender,
-            graph: prototype.graph.inner().clone(),
+            cache: runtime
+                .cache
+                .as_ref()
+                .filter(|_| determinism.is_none())
+                .map(|store| Arc::new(Cache::new(Arc::clone(&redactor), Arc::clone(store)))),
+            cancellation: CancellationToken::new(),
+            capabilities: reveal(&prototype.capabilities, &secrets),
+            checkpointer: None,
+            clock: clock.clone(),
+            determinism: determinism.map(Arc::new),
+            digests: prototype.digests.clone(),
+            engine: runtime.engine.clone(),
+            events: EventLog::default(),
+            fuel,
+            graph: Graph::clone(&prototype.graph),
+            id: TaskId(Uuid::new_v4().to_string()),
+            inputs,
             instances,
-            store,
+            keyvalue: KeyValue {
+                scope: prototype.scope.clone(),
+                store: match &runtime.keyvalue {
+                    Some(store) => Arc::clone(store),
+                    None => Arc::new(MemoryStore::default()),
+                },
+            },
+            limiters: prototype.limiters.clone(),
+            max_log_bytes: runtime
+                .config
+                .max_log_bytes
+                .unwrap_or(DEFAULT_MAX_LOG_BYTES),
+            outputs: prototype.outputs.clone(),
+            parent: None,
+            recorder: None,
+            redactor,
+            replayer: None,
+            secrets: Arc::new(secrets),
+            started_at: clock.now(),
+            timeout: prototype.timeout,
         })
     }
 
-    pub fn subscribe(&self) -> Receiver<Event> {
-        self.sender.subscribe()
+    /// Rebuilds the task of a checkpoint, with the same ID and inputs, its
+    /// nodes that succeeded keeping their outputs instead of running again.
+    ///
+    /// Fails when the workflow or one of its components changed since.
+    pub async fn resume(
+        runtime: &mut Runtime,
+        prototype: &Prototype,
+        checkpoint: &Checkpoint,
+    ) -> Result<Self, Error> {
+        let digests = &prototype.digests;
+        if checkpoint.digests.workflow != digests.workflow {
+            return Err(Error::WorkflowChanged);
+        }
+        let names = checkpoint
+            .digests
+            .components
+            .keys()
+            .chain(digests.components.keys());
+        for component_name in names {
+            if checkpoint.digests.components.get(component_name)
+                != digests.components.get(component_name)
+            {
+                return Err(Error::ComponentChanged(component_name.clone()));
+            }
+        }
+
+        let arguments = trace::arguments(&checkpoint.inputs)?;
+        let mut task = Self::new(runtime, prototype, &arguments).await?;
+        task.id = checkpoint.run_id.clone();
+        for node_index in task.graph.node_indices() {
+            task.restore(node_index, &checkpoint.outputs)?;
+        }
+        Ok(task)
     }
 
-    pub async fn run(&mut self) {
-        let nodes = self.prepare();
-        for (node_index, inputs_index) in nodes {
-            let node = self.graph.node_weight(node_index).unwrap();
-            let mut val = None;
-            if let NodeType::Function(function) = node {
-                if function.val.is_none() {
-                    let instance = self.instances.get(&function.component_name).unwrap();
-                    let func = instance.get_func(&mut self.store, function.index).unwrap();
+    /// Saves a checkpoint of the task to the store every time one of its nodes
+    /// succeeds, `manifest` being the path of the workflow, if any.
+    pub fn checkpoint(&mut self, store: Arc<dyn CheckpointStore>, manifest: Option<PathBuf>) {
+        let checkpoint = Checkpoint {
+            digests: self.digests.clone(),
+            inputs: self
+                .inputs
+                .iter()
+                .map(|(input_name, val)| (input_name.0.clone(), TracedVal::from(val)))
+                .collect(),
+            manifest,
+            outputs: BTreeMap::new(),
+            run_id: self.id.clone(),
+        };
+        let redactor = Arc::clone(&self.redactor);
+        self.checkpointer = Some(Checkpointer::new(checkpoint, redactor, store));
+    }
 
-                    let mut params = Vec::new();
-                    for (name, _) in func.params(&self.store) {
-                        let input_index = *inputs_index.get(&InputName(name.to_string())).unwrap();
-                        match self.graph.node_weight(input_index).unwrap() {
-                            NodeType::Value(val) => params.push(val.clone()),
-                            NodeType::Function(function) => {
-                                params.push(function.val.clone().unwrap())
-                            }
+    pub fn id(&self) -> &TaskId {
+        &self.id
+    }
+
+    /// Subscribes to the events of the task, starting from the first one.
+    pub fn subscribe(&self) -> Subscription {
+        self.subscribe_from(0)
+    }
+
+    /// Subscribes to the events of the task, starting from the event with the
+    /// given sequence number.
+    pub fn subscribe_from(&self, sequence: u64) -> Subscription {
+        self.events.subscribe(sequence)
+    }
+
+    /// Returns a handle able to cancel the task from anywhere, even while it
+    /// runs.
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        CancellationHandle(self.cancellation.clone())
+    }
+
+    /// Records a `Trace` of the task as it runs, its functions running rather
+    /// than reusing cached outputs, which a replay would not.
+    pub fn record(&mut self) {
+        self.cache = None;
+        let recorder = Recorder::new(&self.inputs, Arc::clone(&self.redactor));
+        self.recorder = Some(Arc::new(recorder));
+    }
+
+    /// Returns what the task recorded so far, if it records a trace.
+    pub fn trace(&self) -> Option<Trace> {
+        self.recorder.as_ref().map(|recorder| recorder.trace())
+    }
+
+    /// Replays the trace as the task runs, emitting `Event::ExecutionDiverged`
+    /// for the calls differing from the recorded ones, and records the replay.
+    ///
+    /// With `from`, only that node and the nodes downstream of it run, the
+    /// others taking their recorded outputs.
+    pub fn replay(&mut self, trace: Trace, from: Option<&NodeId>) -> Result<(), Error> {
+        if let Some(from) = from {
+            let start = self
+                .graph
+                .node_indices()
+                .find(|node_index| match &self.graph[*node_index] {
+                    NodeType::Function(function) => &function.node_id == from,
+                    _ => false,
+                })
+                .ok_or_else(|| Error::UnknownNode(from.clone()))?;
+            let mut replayed = HashSet::new();
+            let mut pending = vec![start];
+            while let Some(node_index) = pending.pop() {
+                if replayed.insert(node_index) {
+                    pending.extend(self.graph.neighbors_directed(node_index, Outgoing));
+                }
+            }
+
+            let mut upstream = HashSet::new();
+            for node_index in self.graph.node_indices() {
+                if replayed.contains(&node_index) {
+                    continue;
+                }
+                if self.restore(node_index, &trace.outputs)? {
+                    continue;
+                }
+                if let NodeType::Function(function) = &self.graph[node_index]
+                    && self
+                        .downstream_functions(node_index)
+                        .iter()
+                        .any(|target| replayed.contains(target))
+                {
+                    upstream.insert(function.node_id.clone());
+                }
+            }
+            if let Some(node_id) = upstream.into_iter().min_by(|a, b| a.0.cmp(&b.0)) {
+                return Err(Error::NotRecorded(node_id));
+            }
+        }
+        self.replayer = Some(Arc::new(Replayer::new(trace.calls)));
+        self.record();
+        Ok(())
+    }
+
+    /// Gives the function its recorded output, if any, so that it does not run
+    /// again, telling whether it did.
+    fn restore(
+        &mut self,
+        node_index: NodeIndex,
+        outputs: &BTreeMap<String, TracedVal>,
+    ) -> Result<bool, Error> {
+        let NodeType::Function(function) = &self.graph[node_index] else {
+            return Ok(false);
+        };
+        let Some(traced) = outputs.get(&function.node_id.0) else {
+            return Ok(false);
+        };
+        let val = traced.to_val()?;
+        self.graph[node_index].set_val(val);
+        Ok(true)
+    }
+
+    /// Runs every node as soon as all of its inputs are available, and reports
+    /// the outcome of every node.
+    ///
+    /// Once a node fails (after its retries are exhausted) or times out, no
+    /// new node is started and the nodes already running are awaited. When the
+    /// task's deadline expires or the task is cancelled, every running node is
+    /// interrupted.
+    pub async fn run(&mut self) -> TaskReport {
+        self.started_at = self.clock.now();
+        self.emit(Event::TaskStarted);
+
+        // Real time, even when the task's time is virtual
+        let deadline = self
+            .timeout
+            .map(|timeout| (Instant::now() + timeout, timeout));
+
+        // Outputs of the functions that already ran
+        let mut outputs = HashMap::new();
+        // Number of upstream functions each function is still waiting for
+        let mut waiting = HashMap::new();
+        let mut nodes = HashMap::new();
+        for node_index in self.graph.node_indices() {
+            if let NodeType::Function(function) = &self.graph[node_index] {
+                match &function.val {
+                    Some(val) => {
+                        if let Some(recorder) = &self.recorder {
+                            recorder.output(&self.nested_id(&function.node_id), val);
                         }
+                        if let Some(checkpointer) = &self.checkpointer {
+                            checkpointer.succeeded(&function.node_id, val);
+                        }
+                        outputs.insert(node_index, val.clone());
+                        nodes.insert(
+                            function.node_id.clone(),
+                            NodeReport {
+                                output: Some(val.clone()),
+                                status: NodeStatus::Succeeded,
+                                ..NodeReport::not_run()
+                            },
+                        );
                     }
+                    None => {
+                        waiting.insert(node_index, 0);
+                    }
+                }
+            }
+        }
+        for node_index in waiting.keys().copied().collect::<Vec<_>>() {
+            for target in self.downstream_functions(node_index) {
+                *waiting.get_mut(&target).unwrap() += 1;
+            }
+        }
+        let mut ready: Vec<_> = waiting
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(node_index, _)| *node_index)
+            .collect();
 
-                    self.sender
-                        .send(Event::ExecutionStarted(
+        let this = &*self;
+        let mut running = FuturesUnordered::new();
+        let mut stopped = false;
+        // Functions to skip along with an upstream node, by the node whose
+        // condition was false
+        let mut skips = HashMap::new();
+        // Functions skipped without their dependents, whose output is `none`
+        let mut skipped = HashSet::new();
+        loop {
+            if this.cancellation.is_cancelled() {
+                stopped = true;
+                ready.clear();
+            }
+            while let Some(node_index) = this.next_ready(&mut ready, running.is_empty()) {
+                let NodeType::Function(function) = &this.graph[node_index] else {
+                    continue;
+                };
+                if stopped {
+                    continue;
+                }
+                let cause = match skips.remove(&node_index) {
+                    Some(cause) => Ok(Some(cause)),
+                    None => this
+                        .condition(node_index, &outputs)
+                        .map(|holds| (!holds).then(|| function.node_id.clone())),
+                };
+                let calls = match cause {
+                    Ok(None) => this.calls(node_index, function, &outputs, &skipped),
+                    Ok(Some(cause)) => {
+                        this.emit(Event::ExecutionSkipped {
+                            cause: cause.clone(),
+                            node_id: function.node_id.clone(),
+                        });
+                        nodes.insert(function.node_id.clone(), NodeReport::skipped());
+                        if function.skip_dependents {
+                            for target in this.downstream_functions(node_index) {
+                                skips.entry(target).or_insert_with(|| cause.clone());
+                            }
+                        } else {
+                            outputs.insert(node_index, Val::Option(None));
+                            skipped.insert(node_index);
+                        }
+                        this.release(node_index, &mut waiting, &mut ready);
+                        continue;
+                    }
+                    Err(error) => Err(error),
+                };
+                let calls = match calls {
+                    Ok(calls) => calls,
+                    Err(error) => {
+                        this.emit(Event::ExecutionFailed {
+                            attempt: 0,
+                            duration: Duration::ZERO,
+                            error,
+                            index: None,
+                            node_id: function.node_id.clone(),
+                        });
+                        nodes.insert(
                             function.node_id.clone(),
-                            params.clone(),
-                        ))
-                        .unwrap();
+                            NodeReport {
+                                status: NodeStatus::Failed,
+                                ..NodeReport::not_run()
+                            },
+                        );
+                        stopped = true;
+                        continue;
+                    }
+                };
+                running.push(async move {
+                    let execution = match calls {
+                        Calls::ForEach {
+                            concurrency,
+                            params,
+                        } => {
+                            this.execute_each(function, &params, concurrency, deadline)
+                                .await
+                        }
+                        Calls::Once(params) => {
+                            this.execute(function, &params, None, deadline).await
+                        }
+                    };
+                    (node_index, function, execution)
+                });
+            }
+
+            let Some((node_index, function, execution)) = running.next().await else {
+                break;
+            };
+            let Execution {
+                attempt,
+                attempts,
+                cached,
+                duration,
+                index,
+                outcome,
+            } = execution;
+            let node_id = function.node_id.clone();
+            let (event, status, output) = match outcome {
+                Ok(Output { fuel_consumed, val }) => {
+                    if let Some(val) = &val {
+                        if let Some(recorder) = &this.recorder {
+                            recorder.output(&this.nested_id(&node_id), val);
+                        }
+                        if let Some(checkpointer) = &this.checkpointer {
+                            checkpointer.succeeded(&node_id, val);
+                        }
+                        outputs.insert(node_index, val.clone());
+                    }
+                    this.release(node_index, &mut waiting, &mut ready);
+                    let event = match (cached, &val) {
+                        (true, Some(output)) => Event::ExecutionCached {
+                            index,
+                            node_id,
+                            output: output.clone(),
+                        },
+                        _ => Event::ExecutionSucceeded {
+                            attempt,
+                            duration,
+                            fuel_consumed,
+                            index,
+                            node_id,
+                            output: val.clone(),
+                        },
+                    };
+                    (event, NodeStatus::Succeeded, val)
+                }
+                Err(failure) => {
+                    stopped = true;
+                    let (event, status) = match failure {
+                        Failure::Cancelled => (
+                            Event::ExecutionCancelled {
+                                attempt,
+                                duration,
+                                index,
+                                node_id,
+                            },
+                            NodeStatus::Cancelled,
+                        ),
+                        Failure::LimitExceeded(limit_exceeded) => (
+                            Event::ExecutionLimitExceeded {
+                                attempt,
+                                duration,
+                                index,
+                                limit_exceeded,
+                                node_id,
+                            },
+                            NodeStatus::LimitExceeded,
+                        ),
+                        Failure::OutOfFuel(fuel_consumed) => (
+                            Event::ExecutionOutOfFuel {
+                                attempt,
+                                duration,
+                                fuel_consumed,
+                                index,
+                                node_id,
+                            },
+                            NodeStatus::OutOfFuel,
+                        ),
+                        Failure::TimedOut(timeout) => (
+                            Event::ExecutionTimedOut {
+                                attempt,
+                                duration,
+                                index,
+                                node_id,
+                                timeout,
+                            },
+                            NodeStatus::TimedOut,
+                        ),
+                        Failure::Trap(e) => (
+                            Event::ExecutionFailed {
+                                attempt,
+                                duration,
+                                error: format!("{e:#}"),
+                                index,
+                                node_id,
+                            },
+                            NodeStatus::Failed,
+                        ),
+                    };
+                    (event, status, None)
+                }
+            };
+            this.emit(event);
+            nodes.insert(
+                function.node_id.clone(),
+                NodeReport {
+                    attempts,
+                    duration,
+                    output,
+                    status,
+                },
+            );
+        }
+        drop(running);
 
-                    let results = func.results(&self.store).len();
+        for node_index in self.graph.node_indices() {
+            if let NodeType::Function(function) = &self.graph[node_index] {
+                nodes
+                    .entry(function.node_id.clone())
+                    .or_insert_with(NodeReport::not_run);
+            }
+        }
+        let status = if self.cancellation.is_cancelled() {
+            self.emit(Event::TaskCancelled);
+            TaskStatus::Cancelled
+        } else if nodes
+            .values()
+            .all(|node| matches!(node.status, NodeStatus::Skipped | NodeStatus::Succeeded))
+        {
+            TaskStatus::Succeeded
+        } else {
+            TaskStatus::Failed
+        };
+        let duration = self.clock.since(self.started_at);
+        self.emit(Event::TaskCompleted { duration, status });
+        if self.parent.is_none() {
+            self.events.close();
+        }
 
-                    // We need to set a default value for the output or we get "expected 1 results(s), got 0" error
-                    let mut outputs = vec![Val::S32(0); results];
-                    if let Err(e) = func
-                        .call_async(&mut self.store, &params, &mut outputs)
-                        .await
-                    {
-                        self.sender
-                            .send(Event::ExecutionFailed(
-                                function.node_id.clone(),
-                                e.to_string(),
-                            ))
-                            .unwrap();
-                    } else if let Some(output) = outputs.first() {
-                        val = Some(output.clone());
-                        self.sender
-                            .send(Event::ExecutionSucceeded(
-                                function.node_id.clone(),
-                                output.clone(),
-                            ))
-                            .unwrap();
+        for (node_index, val) in outputs {
+            if !skipped.contains(&node_index) {
+                self.graph[node_index].set_val(val);
+            }
+        }
+
+        let mut outputs: HashMap<_, _> = self
+            .outputs
+            .iter()
+            .filter_map(|(output_name, node_id)| {
+                let val = nodes.get(node_id)?.output.clone()?;
+                Some((output_name.clone(), val))
+            })
+            .collect();
+
+        // The report of a child task is its output, only redacted once shown
+        if self.parent.is_none() && !self.redactor.is_empty() {
+            let redact = |val: &mut Val| *val = self.redactor.redact_val(val);
+            for node in nodes.values_mut() {
+                if let Some(output) = &mut node.output {
+                    redact(output);
+                }
+            }
+            outputs.values_mut().for_each(redact);
+        }
+
+        TaskReport {
+            duration,
+            nodes,
+            outputs,
+            status,
+        }
+    }
+
+    /// Collects the parameters of the function's call, or of its call for each
+    /// element of its list.
+    fn calls(
+        &self,
+        node_index: NodeIndex,
+        function: &Function,
+        outputs: &HashMap<NodeIndex, Val>,
+        skipped: &HashSet<NodeIndex>,
+    ) -> Result<Calls, String> {
+        let for_each = self
+            .graph
+            .neighbors_directed(node_index, Incoming)
+            .find_map(|source| match &self.graph[source] {
+                NodeType::ForEach(items, concurrency) => Some((source, items, concurrency)),
+                _ => None,
+            });
+        let Some((items_index, items, concurrency)) = for_each else {
+            let params = self.params(node_index, function, outputs, skipped, None)?;
+            return Ok(Calls::Once(params));
+        };
+
+        let items = items
+            .eval_items(&self.scope(items_index, outputs, None))
+            .map_err(|e| format!("Invalid for_each: {e}"))?;
+        let params = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let iteration = (&Val::U32(index as u32), item);
+                self.params(node_index, function, outputs, skipped, Some(iteration))
+            })
+            .collect::<Result<_, _>>()?;
+        let concurrency = match self.determinism {
+            Some(_) => 1,
+            None => concurrency.map_or(items.len(), |c| c.get() as usize).max(1),
+        };
+        Ok(Calls::ForEach {
+            concurrency,
+            params,
+        })
+    }
+
+    /// Collects the function's parameters from its manual inputs and the
+    /// outputs of its upstream functions, evaluating its templates for the
+    /// given index and element of its list, if any.
+    fn params(
+        &self,
+        node_index: NodeIndex,
+        function: &Function,
+        outputs: &HashMap<NodeIndex, Val>,
+        skipped: &HashSet<NodeIndex>,
+        iteration: Option<(&Val, &Val)>,
+    ) -> Result<Vec<Val>, String> {
+        let inputs: HashMap<_, _> = self
+            .graph
+            .edges_directed(node_index, Incoming)
+            .filter_map(|edge| match edge.weight() {
+                EdgeKind::Input(input_name) => Some((input_name, edge.source())),
+                _ => None,
+            })
+            .collect();
+        function
+            .params
+            .iter()
+            .map(|input_name| {
+                let input_index = inputs[input_name];
+                match &self.graph[input_index] {
+                    NodeType::Condition(_) | NodeType::ForEach(..) => {
+                        unreachable!("conditions and lists are not parameters")
+                    }
+                    NodeType::Function(source) if skipped.contains(&input_index) => Err(format!(
+                        "Input {} comes from the skipped node {}",
+                        input_name.0, source.node_id.0
+                    )),
+                    NodeType::Function(_) => Ok(outputs[&input_index].clone()),
+                    NodeType::Template(template, ty) => template
+                        .eval(&self.scope(input_index, outputs, iteration), ty)
+                        .map_err(|e| format!("Invalid argument {}: {e}", input_name.0)),
+                    NodeType::Value(val) => Ok(val.clone()),
+                }
+            })
+            .collect()
+    }
+
+    /// Evaluates the function's condition, true when it has none.
+    fn condition(
+        &self,
+        node_index: NodeIndex,
+        outputs: &HashMap<NodeIndex, Val>,
+    ) -> Result<bool, String> {
+        for source in self.graph.neighbors_directed(node_index, Incoming) {
+            if let NodeType::Condition(condition) = &self.graph[source] {
+                return match condition.eval(&self.scope(source, outputs, None), &Type::Bool) {
+                    Ok(Val::Bool(holds)) => Ok(holds),
+                    Ok(val) => unreachable!("conditions evaluate to a bool, not {val:?}"),
+                    Err(e) => Err(format!("Invalid condition: {e}")),
+                };
+            }
+        }
+        Ok(true)
+    }
+
+    /// Resolves the references of a template, a condition or a list to the
+    /// workflow's inputs and secrets, the outputs of the functions it depends
+    /// on and the current index and element, if any.
+    fn scope<'a>(
+        &'a self,
+        node_index: NodeIndex,
+        outputs: &'a HashMap<NodeIndex, Val>,
+        iteration: Option<(&'a Val, &'a Val)>,
+    ) -> Scope<&'a Val> {
+        Scope {
+            index: iteration.map(|(index, _)| index),
+            inputs: self.inputs.iter().map(|(k, v)| (k.clone(), v)).collect(),
+            item: iteration.map(|(_, item)| item),
+            nodes: self
+                .graph
+                .neighbors_directed(node_index, Incoming)
+                .map(|source| {
+                    let NodeType::Function(function) = &self.graph[source] else {
+                        unreachable!("templates only depend on functions");
+                    };
+                    (function.node_id.clone(), &outputs[&source])
+                })
+                .collect(),
+            secrets: self.secrets.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        }
+    }
+
+    /// Takes the next function to start, if one may start: any of the ready
+    /// ones, or in deterministic mode, the one with the smallest ID once no
+    /// function runs.
+    fn next_ready(&self, ready: &mut Vec<NodeIndex>, idle: bool) -> Option<NodeIndex> {
+        if self.determinism.is_none() {
+            return ready.pop();
+        }
+        if !idle {
+            return None;
+        }
+        let (position, _) = ready.iter().enumerate().min_by_key(|(_, node_index)| {
+            match &self.graph[**node_index] {
+                NodeType::Function(function) => &function.node_id.0,
+                _ => unreachable!("only functions are ready"),
+            }
+        })?;
+        Some(ready.swap_remove(position))
+    }
+
+    /// Marks the function as done for the functions depending on it, readying
+    /// those it was the last dependency of.
+    fn release(
+        &self,
+        node_index: NodeIndex,
+        waiting: &mut HashMap<NodeIndex, usize>,
+        ready: &mut Vec<NodeIndex>,
+    ) {
+        for target in self.downstream_functions(node_index) {
+            let count = waiting.get_mut(&target).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push(target);
+            }
+        }
+    }
+
+    /// Returns the functions depending on the given one, either directly or
+    /// through a template, a condition or a list, once per dependency.
+    fn downstream_functions(&self, node_index: NodeIndex) -> Vec<NodeIndex> {
+        let mut functions = Vec::new();
+        for target in self.graph.neighbors_directed(node_index, Outgoing) {
+            match &self.graph[target] {
+                NodeType::Condition(_) | NodeType::ForEach(..) | NodeType::Template(..) => {
+                    functions.extend(self.graph.neighbors_directed(target, Outgoing))
+                }
+                _ => functions.push(target),
+            }
+        }
+        functions
+    }
+
+    /// Runs the function with its retry policy, within the task's deadline
+    /// and until the task is cancelled, unless its output is cached.
+    async fn execute(
+        &self,
+        function: &Function,
+        params: &[Val],
+        index: Option<u32>,
+        deadline: Option<(Instant, Duration)>,
+    ) -> Execution {
+        let started_at = self.clock.now();
+        let cache = self.cache.as_ref().zip(function.cache.as_ref());
+        let key = cache.map(|(_, caching)| caching.key(params));
+        if let (Some((cache, _)), Some(key)) = (cache, &key)
+            && let Some(val) = cache.get(key)
+        {
+            return Execution {
+                attempt: 0,
+                attempts: 0,
+                cached: true,
+                duration: self.clock.since(started_at),
+                index,
+                outcome: Ok(Output {
+                    fuel_consumed: None,
+                    val: Some(val),
+                }),
+            };
+        }
+        // Kept outside of the execution, which is dropped when interrupted:
+        // the attempts of the current iteration and of the previous ones
+        let attempts = AtomicU32::new(0);
+        let previous_attempts = AtomicU32::new(0);
+        let execution = async {
+            let execution =
+                self.call_repeatedly(function, params, index, &attempts, &previous_attempts);
+            match deadline {
+                Some((deadline, timeout)) => tokio::time::timeout_at(deadline, execution)
+                    .await
+                    .unwrap_or(Err(Failure::TimedOut(timeout))),
+                None => execution.await,
+            }
+        };
+
+        // Dropping the execution interrupts the guest at its next epoch tick
+        let outcome = tokio::select! {
+            outcome = execution => outcome,
+            _ = self.cancellation.cancelled() => Err(Failure::Cancelled),
+        };
+        if let (Some((cache, caching)), Some(key), Ok(Output { val: Some(val), .. })) =
+            (cache, &key, &outcome)
+        {
+            cache.insert(key, caching, val);
+        }
+        let attempt = attempts.load(Ordering::SeqCst);
+        Execution {
+            attempt,
+            attempts: attempt + previous_attempts.load(Ordering::SeqCst),
+            cached: false,
+            duration: self.clock.since(started_at),
+            index,
+            outcome,
+        }
+    }
+
+    /// Runs the function for each element of its list, at most `concurrency`
+    /// at once, collecting the outputs in the order of the elements. The
+    /// first element failing interrupts the others.
+    async fn execute_each(
+        &self,
+        function: &Function,
+        params: &[Vec<Val>],
+        concurrency: usize,
+        deadline: Option<(Instant, Duration)>,
+    ) -> Execution {
+        let started_at = self.clock.now();
+        self.emit(Event::ExecutionFannedOut {
+            items: params.len() as u32,
+            node_id: function.node_id.clone(),
+        });
+
+        let executions: Vec<_> = params
+            .iter()
+            .enumerate()
+            .map(|(index, params)| self.execute(function, params, Some(index as u32), deadline))
+            .collect();
+        let mut executions = futures::stream::iter(executions).buffer_unordered(concurrency);
+        let mut attempts = 0;
+        let mut fuel_consumed = None;
+        let mut vals = vec![None; params.len()];
+        while let Some(execution) = executions.next().await {
+            attempts += execution.attempts;
+            match execution.outcome {
+                Ok(output) => {
+                    if let Some(fuel) = output.fuel_consumed {
+                        *fuel_consumed.get_or_insert(0) += fuel;
                     }
-                    func.post_return_async(&mut self.store).await.unwrap();
+                    self.emit(match (execution.cached, &output.val) {
+                        (true, Some(val)) => Event::ExecutionCached {
+                            index: execution.index,
+                            node_id: function.node_id.clone(),
+                            output: val.clone(),
+                        },
+                        _ => Event::ExecutionSucceeded {
+                            attempt: execution.attempt,
+                            duration: execution.duration,
+                            fuel_consumed: output.fuel_consumed,
+                            index: execution.index,
+                            node_id: function.node_id.clone(),
+                            output: output.val.clone(),
+                        },
+                    });
+                    vals[execution.index.unwrap() as usize] = output.val;
                 }
+                Err(failure) => {
+                    return Execution {
+                        attempts,
+                        duration: self.clock.since(started_at),
+                        outcome: Err(failure),
+                        ..execution
+                    };
+                }
+            }
+        }
+
+        let val = vals.into_iter().collect::<Option<Vec<_>>>().map(Val::List);
+        Execution {
+            attempt: attempts,
+            attempts,
+            cached: false,
+            duration: self.clock.since(started_at),
+            index: None,
+            outcome: Ok(Output { fuel_consumed, val }),
+        }
+    }
+
+    /// Runs the function until its output satisfies its `until` condition, if
+    /// any, returning the output of the last iteration.
+    async fn call_repeatedly(
+        &self,
+        function: &Function,
+        params: &[Val],
+        index: Option<u32>,
+        attempts: &AtomicU32,
+        previous_attempts: &AtomicU32,
+    ) -> Result<Output, Failure> {
+        let Some(repetition) = &function.repeat else {
+            return self
+                .call_with_retry(function, params, index, None, attempts)
+                .await;
+        };
+
+        let mut fuel_consumed = None;
+        let mut iteration = 1;
+        loop {
+            let output = self
+                .call_with_retry(function, params, index, Some(iteration), attempts)
+                .await?;
+            if let Some(fuel) = output.fuel_consumed {
+                *fuel_consumed.get_or_insert(0) += fuel;
             }
 
-            if let Some(val) = val {
-                self.graph.node_weight_mut(node_index).unwrap().set_val(val);
+            let scope = Scope {
+                index: None,
+                inputs: self.inputs.iter().map(|(k, v)| (k.clone(), v)).collect(),
+                item: None,
+                nodes: output
+                    .val
+                    .iter()
+                    .map(|val| (function.node_id.clone(), val))
+                    .collect(),
+                secrets: self.secrets.iter().map(|(k, v)| (k.clone(), v)).collect(),
+            };
+            let holds = match repetition.until.eval(&scope, &Type::Bool) {
+                Ok(Val::Bool(holds)) => holds,
+                Ok(val) => unreachable!("conditions evaluate to a bool, not {val:?}"),
+                Err(e) => Err(Failure::Trap(wasmtime::Error::msg(format!(
+                    "Invalid repeat: {e}"
+                ))))?,
+            };
+            if holds {
+                return Ok(Output {
+                    fuel_consumed,
+                    val: output.val,
+                });
             }
+            if iteration >= repetition.max_iterations.get() {
+                let wave = match &output.val {
+                    Some(val) => template::wave(&self.redactor.redact_val(val)),
+                    None => "nothing".to_string(),
+                };
+                Err(Failure::Trap(wasmtime::Error::msg(format!(
+                    "Until condition still false after {iteration} iteration(s), the last one returning {wave}"
+                ))))?;
+            }
+
+            let delay = repetition.delay.unwrap_or_default();
+            self.emit(Event::ExecutionRepeating {
+                delay,
+                index,
+                iteration,
+                node_id: function.node_id.clone(),
+                output: output.val,
+            });
+            self.clock.sleep(delay).await;
+            previous_attempts.fetch_add(attempts.load(Ordering::SeqCst), Ordering::SeqCst);
+            iteration += 1;
         }
     }
 
-    fn prepare(&self) -> Vec<(NodeIndex, HashMap<InputName, NodeIndex>)> {
-        let mut nodes = Vec::new();
-        for node_index in toposort(&self.graph, None).unwrap() {
-            let edges = self.graph.edges_directed(node_index, petgraph::Incoming);
-            let inputs = edges
-                .map(|edge| (edge.weight().clone(), edge.source()))
+    /// Calls the function until it succeeds or its retry policy gives up,
+    /// returning the outcome of the last attempt.
+    async fn call_with_retry(
+        &self,
+        function: &Function,
+        params: &[Val],
+        index: Option<u32>,
+        iteration: Option<u32>,
+        attempts: &AtomicU32,
+    ) -> Result<Output, Failure> {
+        let mut attempt = 1;
+        loop {
+            attempts.store(attempt, Ordering::SeqCst);
+            self.emit(Event::ExecutionStarted {
+                attempt,
+                index,
+                iteration,
+                node_id: function.node_id.clone(),
+                params: params.to_vec(),
+            });
+            let outcome = self.call(function, params, index).await;
+            let Some(retry) = &function.retry else {
+                return outcome;
+            };
+
+            let error = match &outcome {
+                Err(Failure::Trap(e)) if retry.on.contains(&RetryOn::Trap) => format!("{e:#}"),
+                Ok(Output {
+                    val: Some(val @ Val::Result(Err(_))),
+                    ..
+                }) if retry.on.contains(&RetryOn::Error) => {
+                    template::wave(&self.redactor.redact_val(val))
+                }
+                _ => return outcome,
+            };
+            if attempt >= retry.max_attempts {
+                return outcome;
+            }
+
+            let delay = match &self.determinism {
+                Some(determinism) => {
+                    let nested_id = self.nested_id(&function.node_id);
+                    let mut rng = determinism.jitter_rng(&nested_id, index, attempt);
+                    backoff::delay(retry, attempt, &mut rng)
+                }
+                None => backoff::delay(retry, attempt, &mut rand::thread_rng()),
+            };
+            self.emit(Event::ExecutionRetrying {
+                attempt,
+                delay,
+                error,
+                index,
+                iteration,
+                node_id: function.node_id.clone(),
+            });
+            self.clock.sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Calls the function once within its timeout.
+    async fn call(
+        &self,
+        function: &Function,
+        params: &[Val],
+        index: Option<u32>,
+    ) -> Result<Output, Failure> {
+        let call = async {
+            match &function.callee {
+                Callee::Component {
+                    component_name,
+                    index: export_index,
+                } => {
+                    self.call_component(function, component_name, *export_index, params, index)
+                        .await
+                }
+                Callee::Workflow(prototype) => {
+                    self.call_workflow(function, prototype, params).await
+                }
+            }
+        };
+
+        match function.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, call)
+                .await
+                .unwrap_or(Err(Failure::TimedOut(timeout))),
+            None => call.await,
+        }
+    }
+
+    /// Calls an export of a component, in a new `Store` holding a fresh
+    /// instance of it, within the function's fuel budget.
+    ///
+    /// What the component writes to its stdout and stderr is emitted line by
+    /// line as it writes it, up to the function's number of bytes or the
+    /// runtime's one, and what it logs as it logs it, the function's `index` in
+    /// its list, if any, included.
+    async fn call_component(
+        &self,
+        function: &Function,
+        component_name: &ComponentName,
+        export_index: ComponentExportIndex,
+        params: &[Val],
+        index: Option<u32>,
+    ) -> Result<Output, Failure> {
+        let max_log_bytes = function.max_log_bytes.unwrap_or(self.max_log_bytes);
+        let capture = |stream| {
+            let emit = self.emitter();
+            let node_id = function.node_id.clone();
+            Capture::new(max_log_bytes, move |line| {
+                emit(Event::Log {
+                    index,
+                    line,
+                    node_id: node_id.clone(),
+                    stream,
+                })
+            })
+        };
+        let logs = Logs {
+            stderr: capture(LogStream::Stderr),
+            stdout: capture(LogStream::Stdout),
+        };
+        let limiter = &self.limiters[component_name];
+        let capabilities = &self.capabilities[component_name];
+        let trace = self.recorder.as_ref().map(|recorder| {
+            let nested_id = self.nested_id(&function.node_id);
+            let replayed = self
+                .replayer
+                .as_ref()
+                .and_then(|replayer| replayer.next(&nested_id, index));
+            recorder.call(nested_id, index, params, replayed)
+        });
+        let state = State::new(
+            limiter.clone(),
+            capabilities,
+            Arc::clone(&self.redactor),
+            self.keyvalue.clone(),
+            Logger {
+                component_name: component_name.clone(),
+                emit: self.emitter(),
+                index,
+                node_id: function.node_id.clone(),
+                nested_id: self.nested_id(&function.node_id),
+                stderr: logs.stderr.clone(),
+                stdout: logs.stdout.clone(),
+                task_id: self.id.clone(),
+            },
+            self.determinism.clone(),
+            trace.clone(),
+        )?;
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limiter);
+
+        // Yield to the executor at every epoch tick so that other nodes
+        // keep running and timeouts can interrupt a busy guest
+        let meter = match &self.fuel {
+            Some(tank) => Some(fuel::Meter::install(&mut store, tank, function.fuel)?),
+            None => {
+                store.epoch_deadline_async_yield_and_update(1);
+                None
+            }
+        };
+
+        let outcome = self
+            .invoke(&mut store, component_name, export_index, params)
+            .await;
+        let fuel_consumed = meter.map(|meter| meter.settle(&store));
+        if let Some(trace) = &trace {
+            match &outcome {
+                Ok(val) => trace.settle(val.as_ref(), None),
+                Err(e) => trace.settle(None, Some(format!("{e:#}"))),
+            }
+            let divergences = match self.replayer {
+                Some(_) => trace.divergences(),
+                None => BTreeMap::new(),
+            };
+            if !divergences.is_empty() {
+                self.emit(Event::ExecutionDiverged {
+                    divergences,
+                    index,
+                    node_id: function.node_id.clone(),
+                });
+            }
+        }
+        match outcome {
+            Ok(val) => Ok(Output { fuel_consumed, val }),
+            Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) => {
+                Err(Failure::OutOfFuel(fuel_consumed.unwrap_or_default()))
+            }
+            Err(e) => match store.data().limiter.limit_exceeded(&e) {
+                Some(limit_exceeded) => Err(Failure::LimitExceeded(limit_exceeded)),
+                None => Err(Failure::Trap(e)),
+            },
+        }
+    }
+
+    /// Runs another workflow as a child task, the function's parameters
+    /// binding its inputs, and returns the record of its outputs.
+    ///
+    /// Boxed, as the child task may itself run workflows.
+    fn call_workflow<'a>(
+        &'a self,
+        function: &'a Function,
+        prototype: &'a Prototype,
+        params: &'a [Val],
+    ) -> Pin<Box<dyn Future<Output = Result<Output, Failure>> + Send + 'a>> {
+        Box::pin(async move {
+            let inputs = function
+                .params
+                .iter()
+                .cloned()
+                .zip(params.iter().cloned())
                 .collect();
-            nodes.push((node_index, inputs));
+            let inputs = with_defaults(prototype, inputs).map_err(|e| Failure::Trap(e.into()))?;
+            let mut task = Task {
+                cache: self.cache.clone(),
+                cancellation: self.cancellation.child_token(),
+                capabilities: reveal(&prototype.capabilities, &self.secrets),
+                checkpointer: None,
+                clock: self.clock.clone(),
+                determinism: self.determinism.clone(),
+                digests: prototype.digests.clone(),
+                engine: self.engine.clone(),
+                events: self.events.clone(),
+                fuel: self.fuel.clone(),
+                graph: Graph::clone(&prototype.graph),
+                id: self.id.clone(),
+                inputs,
+                instances: self.instances.workflows[&function.node_id].clone(),
+                keyvalue: KeyValue {
+                    scope: prototype.scope.clone(),
+                    store: Arc::clone(&self.keyvalue.store),
+                },
+                limiters: prototype.limiters.clone(),
+                max_log_bytes: self.max_log_bytes,
+                outputs: prototype.outputs.clone(),
+                parent: Some(Parent {
+                    node_id: self.nested_id(&function.node_id),
+                    started_at: self.origin(),
+                }),
+                recorder: self.recorder.clone(),
+                redactor: Arc::clone(&self.redactor),
+                replayer: self.replayer.clone(),
+                secrets: Arc::clone(&self.secrets),
+                started_at: self.clock.now(),
+                timeout: prototype.timeout,
+            };
+
+            let report = task.run().await;
+            match report.status {
+                TaskStatus::Cancelled => Err(Failure::Cancelled),
+                TaskStatus::Failed => {
+                    let mut failed: Vec<_> = report
+                        .nodes
+                        .iter()
+                        .filter(|(_, node)| {
+                            !matches!(
+                                node.status,
+                                NodeStatus::NotRun | NodeStatus::Skipped | NodeStatus::Succeeded
+                            )
+                        })
+                        .map(|(node_id, node)| format!("{node_id} {:?}", node.status))
+                        .collect();
+                    failed.sort();
+                    Err(Failure::Trap(wasmtime::Error::msg(format!(
+                        "Workflow failed: {}",
+                        failed.join(", ")
+                    ))))
+                }
+                TaskStatus::Succeeded => {
+                    let mut outputs: Vec<_> = report
+                        .outputs
+                        .into_iter()
+                        .map(|(output_name, val)| (output_name.0, val))
+                        .collect();
+                    outputs.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    Ok(Output {
+                        fuel_consumed: None,
+                        val: (!prototype.outputs.is_empty()).then_some(Val::Record(outputs)),
+                    })
+                }
+            }
+        })
+    }
+
+    async fn invoke(
+        &self,
+        store: &mut Store<State>,
+        component_name: &ComponentName,
+        index: ComponentExportIndex,
+        params: &[Val],
+    ) -> Result<Option<Val>> {
+        store.data().limiter.instantiating()?;
+        let instance = self
+            .instances
+            .components
+            .get(component_name)
+            .unwrap()
+            .instantiate_async(&mut *store)
+            .await?;
+        let func = instance.get_func(&mut *store, index).unwrap();
+
+        // We need to set a default value for the output or we get "expected 1 results(s), got 0" error
+        let mut outputs = vec![Val::S32(0); func.results(&*store).len()];
+        func.call_async(&mut *store, params, &mut outputs).await?;
+        func.post_return_async(&mut *store).await?;
+        Ok(outputs.into_iter().next())
+    }
+
+    /// ID of the node in the events, prefixed by the IDs of the nodes running
+    /// the ancestors of the task, as in `parent/child`.
+    fn nested_id(&self, node_id: &NodeId) -> NodeId {
+        match &self.parent {
+            Some(parent) => NodeId(format!("{}/{}", parent.node_id.0, node_id.0)),
+            None => node_id.clone(),
+        }
+    }
+
+    /// When the outermost task started.
+    fn origin(&self) -> Instant {
+        match &self.parent {
+            Some(parent) => parent.started_at,
+            None => self.started_at,
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        append(
+            &self.events,
+            self.parent.as_ref(),
+            &self.clock,
+            self.started_at,
+            &self.redactor,
+            event,
+        );
+    }
+
+    /// Returns a function emitting events like `emit`, which outlives the task's
+    /// borrow.
+    fn emitter(&self) -> Box<dyn Fn(Event) + Send + Sync> {
+        let events = self.events.clone();
+        let parent = self.parent.clone();
+        let clock = self.clock.clone();
+        let started_at = self.started_at;
+        let redactor = Arc::clone(&self.redactor);
+        Box::new(move |event| {
+            append(
+                &events,
+                parent.as_ref(),
+                &clock,
+                started_at,
+                &redactor,
+                event,
+            )
+        })
+    }
+}
+
+/// Appends an event of a task to the events of the outermost task, redacted,
+/// and nested in the node running the task, if any.
+fn append(
+    events: &EventLog,
+    parent: Option<&Parent>,
+    clock: &Clock,
+    started_at: Instant,
+    redactor: &Redactor,
+    event: Event,
+) {
+    let event = match redactor.is_empty() {
+        true => event,
+        false => event.redacted(redactor),
+    };
+    match parent {
+        Some(parent) => {
+            if let Some(event) = event.nested_in(&parent.node_id) {
+                events.append(clock.since(parent.started_at), event);
+            }
+        }
+        None => events.append(clock.since(started_at), event),
+    }
+}
+
+impl Drop for Task {
+    /// Ends the subscriptions of a task that never completed.
+    fn drop(&mut self) {
+        if self.parent.is_none() {
+            self.events.close();
         }
-        nodes
     }
 }
 
+/// The output of a call of a component, its last lines emitted as `Event::Log`
+/// when the call ends, even when it is interrupted.
+struct Logs {
+    stderr: Capture,
+    stdout: Capture,
+}
+
+impl Drop for Logs {
+    fn drop(&mut self) {
+        self.stdout.finish();
+        self.stderr.finish();
+    }
+}
+
+/// The task running a task as one of its nodes.
+#[derive(Clone)]
+struct Parent {
+    /// ID of the node, as emitted by the outermost task
+    node_id: NodeId,
+    /// When the outermost task started, which the events are stamped against
+    started_at: Instant,
+}
+
+/// Components of a prototype, and of the workflows its nodes use, linked
+/// against the `Runtime`'s `Linker`.
+#[derive(Clone)]
+struct Instances {
+    components: HashMap<ComponentName, InstancePre<State>>,
+    /// By the node using the workflow
+    workflows: HashMap<NodeId, Instances>,
+}
+
+impl Instances {
+    fn new(runtime: &Runtime, prototype: &Prototype) -> Result<Self> {
+        let mut components = HashMap::new();
+        for (component_name, component) in &prototype.components {
+            let capabilities = &prototype.capabilities[component_name];
+            let instance =
+                state::linker(&runtime.linker, capabilities)?.instantiate_pre(component)?;
+            components.insert(component_name.clone(), instance);
+        }
+        let mut workflows = HashMap::new();
+        for node in prototype.graph.node_weights() {
+            if let NodeType::Function(Function {
+                callee: Callee::Workflow(prototype),
+                node_id,
+                ..
+            }) = node
+            {
+                workflows.insert(node_id.clone(), Self::new(runtime, prototype)?);
+            }
+        }
+        Ok(Self {
+            components,
+            workflows,
+        })
+    }
+}
+
+/// Binds the inputs without argument to their default value.
+fn with_defaults(
+    prototype: &Prototype,
+    mut inputs: HashMap<InputName, Val>,
+) -> Result<HashMap<InputName, Val>, Error> {
+    for (input_name, input) in &prototype.inputs {
+        if !inputs.contains_key(input_name) {
+            let default = input.default.clone();
+            let val = default.ok_or(Error::MissingInput(input_name.clone()))?;
+            inputs.insert(input_name.clone(), val);
+        }
+    }
+    Ok(inputs)
+}
+
+/// Replaces the secrets the environment variables and HTTP headers of the
+/// components refer to with their values.
+fn reveal(
+    capabilities: &HashMap<ComponentName, Capabilities>,
+    secrets: &HashMap<SecretName, Val>,
+) -> HashMap<ComponentName, Capabilities> {
+    let scope = Scope {
+        index: None,
+        inputs: HashMap::new(),
+        item: None,
+        nodes: HashMap::new(),
+        secrets: secrets.iter().map(|(k, v)| (k.clone(), v)).collect(),
+    };
+    let reveal = |value: &mut String| {
+        let Ok(Some(template)) = Template::parse(value) else {
+            return;
+        };
+        match template.eval(&scope, &Type::String) {
+            Ok(Val::String(text)) => *value = text,
+            _ => unreachable!("capabilities are checked by the prototype"),
+        }
+    };
+    let mut capabilities = capabilities.clone();
+    for capabilities in capabilities.values_mut() {
+        capabilities.env.values_mut().for_each(&reveal);
+        capabilities.http.headers.values_mut().for_each(&reveal);
+    }
+    capabilities
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("Component {0:?} changed since the checkpoint")]
+    ComponentChanged(String),
+    #[error("Invalid input {0:?}: {1}")]
+    InvalidInput(InputName, ArgumentError),
+    #[error("Missing input without default: {0:?}")]
+    MissingInput(InputName),
+    #[error("Missing secret, held by no provider: {0:?}")]
+    MissingSecret(SecretName),
+    #[error("Output of {0:?} not recorded, needed to replay the nodes after it")]
+    NotRecorded(NodeId),
+    #[error("Secret error: {0}")]
+    Secret(#[from] secrets::Error),
+    #[error("Invalid trace: {0}")]
+    Trace(#[from] trace::Error),
+    #[error("Unknown input: {0:?}")]
+    UnknownInput(InputName),
+    #[error("Unknown node: {0:?}")]
+    UnknownNode(NodeId),
     #[error("Wasmtime error: {0}")]
     Wasmtime(#[from] wasmtime::Error),
+    #[error("Workflow changed since the checkpoint")]
+    WorkflowChanged,
 }
 
+/// Cancels a `Task`: its running nodes are interrupted and the pending ones
+/// are skipped.
 #[derive(Clone, Debug)]
-pub enum Event {
-    ExecutionFailed(NodeId, String),
-    ExecutionStarted(NodeId, Vec<Val>),
-    ExecutionSucceeded(NodeId, Val),
+pub struct CancellationHandle(CancellationToken);
+
+impl CancellationHandle {
+    pub fn cancel(&self) {
+        self.0.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+/// Identifies a `Task` in what the runtime logs, the tasks of the workflows
+/// its nodes use sharing its ID.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct TaskId(pub String);
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The parameters a function is called with.
+enum Calls {
+    /// For each element of a list, at most `concurrency` at once
+    ForEach {
+        concurrency: usize,
+        params: Vec<Vec<Val>>,
+    },
+    Once(Vec<Val>),
+}
+
+/// What running a function with its retry policy, or for each element of its
+/// list, produced.
+struct Execution {
+    /// Attempt of the element `index` producing the outcome
+    attempt: u32,
+    /// Attempts of every element
+    attempts: u32,
+    /// Whether the output was cached instead of produced by an attempt
+    cached: bool,
+    duration: Duration,
+    index: Option<u32>,
+    outcome: Result<Output, Failure>,
+}
+
+/// What a successful call of a function produced.
+struct Output {
+    fuel_consumed: Option<u64>,
+    val: Option<Val>,
+}
+
+/// Why a call of a function did not produce an output.
+enum Failure {
+    Cancelled,
+    LimitExceeded(LimitExceeded),
+    OutOfFuel(u64),
+    TimedOut(Duration),
+    Trap(wasmtime::Error),
+}
+
+impl From<wasmtime::Error> for Failure {
+    fn from(error: wasmtime::Error) -> Self {
+        Self::Trap(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::{
+        RuntimeConfig,
+        cache::FileCache,
+        checkpoint::{FileCheckpoints, sha256},
+        deterministic::Deterministic,
+        keyvalue::{Bucket, KeyValueStore, MemoryStore},
+        secrets::SecretProvider,
+        testing,
+    };
+
+    #[tokio::test]
+    async fn test_lifecycle_events_and_report() {
+        let mut runtime = Runtime::new().unwrap();
+        let workflow = testing::workflow(
+            "
+            dependencies: { math: $math }
+            edges: [{ source: first, target: second, input: x }]
+            nodes:
+              first: { run: inc, use: math, with: { x: 1 } }
+              second: { run: inc, use: math }
+            outputs: { result: second }
+            ",
+        );
+        let prototype = Prototype::new(&mut runtime, &workflow).await.unwrap();
+        let mut task = Task::new(&mut runtime, &prototype, &HashMap::new())
+            .await
+            .unwrap();
+        let mut subscription = task.subscribe();
+        let report = task.run().await;
+        let mut records = Vec::new();
+        while let Some(record) = subscription.recv().await {
+            records.push(record);
+        }
+
+        assert!(
+            records
+                .iter()
+                .enumerate()
+                .all(|(i, r)| r.sequence == i as u64)
+        );
+        assert!(records.windows(2).all(|r| r[0].elapsed <= r[1].elapsed));
+        let events: Vec<_> = records.into_iter().map(|record| record.event).collect();
+        assert!(matches!(
+            &events[..],
+            [
+                Event::TaskStarted,
+                Event::ExecutionStarted { node_id: a, attempt: 1, .. },
+                Event::ExecutionSucceeded { output: Some(Val::U32(2)), .. },
+                Event::ExecutionStarted { node_id: b, params, .. },
+                Event::ExecutionSucceeded { output: Some(Val::U32(3)), .. },
+                Event::TaskCompleted { status: TaskStatus::Succeeded, .. },
+            ] if a.0 == "first" && b.0 == "second" && params == &[Val::U32(2)]
+        ));
+        assert_eq!(report.status, TaskStatus::Succeeded);
+        assert_eq!(
+            report.outputs[&OutputName("result".to_string())],
+            Val::U32(3)
+        );
+        let first = &report.nodes[&NodeId("first".to_string())];
+        assert_eq!((first.attempts, first.status), (1, NodeStatus::Succeeded));
+    }
+
+    #[tokio::test]
+    async fn test_retries_run_fresh_instances_with_backoff() {
+        let mut runtime = Runtime::new().unwrap();
+        let (report, events) = testing::run(
+            &mut runtime,
+            r#"
+            dependencies: { counter: $counter }
+            edges: []
+            nodes:
+              flaky:
+                run: flaky
+                use: counter
+                with: { key: '"flaky"', failures: 2 }
+                retry: { max_attempts: 3, delay: 10ms, on: [error] }
+              exhausted:
+                run: flaky
+                use: counter
+                with: { key: '"exhausted"', failures: 2 }
+                retry: { max_attempts: 2, delay: 10ms, backoff: constant, on: [error] }
+            "#,
+        )
+        .await;
+        let events_of = |id: &str| -> Vec<_> {
+            events
+                .iter()
+                .filter(|event| match event {
+                    Event::ExecutionFailed { node_id, .. }
+                    | Event::ExecutionRetrying { node_id, .. }
+                    | Event::ExecutionStarted { node_id, .. }
+                    | Event::ExecutionSucceeded { node_id, .. } => node_id.0 == id,
+                    _ => false,
+                })
+                .collect()
+        };
+        let ms = Duration::from_millis;
+        assert!(matches!(
+            &events_of("flaky")[..],
+            [
+                Event::ExecutionStarted { attempt: 1, .. },
+                Event::ExecutionRetrying { attempt: 1, delay: first, error: first_error, .. },
+                Event::ExecutionStarted { attempt: 2, .. },
+                Event::ExecutionRetrying { attempt: 2, delay: second, .. },
+                Event::ExecutionStarted { attempt: 3, .. },
+                Event::ExecutionSucceeded {
+                    attempt: 3,
+                    output: Some(Val::Result(Ok(Some(ok)))),
+                    ..
+                },
+            ] if *first == ms(10) && *second == ms(20) && first_error == "err(1)"
+                && **ok == Val::U64(3)
+        ));
+        assert!(matches!(
+            &events_of("exhausted")[..],
+            [
+                Event::ExecutionStarted { attempt: 1, .. },
+                Event::ExecutionRetrying { attempt: 1, delay, .. },
+                Event::ExecutionStarted { attempt: 2, .. },
+                Event::ExecutionSucceeded { attempt: 2, output: Some(Val::Result(Err(_))), .. },
+            ] if *delay == ms(10)
+        ));
+        let flaky = &report.nodes[&NodeId("flaky".to_string())];
+        assert_eq!((flaky.attempts, flaky.status), (3, NodeStatus::Succeeded));
+    }
+
+    #[tokio::test]
+    async fn test_retries_and_repeats_redact_secrets() {
+        struct Token;
+        impl SecretProvider for Token {
+            fn get<'a>(
+                &'a self,
+                _: &'a SecretName,
+            ) -> BoxFuture<'a, Result<Option<String>, secrets::Error>> {
+                // Escaped once rendered as WAVE
+                Box::pin(async { Ok(Some("a \"quoted\"\nsecret".to_string())) })
+            }
+        }
+        let mut runtime = Runtime::new().unwrap();
+        runtime.secrets = vec![Box::new(Token)];
+        let (report, events) = testing::run(
+            &mut runtime,
+            "
+            dependencies: { math: $math }
+            edges: []
+            nodes:
+              rejected:
+                run: reject
+                use: math
+                with: { s: '${{ secrets.token }}' }
+                retry: { max_attempts: 2, delay: 1ms, on: [error] }
+              repeated:
+                run: echo
+                use: math
+                with: { s: '${{ secrets.token }}' }
+                repeat: { until: 'false', max_iterations: 1 }
+            secrets: [token]
+            ",
+        )
+        .await;
+        assert_eq!(report.status, TaskStatus::Failed);
+        let errors: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::ExecutionFailed { error, .. } | Event::ExecutionRetrying { error, .. } => {
+                    Some(error.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        assert!(errors.contains(&r#"err("***")"#), "{errors:?}");
+        assert!(
+            errors.contains(
+                &r#"Until condition still false after 1 iteration(s), the last one returning "***""#
+            ),
+            "{errors:?}"
+        );
+        assert!(errors.iter().all(|error| !error.contains("quoted")));
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_interrupt_busy_nodes() {
+        let mut runtime = Runtime::new().unwrap();
+        let (report, events) = testing::run(
+            &mut runtime,
+            "
+            dependencies: { math: $math }
+            edges: []
+            timeout: 200ms
+            nodes:
+              fast: { run: spin, use: math, timeout: 50ms }
+              slow: { run: spin, use: math }
+            ",
+        )
+        .await;
+        assert_eq!(report.status, TaskStatus::Failed);
+        let timeout = |id: &str| {
+            events.iter().find_map(|event| match event {
+                Event::ExecutionTimedOut {
+                    node_id, timeout, ..
+                } if node_id.0 == id => Some(*timeout),
+                _ => None,
+            })
+        };
+        assert_eq!(timeout("fast"), Some(Duration::from_millis(50)));
+        assert_eq!(timeout("slow"), Some(Duration::from_millis(200)));
+        assert_eq!(
+            report.nodes[&NodeId("slow".to_string())].status,
+            NodeStatus::TimedOut
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_stops_running_nodes() {
+        let mut runtime = Runtime::new().unwrap();
+        let workflow = testing::workflow(
+            "
+            dependencies: { math: $math }
+            edges: [{ source: spin, target: inc, input: x }]
+            nodes:
+              spin: { run: spin, use: math }
+              inc: { run: inc, use: math }
+            ",
+        );
+        let prototype = Prototype::new(&mut runtime, &workflow).await.unwrap();
+        let mut task = Task::new(&mut runtime, &prototype, &HashMap::new())
+            .await
+            .unwrap();
+        let cancellation_handle = task.cancellation_handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancellation_handle.cancel();
+        });
+
+        let (report, events) = testing::events(&mut task).await;
+        assert_eq!(report.status, TaskStatus::Cancelled);
+        let node = |id: &str| report.nodes[&NodeId(id.to_string())].status;
+        assert_eq!(node("spin"), NodeStatus::Cancelled);
+        assert_eq!(node("inc"), NodeStatus::NotRun);
+        assert!(matches!(
+            &events[events.len() - 3..],
+            [
+                Event::ExecutionCancelled { node_id, .. },
+                Event::TaskCancelled,
+                Event::TaskCompleted {
+                    status: TaskStatus::Cancelled,
+                    ..
+                },
+            ] if node_id.0 == "spin"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_false_conditions_skip_nodes() {
+        let mut runtime = Runtime::new().unwrap();
+        let (report, events) = testing::run(
+            &mut runtime,
+            "
+            dependencies: { math: $math }
+            edges:
+              - { source: alone, target: fed, input: x }
+              - { source: fed, target: after, input: x }
+              - { source: along, target: dependent, input: x }
+            nodes:
+              first: { run: inc, use: math, with: { x: 1 } }
+              alone:
+                run: inc
+                use: math
+                with: { x: '${{ nodes.first.output }}' }
+                if: nodes.first.output == 0
+                skip_dependents: false
+              fed: { run: inc, use: math }
+              after: { run: inc, use: math }
+              along: { run: inc, use: math, with: { x: 1 }, if: 'false' }
+              dependent: { run: inc, use: math }
+            ",
+        )
+        .await;
+        assert_eq!(report.status, TaskStatus::Failed);
+        let node = |id: &str| report.nodes[&NodeId(id.to_string())].status;
+        assert_eq!(node("first"), NodeStatus::Succeeded);
+        assert_eq!(node("alone"), NodeStatus::Skipped);
+        assert_eq!(node("along"), NodeStatus::Skipped);
+        assert_eq!(node("dependent"), NodeStatus::Skipped);
+        assert_eq!(node("fed"), NodeStatus::Failed);
+        // The task stopped once `fed` failed
+        assert_eq!(node("after"), NodeStatus::NotRun);
+
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::ExecutionSkipped { cause, node_id }
+                if cause.0 == "along" && node_id.0 == "dependent"
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::ExecutionFailed { error, node_id, .. }
+                if node_id.0 == "fed" && error == "Input x comes from the skipped node alone"
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_sub_workflows_nest_their_nodes() {
+        let dir = std::env::temp_dir().join(format!("sub-workflows-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let math = testing::component(testing::MATH);
+        let (child, broken) = (dir.join("child.yaml"), dir.join("broken.yaml"));
+        std::fs::write(
+            &child,
+            format!(
+                "
+                dependencies: {{ math: {math} }}
+                edges: []
+                inputs: {{ n: {{ type: u32 }} }}
+                nodes: {{ inc: {{ run: inc, use: math, with: {{ x: '${{{{ inputs.n }}}}' }} }} }}
+                outputs: {{ result: inc }}
+                "
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            &broken,
+            format!(
+                "
+                dependencies: {{ math: {math} }}
+                edges: []
+                nodes: {{ boom: {{ run: boom, use: math }} }}
+                "
+            ),
+        )
+        .unwrap();
+
+        let mut runtime = Runtime::new().unwrap();
+        let (report, events) = testing::run(
+            &mut runtime,
+            &format!(
+                "
+                dependencies: {{ math: $math }}
+                edges: []
+                nodes:
+                  sub: {{ use: child, with: {{ n: 1 }} }}
+                  inc: {{ run: inc, use: math, with: {{ x: '${{{{ nodes.sub.output.result }}}}' }} }}
+                outputs: {{ result: inc, sub: sub }}
+                workflows: {{ child: {} }}
+                ",
+                child.display()
+            ),
+        )
+        .await;
+        assert_eq!(report.status, TaskStatus::Succeeded);
+        let output = |name: &str| &report.outputs[&OutputName(name.to_string())];
+        assert_eq!(
+            output("sub"),
+            &Val::Record(vec![("result".to_string(), Val::U32(2))])
+        );
+        assert_eq!(output("result"), &Val::U32(3));
+        let succeeded: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::ExecutionSucceeded { node_id, .. } => Some(node_id.0.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(succeeded, ["sub/inc", "sub", "inc"]);
+
+        let (report, events) = testing::run(
+            &mut runtime,
+            &format!(
+                "
+                dependencies: {{}}
+                edges: []
+                nodes: {{ sub: {{ use: broken }} }}
+                workflows: {{ broken: {} }}
+                ",
+                broken.display()
+            ),
+        )
+        .await;
+        assert_eq!(report.status, TaskStatus::Failed);
+        assert_eq!(
+            report.nodes[&NodeId("sub".to_string())].status,
+            NodeStatus::Failed
+        );
+        let failed: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::ExecutionFailed { error, node_id, .. } => Some((node_id.0.as_str(), error)),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(
+            &failed[..],
+            [("sub/boom", _), ("sub", error)] if *error == "Workflow failed: node:boom Failed"
+        ));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_output_lines_are_emitted_as_written() {
+        let printer = testing::component(
+            r#"(component
+              (import "wasi:io/error@0.2.3" (instance $io-error
+                (export "error" (type (sub resource)))))
+              (alias export $io-error "error" (type $error))
+              (import "wasi:io/streams@0.2.3" (instance $streams
+                (export $e "error" (type (eq $error)))
+                (export $os "output-stream" (type (sub resource)))
+                (type $own-e (own $e))
+                (type $se (variant (case "last-operation-failed" $own-e) (case "closed")))
+                (export $stream-error "stream-error" (type (eq $se)))
+                (type $borrow (borrow $os))
+                (export "[method]output-stream.blocking-write-and-flush"
+                  (func (param "self" $borrow) (param "contents" (list u8))
+                    (result (result (error $stream-error)))))))
+              (alias export $streams "output-stream" (type $output-stream))
+              (import "wasi:cli/stdout@0.2.3" (instance $stdout
+                (export $os "output-stream" (type (eq $output-stream)))
+                (type $own (own $os))
+                (export "get-stdout" (func (result $own)))))
+              (core module $libc
+                (memory (export "memory") 1)
+                (global $heap (mut i32) (i32.const 1024))
+                (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+                  (local $p i32)
+                  global.get $heap local.set $p
+                  global.get $heap local.get 3 i32.add global.set $heap
+                  local.get $p))
+              (core instance $libc (instantiate $libc))
+              (alias core export $libc "memory" (core memory $mem))
+              (alias core export $libc "realloc" (core func $realloc))
+              (core func $get-stdout (canon lower (func $stdout "get-stdout")))
+              (core func $write
+                (canon lower (func $streams "[method]output-stream.blocking-write-and-flush")
+                  (memory $mem)))
+              (core module $m
+                (import "host" "get-stdout" (func $get-stdout (result i32)))
+                (import "host" "write" (func $write (param i32 i32 i32 i32)))
+                (func (export "print") (param $ptr i32) (param $len i32) (param $spin i32)
+                  (result i32)
+                  (call $write (call $get-stdout) (local.get $ptr) (local.get $len) (i32.const 64))
+                  (if (local.get $spin) (then (loop br 0)))
+                  i32.const 0))
+              (core instance $i (instantiate $m
+                (with "host" (instance
+                  (export "get-stdout" (func $get-stdout))
+                  (export "write" (func $write))))))
+              (func (export "print") (param "text" string) (param "spin" bool) (result u32)
+                (canon lift (core func $i "print") (memory $mem) (realloc $realloc)))
+            )"#,
+        );
+        let mut runtime = Runtime::new().unwrap();
+        let workflow = testing::workflow(&format!(
+            r#"
+            dependencies: {{ printer: {printer} }}
+            edges: []
+            nodes:
+              streamed: {{ run: print, use: printer, with: {{ text: '"one\ntwo"', spin: true }} }}
+              capped:
+                run: print
+                use: printer
+                with: {{ text: '"three\nfour\n"', spin: false }}
+                max_log_bytes: 8
+            "#
+        ));
+        let prototype = Prototype::new(&mut runtime, &workflow).await.unwrap();
+        let mut task = Task::new(&mut runtime, &prototype, &HashMap::new())
+            .await
+            .unwrap();
+        let mut subscription = task.subscribe();
+        let cancellation_handle = task.cancellation_handle();
+        // `streamed` spins once it wrote its first line, so the task is only
+        // cancelled if its lines are emitted before its call ends
+        let lines = async move {
+            let mut lines = Vec::new();
+            while let Some(record) = subscription.recv().await {
+                if let Event::Log { line, node_id, .. } = record.event {
+                    lines.push((node_id.0, line));
+                }
+                let (streamed, capped) = lines
+                    .iter()
+                    .partition::<Vec<_>, _>(|(node_id, _)| node_id == "streamed");
+                if streamed.len() == 1 && capped.len() == 3 {
+                    cancellation_handle.cancel();
+                }
+            }
+            lines
+        };
+        let (report, mut lines) = tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::join!(task.run(), lines)
+        })
+        .await
+        .unwrap();
+        assert_eq!(report.status, TaskStatus::Cancelled);
+        lines.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|(node_id, line)| format!("{node_id}: {line}"))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "capped: three",
+                "capped: fo",
+                "capped: [output truncated]",
+                "streamed: one",
+                "streamed: two",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_for_each_fans_out_in_order() {
+        let mut runtime = Runtime::new().unwrap();
+        let (report, events) = testing::run(
+            &mut runtime,
+            "
+            dependencies: { math: $math }
+            edges: []
+            nodes:
+              range: { run: range, use: math }
+              inc:
+                run: inc
+                use: math
+                with: { x: '${{ item }}' }
+                for_each: { items: nodes.range.output, concurrency: 1 }
+            outputs: { result: inc }
+            ",
+        )
+        .await;
+        assert_eq!(report.status, TaskStatus::Succeeded);
+        assert_eq!(
+            report.outputs[&OutputName("result".to_string())],
+            Val::List(vec![Val::U32(2), Val::U32(3), Val::U32(4), Val::U32(5)])
+        );
+        // After the events of the task and of `range`
+        assert!(matches!(
+            &events[3..6],
+            [
+                Event::ExecutionFannedOut { items: 4, .. },
+                Event::ExecutionStarted { index: Some(0), params, .. },
+                Event::ExecutionSucceeded { index: Some(0), output: Some(Val::U32(2)), .. },
+            ] if params == &[Val::U32(1)]
+        ));
+        assert!(matches!(
+            &events[10..],
+            [
+                Event::ExecutionStarted { index: Some(3), params, .. },
+                Event::ExecutionSucceeded { index: Some(3), .. },
+                Event::ExecutionSucceeded { index: None, output: Some(Val::List(outputs)), .. },
+                Event::TaskCompleted { .. },
+            ] if params == &[Val::U32(4)] && outputs.len() == 4
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_repeat_runs_until_the_condition_holds() {
+        let mut runtime = Runtime::new().unwrap();
+        let (report, events) = testing::run(
+            &mut runtime,
+            "
+            dependencies: { math: $math }
+            edges: []
+            nodes:
+              ready:
+                run: inc
+                use: math
+                with: { x: 1 }
+                repeat: { until: nodes.ready.output == 2, max_iterations: 3 }
+              never:
+                run: inc
+                use: math
+                with: { x: 1 }
+                repeat: { until: nodes.never.output == 3, max_iterations: 3, delay: 1ms }
+            ",
+        )
+        .await;
+        assert_eq!(report.status, TaskStatus::Failed);
+        let node = |id: &str| report.nodes[&NodeId(id.to_string())].status;
+        assert_eq!(node("ready"), NodeStatus::Succeeded);
+        assert_eq!(node("never"), NodeStatus::Failed);
+
+        let events_of = |id: &str| -> Vec<_> {
+            events
+                .iter()
+                .filter(|event| match event {
+                    Event::ExecutionFailed { node_id, .. }
+                    | Event::ExecutionRepeating { node_id, .. }
+                    | Event::ExecutionStarted { node_id, .. }
+                    | Event::ExecutionSucceeded { node_id, .. } => node_id.0 == id,
+                    _ => false,
+                })
+                .collect()
+        };
+        assert!(matches!(
+            &events_of("ready")[..],
+            [
+                Event::ExecutionStarted {
+                    iteration: Some(1),
+                    ..
+                },
+                Event::ExecutionSucceeded {
+                    output: Some(Val::U32(2)),
+                    ..
+                },
+            ]
+        ));
+        assert!(matches!(
+            &events_of("never")[..],
+            [
+                Event::ExecutionStarted { iteration: Some(1), .. },
+                Event::ExecutionRepeating { iteration: 1, output: Some(Val::U32(2)), .. },
+                Event::ExecutionStarted { iteration: Some(2), .. },
+                Event::ExecutionRepeating { iteration: 2, delay, .. },
+                Event::ExecutionStarted { iteration: Some(3), .. },
+                Event::ExecutionFailed { error, .. },
+            ] if *delay == Duration::from_millis(1)
+                && error.contains("still false after 3 iteration(s)")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_traces_replay_cached_runs() {
+        let dir = std::env::temp_dir().join(format!("traced-cache-{}", std::process::id()));
+        let mut runtime = Runtime::new().unwrap();
+        runtime.cache = Some(Arc::new(FileCache::new(&dir)));
+        let workflow = testing::workflow(
+            "
+            dependencies: { math: $math }
+            edges: []
+            nodes:
+              inc: { run: inc, use: math, with: { x: 1 }, cache: true }
+            ",
+        );
+        let prototype = Prototype::new(&mut runtime, &workflow).await.unwrap();
+        let inputs = HashMap::new();
+        let is_cached = |event: &Event| matches!(event, Event::ExecutionCached { .. });
+
+        let (_, events) =
+            testing::events(&mut Task::new(&mut runtime, &prototype, &inputs).await.unwrap()).await;
+        assert!(!events.iter().any(is_cached));
+        let (_, events) =
+            testing::events(&mut Task::new(&mut runtime, &prototype, &inputs).await.unwrap()).await;
+        assert!(events.iter().any(is_cached));
+
+        let mut recorded = Task::new(&mut runtime, &prototype, &inputs).await.unwrap();
+        recorded.record();
+        let (_, events) = testing::events(&mut recorded).await;
+        assert!(!events.iter().any(is_cached));
+        let mut replayed = Task::new(&mut runtime, &prototype, &inputs).await.unwrap();
+        replayed.replay(recorded.trace().unwrap(), None).unwrap();
+        let (report, events) = testing::events(&mut replayed).await;
+        assert_eq!(report.status, TaskStatus::Succeeded);
+        assert!(
+            !events
+                .iter()
+                .any(|event| is_cached(event) || matches!(event, Event::ExecutionDiverged { .. }))
+        );
+
+        let mut deterministic = Runtime::with_config(RuntimeConfig {
+            deterministic: Some(Deterministic::default()),
+            ..RuntimeConfig::default()
+        })
+        .unwrap();
+        deterministic.cache = runtime.cache.clone();
+        let prototype = Prototype::new(&mut deterministic, &workflow).await.unwrap();
+        let mut task = Task::new(&mut deterministic, &prototype, &inputs)
+            .await
+            .unwrap();
+        let (_, events) = testing::events(&mut task).await;
+        assert!(!events.iter().any(is_cached));
+        std::fs::remove_dir_all(dir).ok();
+    }
+    #[tokio::test]
+    async fn test_resume_runs_the_unfinished_nodes() {
+        let dir = std::env::temp_dir().join(format!("resume-{}", std::process::id()));
+        let checkpoints: Arc<dyn CheckpointStore> = Arc::new(FileCheckpoints::new(&dir));
+        let store = Arc::new(MemoryStore::default());
+        let mut runtime = Runtime::new().unwrap();
+        runtime.keyvalue = Some(Arc::clone(&store) as Arc<dyn KeyValueStore>);
+        let yaml = "
+            name: resumed
+            dependencies: { counter: $counter }
+            edges: []
+            nodes:
+              first: { run: flaky, use: counter, with: { key: '\"first\"', failures: 0 } }
+              second:
+                run: flaky
+                use: counter
+                with: { key: '\"second\"', failures: 1 }
+                repeat: { until: case(nodes.second.output) == 'ok', max_iterations: 1 }
+            outputs: { first: first, second: second }
+            ";
+        let prototype = Prototype::new(&mut runtime, &testing::workflow(yaml))
+            .await
+            .unwrap();
+        let mut task = Task::new(&mut runtime, &prototype, &HashMap::new())
+            .await
+            .unwrap();
+        task.checkpoint(Arc::clone(&checkpoints), None);
+        let (report, _) = testing::events(&mut task).await;
+        assert_eq!(report.status, TaskStatus::Failed);
+        let checkpoint = checkpoints.load(task.id()).unwrap().unwrap();
+        assert_eq!(checkpoint.outputs.keys().collect::<Vec<_>>(), ["first"]);
+
+        let mut resumed = Task::resume(&mut runtime, &prototype, &checkpoint)
+            .await
+            .unwrap();
+        assert_eq!(resumed.id(), task.id());
+        let (report, events) = testing::events(&mut resumed).await;
+        assert_eq!(report.status, TaskStatus::Succeeded);
+        let ok = |n| Val::Result(Ok(Some(Box::new(Val::U64(n)))));
+        let output = |name: &str| report.outputs[&OutputName(name.to_string())].clone();
+        assert_eq!((output("first"), output("second")), (ok(1), ok(2)));
+        let started: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::ExecutionStarted { node_id, .. } => Some(node_id.0.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(started, ["second"]);
+        let counts = Bucket {
+            name: "counts".to_string(),
+            scope: "resumed".to_string(),
+        };
+        assert_eq!(store.get(&counts, "first").unwrap(), Some(b"1".to_vec()));
+
+        // Neither the workflow nor its components may change in between
+        let changed = testing::workflow(&yaml.replace("failures: 1", "failures: 0"));
+        let changed = Prototype::new(&mut runtime, &changed).await.unwrap();
+        assert!(matches!(
+            Task::resume(&mut runtime, &changed, &checkpoint).await,
+            Err(Error::WorkflowChanged)
+        ));
+        let mut checkpoint = checkpoint;
+        for digest in checkpoint.digests.components.values_mut() {
+            *digest = sha256(b"another component");
+        }
+        assert!(matches!(
+            Task::resume(&mut runtime, &prototype, &checkpoint).await,
+            Err(Error::ComponentChanged(component_name)) if component_name == "counter"
+        ));
+        std::fs::remove_dir_all(dir).ok();
+    }
 }
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use rand::Rng;
+use workflow::{Backoff, Retry};
+
+/// Computes how long to wait after the given failed attempt (starting at 1)
+/// before trying again, drawing the jitter from `rng`.
+pub(super) fn delay(retry: &Retry, attempt: u32, rng: &mut impl Rng) -> Duration {
+    let initial = *retry.delay;
+    let delay = match retry.backoff {
+        Backoff::Constant => initial,
+        Backoff::Exponential => {
+            initial.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        }
+        Backoff::Linear => initial.saturating_mul(attempt),
+    };
+    let delay = retry
+        .max_delay
+        .map_or(delay, |max_delay| delay.min(*max_delay));
+
+    let jitter = retry.jitter.clamp(0.0, 1.0);
+    if jitter > 0.0 {
+        delay.mul_f64(1.0 + rng.gen_range(-jitter..=jitter))
+    } else {
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use workflow::RetryOn;
+
+    use super::*;
+
+    fn retry(backoff: Backoff) -> Retry {
+        Retry {
+            backoff,
+            delay: workflow::Duration(Duration::from_millis(100)),
+            jitter: 0.0,
+            max_attempts: 5,
+            max_delay: Some(workflow::Duration(Duration::from_millis(350))),
+            on: vec![RetryOn::Trap],
+        }
+    }
+
+    #[test]
+    fn test_exponential_delay_is_capped_by_max_delay() {
+        let retry = retry(Backoff::Exponential);
+        let delays: Vec<_> = (1..=4)
+            .map(|attempt| delay(&retry, attempt, &mut rand::thread_rng()))
+            .collect();
+        assert_eq!(
+            delays,
+            [100, 200, 350, 350].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_linear_delay_grows_by_initial_delay() {
+        let retry = retry(Backoff::Linear);
+        assert_eq!(
+            delay(&retry, 3, &mut rand::thread_rng()),
+            Duration::from_millis(300)
+        );
+    }
+}
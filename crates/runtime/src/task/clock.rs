@@ -0,0 +1,57 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// Time as the events of a task see it.
+#[derive(Clone, Debug)]
+pub(super) enum Clock {
+    Real,
+    /// Deterministic mode: time stands still but for the delays the task
+    /// skips, counted in nanoseconds since `origin`
+    Virtual {
+        origin: Instant,
+        skipped: Arc<AtomicU64>,
+    },
+}
+
+impl Clock {
+    pub(super) fn new(deterministic: bool) -> Self {
+        match deterministic {
+            true => Self::Virtual {
+                origin: Instant::now(),
+                skipped: Arc::default(),
+            },
+            false => Self::Real,
+        }
+    }
+
+    pub(super) fn now(&self) -> Instant {
+        match self {
+            Self::Real => Instant::now(),
+            Self::Virtual { origin, skipped } => {
+                *origin + Duration::from_nanos(skipped.load(Ordering::SeqCst))
+            }
+        }
+    }
+
+    pub(super) fn since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+
+    /// Waits for the delay, or only advances the virtual time by it.
+    pub(super) async fn sleep(&self, delay: Duration) {
+        match self {
+            Self::Real => tokio::time::sleep(delay).await,
+            Self::Virtual { skipped, .. } => {
+                skipped.fetch_add(delay.as_nanos() as u64, Ordering::SeqCst);
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+}
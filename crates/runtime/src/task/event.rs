@@ -0,0 +1,439 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::sync::Notify;
+use wasmtime::component::Val;
+use workflow::NodeId;
+
+use super::{LimitExceeded, TaskStatus};
+use crate::{
+    json::{serialize_millis, serialize_option_val, serialize_val, serialize_vals},
+    secrets::Redactor,
+};
+
+/// An `Event` as emitted by a `Task`, stamped with its position in the task's
+/// event stream and the monotonic time elapsed since the task started.
+#[derive(Clone, Debug, Serialize)]
+pub struct EventRecord {
+    #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+    pub elapsed: Duration,
+    #[serde(flatten)]
+    pub event: Event,
+    /// Starts at 0 with `Event::TaskStarted`, without gaps
+    pub sequence: u64,
+}
+
+/// Something that happened while a `Task` ran.
+///
+/// Attempts are numbered from 1, and durations span from the node's first
+/// attempt to its outcome, retries and backoff delays included.
+///
+/// A node with a `for_each` emits `ExecutionFannedOut`,
+/// then the events of every element, stamped with its `index`, and ends with
+/// an `ExecutionSucceeded` without index holding the list of outputs, or with
+/// the failure of one of the elements.
+///
+/// A node with a `repeat` emits the events of every iteration, stamped with
+/// its `iteration`, separated by `ExecutionRepeating`, and ends with the
+/// outcome of the last iteration.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub enum Event {
+    /// The output of the call was cached by a previous call with the same
+    /// parameters, and reused instead of running the call
+    ExecutionCached {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        node_id: NodeId,
+        #[serde(serialize_with = "serialize_val")]
+        output: Val,
+    },
+    /// The node was interrupted because the task was cancelled
+    ExecutionCancelled {
+        attempt: u32,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        node_id: NodeId,
+    },
+    /// A call of the node's component replaying a trace differs from the
+    /// recorded one, by the fields of the call in `divergences`
+    ExecutionDiverged {
+        divergences: BTreeMap<String, Divergence>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        node_id: NodeId,
+    },
+    ExecutionFailed {
+        attempt: u32,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        error: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        node_id: NodeId,
+    },
+    /// The node runs for each of `items` elements
+    ExecutionFannedOut { items: u32, node_id: NodeId },
+    /// The node's component exceeded one of its resource limits
+    ExecutionLimitExceeded {
+        attempt: u32,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        limit_exceeded: LimitExceeded,
+        node_id: NodeId,
+    },
+    /// The node or the whole task exhausted its fuel budget
+    ExecutionOutOfFuel {
+        attempt: u32,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        fuel_consumed: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        node_id: NodeId,
+    },
+    /// The output of the iteration does not satisfy the node's `until`
+    /// condition, and the node runs again after `delay`
+    ExecutionRepeating {
+        #[serde(rename = "delay_ms", serialize_with = "serialize_millis")]
+        delay: Duration,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        iteration: u32,
+        node_id: NodeId,
+        #[serde(serialize_with = "serialize_option_val")]
+        output: Option<Val>,
+    },
+    /// The attempt failed and the node is retried after `delay`
+    ExecutionRetrying {
+        attempt: u32,
+        #[serde(rename = "delay_ms", serialize_with = "serialize_millis")]
+        delay: Duration,
+        error: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        iteration: Option<u32>,
+        node_id: NodeId,
+    },
+    /// The node did not run because its condition was false, `cause` being
+    /// either the node itself or the upstream node skipped along with its
+    /// dependents
+    ExecutionSkipped { cause: NodeId, node_id: NodeId },
+    /// An attempt of the node started
+    ExecutionStarted {
+        attempt: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        iteration: Option<u32>,
+        node_id: NodeId,
+        #[serde(serialize_with = "serialize_vals")]
+        params: Vec<Val>,
+    },
+    ExecutionSucceeded {
+        attempt: u32,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        /// Only known when the runtime meters fuel
+        fuel_consumed: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        node_id: NodeId,
+        /// `None` when the function returns nothing
+        #[serde(serialize_with = "serialize_option_val")]
+        output: Option<Val>,
+    },
+    /// The node or the whole task ran out of time
+    ExecutionTimedOut {
+        attempt: u32,
+        #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        node_id: NodeId,
+        #[serde(rename = "timeout_ms", serialize_with = "serialize_millis")]
+        timeout: Duration,
+    },
+    /// A message the node's component logged through `wasi:logging`
+    GuestLog {
+        context: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        level: LogLevel,
+        message: String,
+        node_id: NodeId,
+    },
+    /// A line the node's component wrote to its stdout or stderr, emitted
+    /// once written, or once the call ends for a last line without line break
+    Log {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+        line: String,
+        node_id: NodeId,
+        stream: LogStream,
+    },
+    /// The task was cancelled, no node runs anymore
+    TaskCancelled,
+    /// Always the last event of a task
+    TaskCompleted {
+        #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        status: TaskStatus,
+    },
+    /// Always the first event of a task
+    TaskStarted,
+}
+
+impl Event {
+    /// Turns an event of the task run by the node `parent` into an event of
+    /// the task running it, dropping the events of the task as a whole.
+    pub(super) fn nested_in(mut self, parent: &NodeId) -> Option<Self> {
+        let nest = |node_id: &mut NodeId| *node_id = NodeId(format!("{}/{}", parent.0, node_id.0));
+        match &mut self {
+            Event::ExecutionCached { node_id, .. }
+            | Event::ExecutionCancelled { node_id, .. }
+            | Event::ExecutionDiverged { node_id, .. }
+            | Event::ExecutionFailed { node_id, .. }
+            | Event::ExecutionFannedOut { node_id, .. }
+            | Event::ExecutionLimitExceeded { node_id, .. }
+            | Event::ExecutionOutOfFuel { node_id, .. }
+            | Event::ExecutionRepeating { node_id, .. }
+            | Event::ExecutionRetrying { node_id, .. }
+            | Event::ExecutionStarted { node_id, .. }
+            | Event::ExecutionSucceeded { node_id, .. }
+            | Event::ExecutionTimedOut { node_id, .. }
+            | Event::GuestLog { node_id, .. }
+            | Event::Log { node_id, .. } => nest(node_id),
+            Event::ExecutionSkipped { cause, node_id } => {
+                nest(cause);
+                nest(node_id);
+            }
+            Event::TaskCancelled | Event::TaskCompleted { .. } | Event::TaskStarted => {
+                return None;
+            }
+        }
+        Some(self)
+    }
+
+    /// Replaces the values of secrets in the text and the values of an event.
+    pub(super) fn redacted(mut self, redactor: &Redactor) -> Self {
+        match &mut self {
+            Event::ExecutionFailed { error, .. } | Event::ExecutionRetrying { error, .. } => {
+                *error = redactor.redact(error);
+            }
+            Event::ExecutionCached { output, .. } => *output = redactor.redact_val(output),
+            Event::ExecutionDiverged { divergences, .. } => {
+                for divergence in divergences.values_mut() {
+                    divergence.recorded = redactor.redact_json(&divergence.recorded);
+                    divergence.replayed = redactor.redact_json(&divergence.replayed);
+                }
+            }
+            Event::ExecutionRepeating { output, .. } | Event::ExecutionSucceeded { output, .. } => {
+                if let Some(output) = output {
+                    *output = redactor.redact_val(output);
+                }
+            }
+            Event::ExecutionStarted { params, .. } => {
+                for param in params {
+                    *param = redactor.redact_val(param);
+                }
+            }
+            Event::GuestLog {
+                context, message, ..
+            } => {
+                *context = redactor.redact(context);
+                *message = redactor.redact(message);
+            }
+            Event::Log { line, .. } => *line = redactor.redact(line),
+            Event::ExecutionCancelled { .. }
+            | Event::ExecutionFannedOut { .. }
+            | Event::ExecutionLimitExceeded { .. }
+            | Event::ExecutionOutOfFuel { .. }
+            | Event::ExecutionSkipped { .. }
+            | Event::ExecutionTimedOut { .. }
+            | Event::TaskCancelled
+            | Event::TaskCompleted { .. }
+            | Event::TaskStarted => {}
+        }
+        self
+    }
+}
+
+/// A field of a call of a component, as recorded in a trace and as replayed.
+#[derive(Clone, Debug, Serialize)]
+pub struct Divergence {
+    pub recorded: serde_json::Value,
+    pub replayed: serde_json::Value,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Critical,
+    Debug,
+    Error,
+    Info,
+    Trace,
+    Warn,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogStream {
+    Stderr,
+    Stdout,
+}
+
+/// Append-only log of the events of a `Task`.
+///
+/// Appending never blocks nor fails, and every subscription reads the whole
+/// log at its own pace, so events are neither lost nor reordered.
+#[derive(Clone, Debug, Default)]
+pub(super) struct EventLog(Arc<Shared>);
+
+#[derive(Debug, Default)]
+struct Shared {
+    notify: Notify,
+    state: Mutex<LogState>,
+}
+
+#[derive(Debug, Default)]
+struct LogState {
+    closed: bool,
+    records: Vec<EventRecord>,
+}
+
+impl EventLog {
+    /// Appends the event, numbering it after the last one.
+    pub(super) fn append(&self, elapsed: Duration, event: Event) {
+        let mut state = self.0.state.lock().unwrap();
+        let sequence = state.records.len() as u64;
+        state.records.push(EventRecord {
+            elapsed,
+            event,
+            sequence,
+        });
+        drop(state);
+        self.0.notify.notify_waiters();
+    }
+
+    /// Ends the subscriptions once they received every event.
+    pub(super) fn close(&self) {
+        self.0.state.lock().unwrap().closed = true;
+        self.0.notify.notify_waiters();
+    }
+
+    pub(super) fn subscribe(&self, sequence: u64) -> Subscription {
+        Subscription {
+            log: self.clone(),
+            sequence,
+        }
+    }
+}
+
+/// Reads the events of a `Task` in order, starting from a given sequence
+/// number, however late it subscribed.
+#[derive(Debug)]
+pub struct Subscription {
+    log: EventLog,
+    sequence: u64,
+}
+
+impl Subscription {
+    /// Waits for the next event, returning `None` once the task completed and
+    /// every event was received.
+    pub async fn recv(&mut self) -> Option<EventRecord> {
+        loop {
+            // Registered before reading, so that no append is missed
+            let notified = self.log.0.notify.notified();
+            {
+                let state = self.log.0.state.lock().unwrap();
+                if let Some(record) = state.records.get(self.sequence as usize) {
+                    self.sequence += 1;
+                    return Some(record.clone());
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_late_subscription_replays_history() {
+        let log = EventLog::default();
+        log.append(Duration::ZERO, Event::TaskStarted);
+        let mut live = log.subscribe(0);
+        assert!(matches!(
+            live.recv().await.unwrap().event,
+            Event::TaskStarted
+        ));
+
+        let completion = tokio::spawn({
+            let log = log.clone();
+            async move {
+                log.append(
+                    Duration::from_millis(1),
+                    Event::TaskCompleted {
+                        duration: Duration::from_millis(1),
+                        status: TaskStatus::Succeeded,
+                    },
+                );
+                log.close();
+            }
+        });
+        let record = live.recv().await.unwrap();
+        assert_eq!(record.sequence, 1);
+        assert!(live.recv().await.is_none());
+        completion.await.unwrap();
+
+        let mut late = log.subscribe(0);
+        assert_eq!(late.recv().await.unwrap().sequence, 0);
+        assert_eq!(late.recv().await.unwrap().sequence, 1);
+        assert!(late.recv().await.is_none());
+        assert!(log.subscribe(1).recv().await.is_some());
+    }
+
+    #[test]
+    fn test_divergences_are_redacted() {
+        let event = Event::ExecutionDiverged {
+            divergences: BTreeMap::from([(
+                "params".to_string(),
+                Divergence {
+                    recorded: serde_json::json!(["token s3cr3t"]),
+                    replayed: serde_json::json!(["token other"]),
+                },
+            )]),
+            index: None,
+            node_id: NodeId("call".to_string()),
+        };
+        let Event::ExecutionDiverged { divergences, .. } =
+            event.redacted(&Redactor::new(["s3cr3t"]))
+        else {
+            unreachable!();
+        };
+        assert_eq!(
+            divergences["params"].recorded,
+            serde_json::json!(["token ***"])
+        );
+        assert_eq!(
+            divergences["params"].replayed,
+            serde_json::json!(["token other"])
+        );
+    }
+}
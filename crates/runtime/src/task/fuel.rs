@@ -0,0 +1,155 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use wasmtime::{Result, Store, Trap, UpdateDeadline};
+
+use crate::state::State;
+
+/// Fuel left to a whole task, shared by the nodes running concurrently.
+#[derive(Clone, Debug)]
+pub(super) struct Tank(Arc<AtomicU64>);
+
+impl Tank {
+    pub(super) fn new(fuel: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(fuel)))
+    }
+
+    pub(super) fn level(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Removes fuel from the tank, returning whether it held that much, the
+    /// tank being left empty otherwise.
+    fn drain(&self, fuel: u64) -> bool {
+        let previous = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |level| {
+                Some(level.saturating_sub(fuel))
+            })
+            .unwrap();
+        previous >= fuel
+    }
+}
+
+/// Meters the fuel consumed by a single call.
+pub(super) struct Meter {
+    budget: u64,
+    drained: Arc<AtomicU64>,
+    tank: Tank,
+}
+
+impl Meter {
+    /// Fills the store with the node's budget, bounded by what is left in the
+    /// task's tank. The tank is drained at every epoch tick, so that nodes
+    /// running concurrently trap as soon as it is empty.
+    pub(super) fn install(
+        store: &mut Store<State>,
+        tank: &Tank,
+        budget: Option<u64>,
+    ) -> Result<Self> {
+        let budget = budget.unwrap_or(u64::MAX).min(tank.level());
+        store.set_fuel(budget)?;
+
+        let drained = Arc::new(AtomicU64::new(0));
+        let meter = Self {
+            budget,
+            drained: drained.clone(),
+            tank: tank.clone(),
+        };
+
+        let tank = tank.clone();
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(move |store| {
+            let consumed = budget - store.get_fuel()?;
+            let previous = drained.swap(consumed, Ordering::SeqCst);
+            if tank.drain(consumed - previous) {
+                Ok(UpdateDeadline::Yield(1))
+            } else {
+                Err(Trap::OutOfFuel.into())
+            }
+        });
+
+        Ok(meter)
+    }
+
+    /// Drains what the call consumed since the last epoch tick and returns
+    /// the fuel consumed by the whole call.
+    pub(super) fn settle(&self, store: &Store<State>) -> u64 {
+        let consumed = self.budget - store.get_fuel().unwrap_or(0);
+        let previous = self.drained.swap(consumed, Ordering::SeqCst);
+        self.tank.drain(consumed.saturating_sub(previous));
+        consumed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Runtime, RuntimeConfig,
+        task::{Event, NodeStatus, TaskStatus},
+        testing,
+    };
+
+    #[test]
+    fn test_tank_drains_to_exactly_empty() {
+        let tank = Tank::new(10);
+        assert!(tank.drain(4));
+        assert!(tank.drain(6));
+        assert_eq!(tank.level(), 0);
+        assert!(tank.drain(0));
+        assert!(!tank.drain(1));
+        assert_eq!(tank.level(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_meter_enforces_the_node_budget() {
+        let mut runtime = Runtime::with_config(RuntimeConfig {
+            consume_fuel: true,
+            ..RuntimeConfig::default()
+        })
+        .unwrap();
+        let burn = async |runtime: &mut Runtime, fuel: Option<u64>| {
+            let fuel = fuel.map_or(String::new(), |fuel| format!("fuel: {fuel},"));
+            let yaml = format!(
+                "
+                dependencies: {{ math: $math }}
+                edges: []
+                nodes:
+                  burn: {{ run: burn, use: math, {fuel} with: {{ n: 1000 }} }}
+                "
+            );
+            testing::run(runtime, &yaml).await
+        };
+
+        let (report, events) = burn(&mut runtime, None).await;
+        assert_eq!(report.status, TaskStatus::Succeeded);
+        let consumed = events
+            .iter()
+            .find_map(|event| match event {
+                Event::ExecutionSucceeded { fuel_consumed, .. } => *fuel_consumed,
+                _ => None,
+            })
+            .unwrap();
+
+        // A budget of exactly what the call consumes is enough
+        let (report, _) = burn(&mut runtime, Some(consumed)).await;
+        assert_eq!(report.status, TaskStatus::Succeeded);
+
+        let (report, events) = burn(&mut runtime, Some(consumed / 2)).await;
+        assert_eq!(report.status, TaskStatus::Failed);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::ExecutionOutOfFuel { fuel_consumed, .. } if *fuel_consumed == consumed / 2
+        )));
+        assert!(
+            !events
+                .iter()
+                .any(|event| matches!(event, Event::ExecutionFailed { .. }))
+        );
+        let node = &report.nodes[&workflow::NodeId("burn".to_string())];
+        assert_eq!(node.status, NodeStatus::OutOfFuel);
+    }
+}
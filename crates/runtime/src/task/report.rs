@@ -0,0 +1,77 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::Serialize;
+use wasmtime::component::Val;
+use workflow::{NodeId, OutputName};
+
+use crate::json::{serialize_millis, serialize_option_val, serialize_val_map};
+
+/// Summary of a `Task` run, returned by `Task::run`.
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskReport {
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+    /// Every function node of the workflow, including those that never ran
+    pub nodes: HashMap<NodeId, NodeReport>,
+    /// Outputs of the workflow whose node succeeded
+    #[serde(serialize_with = "serialize_val_map")]
+    pub outputs: HashMap<OutputName, Val>,
+    pub status: TaskStatus,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    /// The task was cancelled before every node succeeded
+    Cancelled,
+    /// At least one node did not succeed
+    Failed,
+    Succeeded,
+}
+
+/// Outcome of a single node of a `Task`.
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeReport {
+    /// 0 when the node never ran
+    pub attempts: u32,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+    #[serde(serialize_with = "serialize_option_val")]
+    pub output: Option<Val>,
+    pub status: NodeStatus,
+}
+
+impl NodeReport {
+    pub(super) fn not_run() -> Self {
+        Self {
+            attempts: 0,
+            duration: Duration::ZERO,
+            output: None,
+            status: NodeStatus::NotRun,
+        }
+    }
+
+    pub(super) fn skipped() -> Self {
+        Self {
+            status: NodeStatus::Skipped,
+            ..Self::not_run()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeStatus {
+    Cancelled,
+    Failed,
+    LimitExceeded,
+    /// The node never ran, because the task stopped before its inputs were
+    /// ready
+    NotRun,
+    OutOfFuel,
+    /// The node never ran, because its condition (or that of an upstream node
+    /// skipping its dependents) was false
+    Skipped,
+    Succeeded,
+    TimedOut,
+}
@@ -0,0 +1,886 @@
+//! Expressions embedded in the nodes' `with` values, as `${{ <expression> }}`.
+//!
+//! An expression is one of:
+//! - a reference to an input of the workflow, `inputs.<name>`, to the output
+//!   of a node, `nodes.<id>.output`, to a secret, `secrets.<name>`, or, in
+//!   nodes with a `for_each`, to the current element, `item`, and its
+//!   position, `index`, followed by any number of accessors: `.<field>` for
+//!   record fields, `.ok`/`.err` for results, `.<case>` for variant payloads
+//!   and `[<index>]` for lists and tuples, options being unwrapped along the
+//!   way and `none` accessing to `none`;
+//! - a literal: `'text'`, `"text"`, `42`, `true` or `false`;
+//! - a call of a function: `case(variant)`, `concat(..)`,
+//!   `default(option, fallback)`, `json(value)`, `len(string or list)`,
+//!   `lower(string)`, `trim(string)`, `upper(string)` or `wave(value)`, the
+//!   functions but `default` taking no secret, which could not be redacted
+//!   from their output anymore;
+//! - the concatenation of expressions as text, `a + b`;
+//! - the comparison of expressions taking no secret, `a == b` or `a != b`,
+//!   values being equal when their JSON representations are, and the negation
+//!   of one, `!a`.
+//!
+//! A `with` value made of a single expression passes the expression's value,
+//! converted to the parameter's type. Otherwise, the expressions are rendered
+//! as text within the surrounding text: strings as they are, other values as
+//! WAVE. The resulting text is passed as is to a string parameter, and parsed
+//! as WAVE for any other parameter.
+//!
+//! `$${{` stands for a literal `${{`, starting no expression.
+
+use std::collections::HashMap;
+
+use wasmtime::component::{Type, Val};
+use workflow::{InputName, NodeId, SecretName};
+
+use crate::{json, wit};
+
+/// A `with` value embedding expressions.
+#[derive(Clone, Debug)]
+pub struct Template(Body);
+
+#[derive(Clone, Debug)]
+enum Body {
+    /// The value of a single expression
+    Expression(Expr),
+    /// Text with embedded expressions
+    Interpolation(Vec<Part>),
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Expr(Expr),
+    Text(String),
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Call(Function, Vec<Expr>),
+    Concat(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Literal(Val),
+    Ne(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Reference(Reference, Vec<Accessor>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Reference {
+    Index,
+    Input(InputName),
+    Item,
+    Node(NodeId),
+    Secret(SecretName),
+}
+
+#[derive(Clone, Debug)]
+enum Accessor {
+    Field(String),
+    Index(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+    Case,
+    Concat,
+    Default,
+    Json,
+    Len,
+    Lower,
+    Trim,
+    Upper,
+    Wave,
+}
+
+impl Template {
+    /// Parses the template of a `with` value, `None` when it holds no
+    /// expression.
+    pub(crate) fn parse(source: &str) -> Result<Option<Self>, String> {
+        if !source.contains("${{") {
+            return Ok(None);
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("${{") {
+            if let Some(text) = rest[..start].strip_suffix('$') {
+                parts.push(Part::Text(format!("{text}${{{{")));
+                rest = &rest[start + 3..];
+                continue;
+            }
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let mut parser = Parser {
+                rest: &rest[start + 3..],
+            };
+            let expr = parser.expr()?;
+            parser.skip_whitespace();
+            rest = parser
+                .rest
+                .strip_prefix("}}")
+                .ok_or_else(|| format!("Expected '}}}}', found {:?}", parser.rest))?;
+            parts.push(Part::Expr(expr));
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        // Surrounding whitespace does not turn an expression into text
+        let is_expression = parts
+            .iter()
+            .filter(|part| matches!(part, Part::Expr(_)))
+            .count()
+            == 1
+            && parts.iter().all(|part| match part {
+                Part::Expr(_) => true,
+                Part::Text(text) => text.trim().is_empty(),
+            });
+        Ok(Some(match is_expression {
+            true => {
+                let Some(Part::Expr(expr)) =
+                    parts.into_iter().find(|part| matches!(part, Part::Expr(_)))
+                else {
+                    unreachable!()
+                };
+                Template(Body::Expression(expr))
+            }
+            false => Template(Body::Interpolation(parts)),
+        }))
+    }
+
+    /// Parses an expression written on its own, such as the condition of a
+    /// node, which may omit the `${{ }}` around it.
+    pub(crate) fn parse_expression(source: &str) -> Result<Self, String> {
+        match Self::parse(source)? {
+            Some(template) => Ok(template),
+            None => Ok(Self::parse(&format!("${{{{ {source} }}}}"))?.unwrap()),
+        }
+    }
+
+    /// Returns the input of the workflow the template is made of, if any.
+    pub(crate) fn input(&self) -> Option<&InputName> {
+        match &self.0 {
+            Body::Expression(Expr::Reference(Reference::Input(input_name), accessors))
+                if accessors.is_empty() =>
+            {
+                Some(input_name)
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks that the template is a single expression evaluating to a list,
+    /// returning the type of its elements when known ahead of time.
+    pub(crate) fn check_items(&self, scope: &Scope<&Option<Type>>) -> Result<Option<Type>, String> {
+        let Body::Expression(expr) = &self.0 else {
+            return Err("Expected a single expression".to_string());
+        };
+        match expr.check(scope)? {
+            Kind::Type(Type::List(list)) => Ok(Some(list.ty())),
+            Kind::Unknown => Ok(None),
+            _ => Err("The expression is not a list".to_string()),
+        }
+    }
+
+    /// Evaluates a template checked by `check_items` into its elements.
+    pub(crate) fn eval_items(&self, scope: &Scope<&Val>) -> Result<Vec<Val>, String> {
+        let Body::Expression(expr) = &self.0 else {
+            unreachable!("items are single expressions");
+        };
+        match expr.eval(scope)? {
+            Val::List(vals) => Ok(vals),
+            val => Err(format!("Expected a list, got {}", render(&val))),
+        }
+    }
+
+    /// Returns every input and node the template refers to.
+    pub(crate) fn references(&self) -> Vec<&Reference> {
+        let mut references = Vec::new();
+        match &self.0 {
+            Body::Expression(expr) => expr.collect_references(&mut references),
+            Body::Interpolation(parts) => {
+                for part in parts {
+                    if let Part::Expr(expr) = part {
+                        expr.collect_references(&mut references);
+                    }
+                }
+            }
+        }
+        references
+    }
+
+    /// Checks the references of the template and, where its type is known
+    /// ahead of time, that its value fits the parameter's type.
+    pub(crate) fn check(&self, scope: &Scope<&Option<Type>>, ty: &Type) -> Result<(), String> {
+        match &self.0 {
+            Body::Expression(expr) => {
+                let fits = match expr.check(scope)? {
+                    Kind::Bool => matches!(ty, Type::Bool),
+                    Kind::Integer => matches!(
+                        ty,
+                        Type::S8
+                            | Type::U8
+                            | Type::S16
+                            | Type::U16
+                            | Type::S32
+                            | Type::U32
+                            | Type::S64
+                            | Type::U64
+                    ),
+                    Kind::String => matches!(ty, Type::String | Type::Char | Type::Enum(_)),
+                    Kind::Type(expr_ty) => wit::same_shape(&expr_ty, ty),
+                    Kind::Unknown => true,
+                };
+                match fits {
+                    true => Ok(()),
+                    false => Err(format!("The expression does not match the type {ty:?}")),
+                }
+            }
+            Body::Interpolation(parts) => {
+                for part in parts {
+                    if let Part::Expr(expr) = part {
+                        expr.check(scope)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Evaluates the template into a value of the parameter's type.
+    pub(crate) fn eval(&self, scope: &Scope<&Val>, ty: &Type) -> Result<Val, String> {
+        match &self.0 {
+            Body::Expression(expr) => {
+                let val = expr.eval(scope)?;
+                json::json_to_val(&json::val_to_json(&val), ty).map_err(|e| e.to_string())
+            }
+            Body::Interpolation(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    match part {
+                        Part::Expr(expr) => text.push_str(&render(&expr.eval(scope)?)),
+                        Part::Text(part) => text.push_str(part),
+                    }
+                }
+                match ty {
+                    Type::String => Ok(Val::String(text)),
+                    // The text may hold secrets, so it is not shown
+                    ty => Val::from_wave(ty, &text)
+                        .map_err(|e| format!("The text is not a valid WAVE value: {e}")),
+                }
+            }
+        }
+    }
+}
+
+/// What the references of a template resolve to: types when the workflow is
+/// checked, values when the template is evaluated.
+pub(crate) struct Scope<T> {
+    /// Position of the current element, in nodes with a `for_each`
+    pub(crate) index: Option<T>,
+    pub(crate) inputs: HashMap<InputName, T>,
+    /// Current element, in nodes with a `for_each`
+    pub(crate) item: Option<T>,
+    pub(crate) nodes: HashMap<NodeId, T>,
+    pub(crate) secrets: HashMap<SecretName, T>,
+}
+
+impl<T: Copy> Scope<T> {
+    fn resolve(&self, reference: &Reference) -> Result<T, String> {
+        let iteration = |val: Option<T>, name| {
+            val.ok_or_else(|| format!("{name:?} is only defined in nodes with a for_each"))
+        };
+        match reference {
+            Reference::Index => iteration(self.index, "index"),
+            Reference::Input(input_name) => self
+                .inputs
+                .get(input_name)
+                .copied()
+                .ok_or_else(|| format!("Undefined input: inputs.{}", input_name.0)),
+            Reference::Item => iteration(self.item, "item"),
+            Reference::Node(node_id) => self
+                .nodes
+                .get(node_id)
+                .copied()
+                .ok_or_else(|| format!("Undefined output: nodes.{}.output", node_id.0)),
+            Reference::Secret(secret_name) => self
+                .secrets
+                .get(secret_name)
+                .copied()
+                .ok_or_else(|| format!("Undefined secret: secrets.{}", secret_name.0)),
+        }
+    }
+}
+
+/// What is known of the value of an expression before it is evaluated.
+enum Kind {
+    Bool,
+    Integer,
+    String,
+    Type(Type),
+    Unknown,
+}
+
+impl Expr {
+    fn collect_references<'a>(&'a self, references: &mut Vec<&'a Reference>) {
+        match self {
+            Expr::Call(_, args) => {
+                for arg in args {
+                    arg.collect_references(references);
+                }
+            }
+            Expr::Concat(left, right) | Expr::Eq(left, right) | Expr::Ne(left, right) => {
+                left.collect_references(references);
+                right.collect_references(references);
+            }
+            Expr::Literal(_) => {}
+            Expr::Not(expr) => expr.collect_references(references),
+            Expr::Reference(reference, _) => references.push(reference),
+        }
+    }
+
+    fn has_secret(&self) -> bool {
+        let mut references = Vec::new();
+        self.collect_references(&mut references);
+        references
+            .iter()
+            .any(|reference| matches!(reference, Reference::Secret(_)))
+    }
+
+    fn check(&self, scope: &Scope<&Option<Type>>) -> Result<Kind, String> {
+        match self {
+            Expr::Call(function, args) => {
+                let kinds = args
+                    .iter()
+                    .map(|arg| arg.check(scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                function.check_arity(args.len())?;
+                if function.reveals() && args.iter().any(Expr::has_secret) {
+                    return Err(format!(
+                        "{function:?} cannot take a secret, which it would reveal"
+                    ));
+                }
+                Ok(match function {
+                    Function::Default => Kind::Unknown,
+                    Function::Len => Kind::Integer,
+                    Function::Lower | Function::Trim | Function::Upper => {
+                        if let Kind::Bool | Kind::Integer = kinds[0] {
+                            return Err(format!("{function:?} expects a string"));
+                        }
+                        Kind::String
+                    }
+                    Function::Case => {
+                        let is_variant = match &kinds[0] {
+                            Kind::Type(ty) => matches!(
+                                ty,
+                                Type::Enum(_)
+                                    | Type::Option(_)
+                                    | Type::Result(_)
+                                    | Type::Variant(_)
+                            ),
+                            Kind::Unknown => true,
+                            _ => false,
+                        };
+                        if !is_variant {
+                            return Err(format!("{function:?} expects a variant"));
+                        }
+                        Kind::String
+                    }
+                    Function::Concat | Function::Json | Function::Wave => Kind::String,
+                })
+            }
+            Expr::Concat(left, right) => {
+                left.check(scope)?;
+                right.check(scope)?;
+                Ok(Kind::String)
+            }
+            Expr::Eq(left, right) | Expr::Ne(left, right) => {
+                left.check(scope)?;
+                right.check(scope)?;
+                if left.has_secret() || right.has_secret() {
+                    return Err("Comparisons cannot take a secret, which they would reveal".into());
+                }
+                Ok(Kind::Bool)
+            }
+            Expr::Not(expr) => match expr.check(scope)? {
+                Kind::Bool | Kind::Type(Type::Bool) | Kind::Unknown => Ok(Kind::Bool),
+                _ => Err("Negations expect a bool".to_string()),
+            },
+            Expr::Literal(Val::Bool(_)) => Ok(Kind::Bool),
+            Expr::Literal(Val::S64(_)) => Ok(Kind::Integer),
+            Expr::Literal(_) => Ok(Kind::String),
+            Expr::Reference(reference, accessors) => {
+                let Some(mut ty) = scope.resolve(reference)?.clone() else {
+                    return Ok(Kind::Unknown);
+                };
+                for accessor in accessors {
+                    ty = access_type(ty, accessor)?;
+                }
+                Ok(Kind::Type(ty))
+            }
+        }
+    }
+
+    fn eval(&self, scope: &Scope<&Val>) -> Result<Val, String> {
+        match self {
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                function.call(args)
+            }
+            Expr::Concat(left, right) => Ok(Val::String(
+                render(&left.eval(scope)?) + &render(&right.eval(scope)?),
+            )),
+            Expr::Eq(left, right) => Ok(Val::Bool(equals(&left.eval(scope)?, &right.eval(scope)?))),
+            Expr::Literal(val) => Ok(val.clone()),
+            Expr::Ne(left, right) => {
+                Ok(Val::Bool(!equals(&left.eval(scope)?, &right.eval(scope)?)))
+            }
+            Expr::Not(expr) => match expr.eval(scope)? {
+                Val::Bool(b) => Ok(Val::Bool(!b)),
+                val => Err(format!("Negations expect a bool, got {}", render(&val))),
+            },
+            Expr::Reference(reference, accessors) => {
+                let mut val = scope.resolve(reference)?.clone();
+                for accessor in accessors {
+                    val = access_val(val, accessor)?;
+                }
+                Ok(val)
+            }
+        }
+    }
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "case" => Function::Case,
+            "concat" => Function::Concat,
+            "default" => Function::Default,
+            "json" => Function::Json,
+            "len" => Function::Len,
+            "lower" => Function::Lower,
+            "trim" => Function::Trim,
+            "upper" => Function::Upper,
+            "wave" => Function::Wave,
+            _ => return None,
+        })
+    }
+
+    /// Whether the function's output reveals something of its arguments,
+    /// such as their text or their length.
+    fn reveals(&self) -> bool {
+        !matches!(self, Function::Default)
+    }
+
+    fn check_arity(&self, arity: usize) -> Result<(), String> {
+        let expected = match self {
+            Function::Concat => return Ok(()),
+            Function::Default => 2,
+            _ => 1,
+        };
+        match arity == expected {
+            true => Ok(()),
+            false => Err(format!(
+                "{self:?} expects {expected} argument(s), got {arity}"
+            )),
+        }
+    }
+
+    fn call(&self, mut args: Vec<Val>) -> Result<Val, String> {
+        let string = |val: &Val| match val {
+            Val::String(s) => Ok(s.clone()),
+            val => Err(format!("{self:?} expects a string, got {}", render(val))),
+        };
+        Ok(match self {
+            Function::Case => Val::String(match &args[0] {
+                Val::Enum(case) | Val::Variant(case, _) => case.clone(),
+                Val::Option(option) => (if option.is_some() { "some" } else { "none" }).to_string(),
+                Val::Result(result) => (if result.is_ok() { "ok" } else { "err" }).to_string(),
+                val => Err(format!("Case expects a variant, got {}", render(val)))?,
+            }),
+            Function::Concat => Val::String(args.iter().map(render).collect()),
+            Function::Default => match args.swap_remove(0) {
+                Val::Option(None) => args.swap_remove(0),
+                Val::Option(Some(val)) => *val,
+                val => val,
+            },
+            Function::Json => Val::String(json::val_to_json(&args[0]).to_string()),
+            Function::Len => match &args[0] {
+                Val::String(s) => Val::U64(s.chars().count() as u64),
+                Val::List(vals) => Val::U64(vals.len() as u64),
+                val => Err(format!(
+                    "Len expects a string or a list, got {}",
+                    render(val)
+                ))?,
+            },
+            Function::Lower => Val::String(string(&args[0])?.to_lowercase()),
+            Function::Trim => Val::String(string(&args[0])?.trim().to_string()),
+            Function::Upper => Val::String(string(&args[0])?.to_uppercase()),
+            Function::Wave => Val::String(wave(&args[0])),
+        })
+    }
+}
+
+fn access_type(ty: Type, accessor: &Accessor) -> Result<Type, String> {
+    let payload =
+        |ty: Option<Type>, name: &str| ty.ok_or_else(|| format!("{name:?} has no payload"));
+    match (ty, accessor) {
+        (Type::Option(option), accessor) => access_type(option.ty(), accessor),
+        (Type::Record(record), Accessor::Field(name)) => record
+            .fields()
+            .find(|field| field.name == name)
+            .map(|field| field.ty)
+            .ok_or_else(|| format!("Unknown field: {name:?}")),
+        (Type::Result(result), Accessor::Field(name)) if name == "ok" => payload(result.ok(), name),
+        (Type::Result(result), Accessor::Field(name)) if name == "err" => {
+            payload(result.err(), name)
+        }
+        (Type::Variant(variant), Accessor::Field(name)) => {
+            let case = variant
+                .cases()
+                .find(|case| case.name == name)
+                .ok_or_else(|| format!("Unknown case: {name:?}"))?;
+            payload(case.ty, name)
+        }
+        (Type::List(list), Accessor::Index(_)) => Ok(list.ty()),
+        (Type::Tuple(tuple), Accessor::Index(index)) => tuple
+            .types()
+            .nth(*index)
+            .ok_or_else(|| format!("Index out of the tuple: {index}")),
+        (ty, accessor) => Err(format!("Cannot access {accessor:?} in {ty:?}")),
+    }
+}
+
+fn access_val(val: Val, accessor: &Accessor) -> Result<Val, String> {
+    let missing = || format!("{accessor:?} is missing");
+    match (val, accessor) {
+        (Val::Option(Some(val)), accessor) => access_val(*val, accessor),
+        (val @ Val::Option(None), _) => Ok(val),
+        (Val::Record(fields), Accessor::Field(name)) => fields
+            .into_iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, val)| val)
+            .ok_or_else(missing),
+        (Val::Result(Ok(payload)), Accessor::Field(name)) if name == "ok" => {
+            payload.map(|val| *val).ok_or_else(missing)
+        }
+        (Val::Result(Err(payload)), Accessor::Field(name)) if name == "err" => {
+            payload.map(|val| *val).ok_or_else(missing)
+        }
+        (Val::Variant(case, payload), Accessor::Field(name)) if &case == name => {
+            payload.map(|val| *val).ok_or_else(missing)
+        }
+        (Val::List(vals) | Val::Tuple(vals), Accessor::Index(index)) => {
+            vals.into_iter().nth(*index).ok_or_else(missing)
+        }
+        (val, accessor) => Err(format!("Cannot access {accessor:?} in {}", render(&val))),
+    }
+}
+
+/// Compares values through their JSON representation, so that integers of
+/// different types and case names compare as expected.
+fn equals(left: &Val, right: &Val) -> bool {
+    json::val_to_json(left) == json::val_to_json(right)
+}
+
+/// Renders a value as text: strings as they are, other values as WAVE.
+fn render(val: &Val) -> String {
+    match val {
+        Val::Char(c) => c.to_string(),
+        Val::String(s) => s.clone(),
+        val => wave(val),
+    }
+}
+
+pub(crate) fn wave(val: &Val) -> String {
+    val.to_wave().unwrap_or_else(|_| format!("{val:?}"))
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(format!("Expected {token:?}, found {:?}", self.rest)),
+        }
+    }
+
+    fn ident(&mut self) -> Result<&str, String> {
+        self.skip_whitespace();
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(format!("Expected a name, found {:?}", self.rest));
+        }
+        let (ident, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(ident)
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let left = self.concat()?;
+        if self.eat("==") {
+            Ok(Expr::Eq(Box::new(left), Box::new(self.concat()?)))
+        } else if self.eat("!=") {
+            Ok(Expr::Ne(Box::new(left), Box::new(self.concat()?)))
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn concat(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+        while self.eat("+") {
+            expr = Expr::Concat(Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        self.skip_whitespace();
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.term()?)));
+        }
+        if self.eat("(") {
+            let expr = self.expr()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if let Some(quote) = self.rest.chars().next().filter(|c| *c == '\'' || *c == '"') {
+            let end = self.rest[1..]
+                .find(quote)
+                .ok_or_else(|| format!("Unterminated string: {:?}", self.rest))?;
+            let text = self.rest[1..=end].to_string();
+            self.rest = &self.rest[end + 2..];
+            return Ok(Expr::Literal(Val::String(text)));
+        }
+
+        let ident = self.ident()?;
+        if let Ok(integer) = ident.parse() {
+            return Ok(Expr::Literal(Val::S64(integer)));
+        }
+        match ident {
+            "true" => Ok(Expr::Literal(Val::Bool(true))),
+            "false" => Ok(Expr::Literal(Val::Bool(false))),
+            "index" => Ok(Expr::Reference(Reference::Index, self.accessors()?)),
+            "item" => Ok(Expr::Reference(Reference::Item, self.accessors()?)),
+            "inputs" => {
+                self.expect(".")?;
+                let input_name = InputName(self.ident()?.to_string());
+                Ok(Expr::Reference(
+                    Reference::Input(input_name),
+                    self.accessors()?,
+                ))
+            }
+            "nodes" => {
+                self.expect(".")?;
+                let node_id = NodeId(self.ident()?.to_string());
+                self.expect(".")?;
+                match self.ident()? {
+                    "output" => Ok(Expr::Reference(Reference::Node(node_id), self.accessors()?)),
+                    other => Err(format!("Expected \"output\", found {other:?}")),
+                }
+            }
+            "secrets" => {
+                self.expect(".")?;
+                let secret_name = SecretName(self.ident()?.to_string());
+                Ok(Expr::Reference(
+                    Reference::Secret(secret_name),
+                    self.accessors()?,
+                ))
+            }
+            name => {
+                let function = Function::from_name(name)
+                    .ok_or_else(|| format!("Unknown function: {name:?}"))?;
+                self.expect("(")?;
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Expr::Call(function, args))
+            }
+        }
+    }
+
+    fn accessors(&mut self) -> Result<Vec<Accessor>, String> {
+        let mut accessors = Vec::new();
+        loop {
+            if self.rest.starts_with('.') {
+                self.rest = &self.rest[1..];
+                accessors.push(Accessor::Field(self.ident()?.to_string()));
+            } else if self.rest.starts_with('[') {
+                self.rest = &self.rest[1..];
+                let index = self.ident()?;
+                let index = index
+                    .parse()
+                    .map_err(|_| format!("Invalid index: {index:?}"))?;
+                self.expect("]")?;
+                accessors.push(Accessor::Index(index));
+            } else {
+                return Ok(accessors);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_interpolation() {
+        let template =
+            Template::parse("${{ inputs.base }}/users/${{ nodes.fetch.output.id + '.json' }}")
+                .unwrap()
+                .unwrap();
+        let base = Val::String("https://example.com".to_string());
+        let output = Val::Record(vec![("id".to_string(), Val::U32(7))]);
+        let token = Val::String("s3cr3t".to_string());
+        let scope = Scope {
+            index: None,
+            inputs: HashMap::from([(InputName("base".to_string()), &base)]),
+            item: None,
+            nodes: HashMap::from([(NodeId("fetch".to_string()), &output)]),
+            secrets: HashMap::from([(SecretName("token".to_string()), &token)]),
+        };
+        assert_eq!(
+            template.eval(&scope, &Type::String),
+            Ok(Val::String("https://example.com/users/7.json".to_string()))
+        );
+        assert_eq!(
+            Template::parse("${{ upper(nodes.missing.output) }}")
+                .unwrap()
+                .unwrap()
+                .eval(&scope, &Type::String),
+            Err("Undefined output: nodes.missing.output".to_string())
+        );
+        assert_eq!(
+            Template::parse("Bearer ${{ secrets.token }}")
+                .unwrap()
+                .unwrap()
+                .eval(&scope, &Type::String),
+            Ok(Val::String("Bearer s3cr3t".to_string()))
+        );
+        let error = Template::parse("[${{ secrets.token }}]")
+            .unwrap()
+            .unwrap()
+            .eval(&scope, &Type::U32)
+            .unwrap_err();
+        assert!(!error.contains("s3cr3t"), "{error}");
+        assert_eq!(
+            Template::parse("$${{ inputs.base }} is ${{ inputs.base }}")
+                .unwrap()
+                .unwrap()
+                .eval(&scope, &Type::String),
+            Ok(Val::String(
+                "${{ inputs.base }} is https://example.com".to_string()
+            ))
+        );
+        assert_eq!(
+            Template::parse("$${{ secrets.token }}")
+                .unwrap()
+                .unwrap()
+                .references(),
+            Vec::<&Reference>::new()
+        );
+    }
+
+    #[test]
+    fn test_eval_condition() {
+        let output = Val::Variant("approved".to_string(), Some(Box::new(Val::U32(3))));
+        let none = Val::Option(None);
+        let scope = Scope {
+            index: None,
+            inputs: HashMap::new(),
+            item: None,
+            nodes: HashMap::from([
+                (NodeId("review".to_string()), &output),
+                (NodeId("skipped".to_string()), &none),
+            ]),
+            secrets: HashMap::new(),
+        };
+        let eval = |source| {
+            Template::parse_expression(source)
+                .unwrap()
+                .eval(&scope, &Type::Bool)
+        };
+        assert_eq!(
+            eval("case(nodes.review.output) == 'approved'"),
+            Ok(Val::Bool(true))
+        );
+        assert_eq!(
+            eval("${{ nodes.review.output.approved != 3 }}"),
+            Ok(Val::Bool(false))
+        );
+        assert_eq!(
+            eval("!(default(nodes.skipped.output.id, 0) == 0)"),
+            Ok(Val::Bool(false))
+        );
+    }
+
+    #[test]
+    fn test_secrets_are_not_transformed() {
+        let secret_type = None;
+        let scope = Scope {
+            index: None,
+            inputs: HashMap::new(),
+            item: None,
+            nodes: HashMap::new(),
+            secrets: HashMap::from([(SecretName("token".to_string()), &secret_type)]),
+        };
+        let check = |source: &str| {
+            Template::parse(source)
+                .unwrap()
+                .unwrap()
+                .check(&scope, &Type::String)
+        };
+        for function in ["case", "json", "len", "lower", "trim", "upper", "wave"] {
+            assert_eq!(
+                check(&format!("${{{{ {function}(secrets.token) }}}}")),
+                Err(format!(
+                    "{:?} cannot take a secret, which it would reveal",
+                    Function::from_name(function).unwrap()
+                ))
+            );
+        }
+        assert!(check("${{ concat('Bearer ', secrets.token) }}").is_err());
+        assert!(check("${{ upper('x' + secrets.token) }}").is_err());
+        assert!(check("Bearer ${{ secrets.token }}").is_ok());
+        assert!(check("${{ default(secrets.token, 'none') }}").is_ok());
+        for comparison in ["secrets.token == 'x'", "'x' != secrets.token + 'y'"] {
+            assert_eq!(
+                check(&format!("${{{{ {comparison} }}}}")),
+                Err("Comparisons cannot take a secret, which they would reveal".to_string())
+            );
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Helpers of the tests running workflows of small components written in WAT.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use workflow::Workflow;
+
+use crate::{
+    Runtime,
+    prototype::Prototype,
+    task::{Event, Task, TaskReport},
+};
+
+/// A component of small functions: `inc` adds 1 to `x`, `spin` never returns,
+/// `boom` traps, `fail` returns `err(42)`, `burn` loops `n` times, `grow`
+/// grows its memory by `pages`, `range` returns `[1, 2, 3, 4]`, `sum` adds up
+/// `xs`, `echo` returns `s`, `reject` returns `err(s)` and `guard` adds 1 to
+/// its parameter named `if`.
+pub(crate) const MATH: &str = r#"(component
+  (core module $m
+    (memory (export "memory") 1)
+    (data (i32.const 16) "\01\00\00\00\2a\00\00\00")
+    (func (export "inc") (param i32) (result i32) local.get 0 i32.const 1 i32.add)
+    (func (export "spin") (result i32) (loop br 0) i32.const 0)
+    (func (export "boom") (result i32) unreachable)
+    (func (export "fail") (result i32) i32.const 16)
+    (func (export "burn") (param i32) (result i32)
+      (block (loop
+        local.get 0 i32.eqz br_if 1
+        local.get 0 i32.const 1 i32.sub local.set 0
+        br 0))
+      i32.const 7)
+    (func (export "grow") (param i32) (result i32) local.get 0 memory.grow)
+    (data (i32.const 32) "\30\00\00\00\04\00\00\00")
+    (data (i32.const 48) "\01\00\00\00\02\00\00\00\03\00\00\00\04\00\00\00")
+    (func (export "range") (result i32) i32.const 32)
+    (global $heap (mut i32) (i32.const 1024))
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $p i32)
+      global.get $heap local.set $p
+      global.get $heap local.get 3 i32.add global.set $heap
+      local.get $p)
+    (func (export "sum") (param $ptr i32) (param $len i32) (result i32)
+      (local $acc i32)
+      (block (loop
+        local.get $len i32.eqz br_if 1
+        local.get $acc local.get $ptr i32.load i32.add local.set $acc
+        local.get $ptr i32.const 4 i32.add local.set $ptr
+        local.get $len i32.const 1 i32.sub local.set $len
+        br 0))
+      local.get $acc)
+    (func (export "echo") (param i32 i32) (result i32)
+      i32.const 128 local.get 0 i32.store
+      i32.const 132 local.get 1 i32.store
+      i32.const 128)
+    (func (export "reject") (param i32 i32) (result i32)
+      i32.const 136 i32.const 1 i32.store8
+      i32.const 140 local.get 0 i32.store
+      i32.const 144 local.get 1 i32.store
+      i32.const 136)
+  )
+  (core instance $i (instantiate $m))
+  (alias core export $i "memory" (core memory $mem))
+  (alias core export $i "realloc" (core func $realloc))
+  (func (export "inc") (param "x" u32) (result u32) (canon lift (core func $i "inc")))
+  (func (export "spin") (result u32) (canon lift (core func $i "spin")))
+  (func (export "boom") (result u32) (canon lift (core func $i "boom")))
+  (func (export "fail") (result (result u32 (error u32)))
+    (canon lift (core func $i "fail") (memory $mem)))
+  (func (export "burn") (param "n" u32) (result u32) (canon lift (core func $i "burn")))
+  (func (export "range") (result (list u32)) (canon lift (core func $i "range") (memory $mem)))
+  (func (export "sum") (param "xs" (list u32)) (result u32)
+    (canon lift (core func $i "sum") (memory $mem) (realloc $realloc)))
+  (func (export "echo") (param "s" string) (result string)
+    (canon lift (core func $i "echo") (memory $mem) (realloc $realloc)))
+  (func (export "grow") (param "pages" u32) (result s32) (canon lift (core func $i "grow")))
+  (func (export "reject") (param "s" string) (result (result u32 (error string)))
+    (canon lift (core func $i "reject") (memory $mem) (realloc $realloc)))
+  (func (export "guard") (param "if" u32) (result u32) (canon lift (core func $i "inc")))
+)"#;
+
+/// A component counting its calls in the `counts` bucket of `wasi:keyvalue`:
+/// `flaky` increments the counter of `key` and returns `err` with its value
+/// until it exceeds `failures`, `ok` then. It traps when an instance is called
+/// twice.
+pub(crate) const COUNTER: &str = r#"(component
+  (import "wasi:keyvalue/store@0.2.0-draft" (instance $store
+    (export $bucket "bucket" (type (sub resource)))
+    (type $e (variant (case "no-such-store") (case "access-denied") (case "other" string)))
+    (export $error "error" (type (eq $e)))
+    (type $own (own $bucket))
+    (export "open" (func (param "identifier" string) (result (result $own (error $error)))))))
+  (alias export $store "bucket" (type $bucket))
+  (alias export $store "error" (type $error))
+  (import "wasi:keyvalue/atomics@0.2.0-draft" (instance $atomics
+    (export $b "bucket" (type (eq $bucket)))
+    (export $e "error" (type (eq $error)))
+    (type $borrow (borrow $b))
+    (export "increment"
+      (func (param "bucket" $borrow) (param "key" string) (param "delta" u64)
+        (result (result u64 (error $e)))))))
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $p i32)
+      global.get $heap local.set $p
+      global.get $heap local.get 3 i32.add global.set $heap
+      local.get $p))
+  (core instance $libc (instantiate $libc))
+  (alias core export $libc "memory" (core memory $mem))
+  (alias core export $libc "realloc" (core func $realloc))
+  (core func $open (canon lower (func $store "open") (memory $mem) (realloc $realloc)))
+  (core func $increment
+    (canon lower (func $atomics "increment") (memory $mem) (realloc $realloc)))
+  (core module $m
+    (import "libc" "memory" (memory 1))
+    (import "host" "open" (func $open (param i32 i32 i32)))
+    (import "host" "increment" (func $increment (param i32 i32 i32 i64 i32)))
+    (data (i32.const 16) "counts")
+    (global $called (mut i32) (i32.const 0))
+    (func $incr (param $key i32) (param $len i32) (result i64)
+      (if (global.get $called) (then unreachable))
+      (global.set $called (i32.const 1))
+      (call $open (i32.const 16) (i32.const 6) (i32.const 64))
+      (if (i32.load8_u (i32.const 64)) (then unreachable))
+      (call $increment
+        (i32.load (i32.const 68)) (local.get $key) (local.get $len) (i64.const 1) (i32.const 80))
+      (if (i32.load8_u (i32.const 80)) (then unreachable))
+      (i64.load (i32.const 88)))
+    (func (export "flaky") (param $key i32) (param $len i32) (param $failures i32) (result i32)
+      (local $n i64)
+      (local.set $n (call $incr (local.get $key) (local.get $len)))
+      (i32.store8 (i32.const 96)
+        (i64.le_u (local.get $n) (i64.extend_i32_u (local.get $failures))))
+      (i64.store (i32.const 104) (local.get $n))
+      (i32.const 96)))
+  (core instance $i (instantiate $m
+    (with "libc" (instance $libc))
+    (with "host" (instance
+      (export "open" (func $open))
+      (export "increment" (func $increment))))))
+  (func (export "flaky") (param "key" string) (param "failures" u32)
+    (result (result u64 (error u32)))
+    (canon lift (core func $i "flaky") (memory $mem) (realloc $realloc)))
+)"#;
+
+/// Compiles a component written in WAT to a file of its own, returning its
+/// path.
+pub(crate) fn component(wat: &str) -> String {
+    static COMPONENTS: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("components-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!(
+        "{}.wasm",
+        COMPONENTS.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+    path.display().to_string()
+}
+
+/// Parses a workflow written in YAML, `$math` and `$counter` standing for the
+/// paths of `MATH` and `COUNTER`.
+pub(crate) fn workflow(yaml: &str) -> Workflow {
+    let mut yaml = yaml.to_string();
+    for (name, wat) in [("$math", MATH), ("$counter", COUNTER)] {
+        if yaml.contains(name) {
+            yaml = yaml.replace(name, &component(wat));
+        }
+    }
+    Workflow::parse(yaml.as_bytes()).unwrap()
+}
+
+/// Runs a workflow without arguments, returning its report and its events.
+pub(crate) async fn run(runtime: &mut Runtime, yaml: &str) -> (TaskReport, Vec<Event>) {
+    let prototype = Prototype::new(runtime, &workflow(yaml)).await.unwrap();
+    let mut task = Task::new(runtime, &prototype, &HashMap::new())
+        .await
+        .unwrap();
+    events(&mut task).await
+}
+
+/// Runs a task, returning its report and its events.
+pub(crate) async fn events(task: &mut Task) -> (TaskReport, Vec<Event>) {
+    let mut subscription = task.subscribe();
+    let report = task.run().await;
+    let mut events = Vec::new();
+    while let Some(record) = subscription.recv().await {
+        events.push(record.event);
+    }
+    (report, events)
+}
@@ -0,0 +1,815 @@
+//! Recording of what a task does, to reproduce a run elsewhere by replaying
+//! it.
+//!
+//! A `Trace` holds the inputs of a workflow, the output of every node that
+//! succeeded, and every call of a component: its parameters and outcome, what
+//! its clocks and random sources returned and the responses to its HTTP
+//! requests. The values of secrets are redacted from it, as from events.
+//!
+//! Replaying a trace gives every call of a component, identified by its node
+//! and the element of its list, what the same call got when recorded: the
+//! readings of its clocks, its random bytes and the responses to its requests,
+//! in order. Past them, the call gets new ones. A call whose parameters,
+//! outcome or host calls differ from the recorded ones emits
+//! `Event::ExecutionDiverged`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    Request, Response,
+    header::{CONTENT_LENGTH, TRANSFER_ENCODING},
+    http::response::Parts,
+};
+use rand::{RngCore, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+use wasmtime::component::Val;
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+use wasmtime_wasi_http::{
+    body::HyperOutgoingBody,
+    types::{
+        HostFutureIncomingResponse, IncomingResponse, OutgoingRequestConfig, default_send_request,
+    },
+};
+use workflow::{Argument, Capabilities, InputName, NodeId};
+
+use crate::{
+    deterministic::{self, Determinism, Fixtures, HttpFixture, VIRTUAL_EPOCH, VirtualClock},
+    secrets::Redactor,
+    task::Divergence,
+};
+
+/// What a task did.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Trace {
+    /// Calls of components, in the order they started
+    pub calls: Vec<Call>,
+    /// Values of the workflow's inputs
+    pub inputs: BTreeMap<String, TracedVal>,
+    /// Outputs of the nodes that succeeded, by node ID as in the events
+    pub outputs: BTreeMap<String, TracedVal>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(std::fs::write(path, serde_json::to_string_pretty(self)?)?)
+    }
+
+    /// Returns the recorded inputs as arguments of a new task.
+    pub fn arguments(&self) -> Result<HashMap<InputName, Argument>, Error> {
+        arguments(&self.inputs)
+    }
+}
+
+/// Turns recorded values of inputs into arguments of a new task.
+pub(crate) fn arguments(
+    inputs: &BTreeMap<String, TracedVal>,
+) -> Result<HashMap<InputName, Argument>, Error> {
+    inputs
+        .iter()
+        .map(|(input_name, val)| {
+            let wave = val
+                .to_val()?
+                .to_wave()
+                .map_err(|e| Error::Wave(e.to_string()))?;
+            Ok((InputName(input_name.clone()), Argument::Wave(wave)))
+        })
+        .collect()
+}
+
+/// A call of a component by a node, or by an element of its list.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Call {
+    /// Why the call failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Exchanges of the HTTP requests of the call, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub http: Vec<HttpFixture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    /// Bytes returned by the insecure random source
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "base64_bytes")]
+    pub insecure_random: Vec<u8>,
+    /// Returned by `wasi:random/insecure-seed`, when the component is granted
+    /// random sources, as a decimal string since JSON numbers lose precision
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "decimal")]
+    pub insecure_seed: Option<u128>,
+    /// Readings of the monotonic clock, in nanoseconds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub monotonic_clock: Vec<u64>,
+    /// ID of the node, as in the events
+    pub node_id: NodeId,
+    /// `None` when the function returns nothing, or did not return
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<TracedVal>,
+    pub params: Vec<TracedVal>,
+    /// Bytes returned by the secure random source
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "base64_bytes")]
+    pub secure_random: Vec<u8>,
+    /// Readings of the wall clock, in nanoseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wall_clock: Vec<u64>,
+}
+
+/// A value in a trace, tagged with its kind to be read back without its type.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TracedVal {
+    Bool(bool),
+    Char(char),
+    Enum(String),
+    Flags(Vec<String>),
+    Float32(f32),
+    Float64(f64),
+    List(Vec<TracedVal>),
+    Option(Option<Box<TracedVal>>),
+    Record(Vec<(String, TracedVal)>),
+    /// Resources are only recorded as such, and cannot be read back
+    Resource,
+    Result(Result<Option<Box<TracedVal>>, Option<Box<TracedVal>>>),
+    S8(i8),
+    S16(i16),
+    S32(i32),
+    S64(i64),
+    String(String),
+    Tuple(Vec<TracedVal>),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    Variant(String, Option<Box<TracedVal>>),
+}
+
+impl From<&Val> for TracedVal {
+    fn from(val: &Val) -> Self {
+        let traced = |val: &Option<Box<Val>>| val.as_deref().map(|val| Box::new(Self::from(val)));
+        let list = |vals: &[Val]| vals.iter().map(Self::from).collect();
+        match val {
+            Val::Bool(b) => Self::Bool(*b),
+            Val::S8(n) => Self::S8(*n),
+            Val::U8(n) => Self::U8(*n),
+            Val::S16(n) => Self::S16(*n),
+            Val::U16(n) => Self::U16(*n),
+            Val::S32(n) => Self::S32(*n),
+            Val::U32(n) => Self::U32(*n),
+            Val::S64(n) => Self::S64(*n),
+            Val::U64(n) => Self::U64(*n),
+            Val::Float32(x) => Self::Float32(*x),
+            Val::Float64(x) => Self::Float64(*x),
+            Val::Char(c) => Self::Char(*c),
+            Val::String(s) => Self::String(s.clone()),
+            Val::List(vals) => Self::List(list(vals)),
+            Val::Record(fields) => Self::Record(
+                fields
+                    .iter()
+                    .map(|(name, val)| (name.clone(), Self::from(val)))
+                    .collect(),
+            ),
+            Val::Tuple(vals) => Self::Tuple(list(vals)),
+            Val::Variant(case, payload) => Self::Variant(case.clone(), traced(payload)),
+            Val::Enum(case) => Self::Enum(case.clone()),
+            Val::Option(val) => Self::Option(traced(val)),
+            Val::Result(Ok(val)) => Self::Result(Ok(traced(val))),
+            Val::Result(Err(val)) => Self::Result(Err(traced(val))),
+            Val::Flags(flags) => Self::Flags(flags.clone()),
+            Val::Resource(_) => Self::Resource,
+        }
+    }
+}
+
+impl TracedVal {
+    pub fn to_val(&self) -> Result<Val, Error> {
+        let val = |traced: &Option<Box<Self>>| {
+            traced
+                .as_deref()
+                .map(|traced| traced.to_val().map(Box::new))
+                .transpose()
+        };
+        let list = |traced: &[Self]| traced.iter().map(Self::to_val).collect::<Result<_, _>>();
+        Ok(match self {
+            Self::Bool(b) => Val::Bool(*b),
+            Self::Char(c) => Val::Char(*c),
+            Self::Enum(case) => Val::Enum(case.clone()),
+            Self::Flags(flags) => Val::Flags(flags.clone()),
+            Self::Float32(x) => Val::Float32(*x),
+            Self::Float64(x) => Val::Float64(*x),
+            Self::List(traced) => Val::List(list(traced)?),
+            Self::Option(traced) => Val::Option(val(traced)?),
+            Self::Record(fields) => Val::Record(
+                fields
+                    .iter()
+                    .map(|(name, traced)| Ok((name.clone(), traced.to_val()?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            Self::Resource => Err(Error::Resource)?,
+            Self::Result(Ok(traced)) => Val::Result(Ok(val(traced)?)),
+            Self::Result(Err(traced)) => Val::Result(Err(val(traced)?)),
+            Self::S8(n) => Val::S8(*n),
+            Self::S16(n) => Val::S16(*n),
+            Self::S32(n) => Val::S32(*n),
+            Self::S64(n) => Val::S64(*n),
+            Self::String(s) => Val::String(s.clone()),
+            Self::Tuple(traced) => Val::Tuple(list(traced)?),
+            Self::U8(n) => Val::U8(*n),
+            Self::U16(n) => Val::U16(*n),
+            Self::U32(n) => Val::U32(*n),
+            Self::U64(n) => Val::U64(*n),
+            Self::Variant(case, traced) => Val::Variant(case.clone(), val(traced)?),
+        })
+    }
+}
+
+/// Records the trace of a task, shared by the tasks of the workflows its
+/// nodes use.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    calls: Mutex<Vec<Arc<Mutex<Call>>>>,
+    inputs: BTreeMap<String, TracedVal>,
+    outputs: Mutex<BTreeMap<String, TracedVal>>,
+    redactor: Arc<Redactor>,
+}
+
+impl Recorder {
+    pub(crate) fn new(inputs: &HashMap<InputName, Val>, redactor: Arc<Redactor>) -> Self {
+        let inputs = inputs
+            .iter()
+            .map(|(input_name, val)| (input_name.0.clone(), traced(&redactor, val)))
+            .collect();
+        Self {
+            calls: Mutex::default(),
+            inputs,
+            outputs: Mutex::default(),
+            redactor,
+        }
+    }
+
+    /// Starts recording a call, replaying the given recorded call, if any.
+    pub(crate) fn call(
+        &self,
+        node_id: NodeId,
+        index: Option<u32>,
+        params: &[Val],
+        replayed: Option<Call>,
+    ) -> CallTrace {
+        let call = Arc::new(Mutex::new(Call {
+            error: None,
+            http: Vec::new(),
+            index,
+            insecure_random: Vec::new(),
+            insecure_seed: None,
+            monotonic_clock: Vec::new(),
+            node_id,
+            output: None,
+            params: params
+                .iter()
+                .map(|val| traced(&self.redactor, val))
+                .collect(),
+            secure_random: Vec::new(),
+            wall_clock: Vec::new(),
+        }));
+        self.calls.lock().unwrap().push(Arc::clone(&call));
+        CallTrace {
+            call,
+            redactor: Arc::clone(&self.redactor),
+            replayed: replayed.map(|call| {
+                Arc::new(Replayed {
+                    fixtures: Mutex::new(Fixtures::new(call.http.clone())),
+                    call,
+                })
+            }),
+        }
+    }
+
+    /// Records the output of a node, `node_id` being its ID in the events.
+    pub(crate) fn output(&self, node_id: &NodeId, val: &Val) {
+        let traced = traced(&self.redactor, val);
+        self.outputs
+            .lock()
+            .unwrap()
+            .insert(node_id.0.clone(), traced);
+    }
+
+    pub(crate) fn trace(&self) -> Trace {
+        Trace {
+            calls: self
+                .calls
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|call| call.lock().unwrap().clone())
+                .collect(),
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.lock().unwrap().clone(),
+        }
+    }
+}
+
+fn traced(redactor: &Redactor, val: &Val) -> TracedVal {
+    TracedVal::from(&redactor.redact_val(val))
+}
+
+/// Hands the recorded calls out to the calls replaying them, in order.
+#[derive(Debug)]
+pub(crate) struct Replayer {
+    calls: Mutex<CallsByNode>,
+}
+
+/// Recorded calls, by node and element of its list.
+type CallsByNode = HashMap<(NodeId, Option<u32>), VecDeque<Call>>;
+
+impl Replayer {
+    pub(crate) fn new(calls: Vec<Call>) -> Self {
+        let mut by_node = CallsByNode::new();
+        for call in calls {
+            by_node
+                .entry((call.node_id.clone(), call.index))
+                .or_default()
+                .push_back(call);
+        }
+        Self {
+            calls: Mutex::new(by_node),
+        }
+    }
+
+    /// Takes the next recorded call of the node, `node_id` being its ID in the
+    /// events.
+    pub(crate) fn next(&self, node_id: &NodeId, index: Option<u32>) -> Option<Call> {
+        self.calls
+            .lock()
+            .unwrap()
+            .get_mut(&(node_id.clone(), index))?
+            .pop_front()
+    }
+}
+
+/// The recording of a call of a component, replaying a recorded call.
+#[derive(Clone, Debug)]
+pub(crate) struct CallTrace {
+    call: Arc<Mutex<Call>>,
+    redactor: Arc<Redactor>,
+    replayed: Option<Arc<Replayed>>,
+}
+
+#[derive(Debug)]
+struct Replayed {
+    call: Call,
+    fixtures: Mutex<Fixtures>,
+}
+
+impl CallTrace {
+    /// Gives the component clocks and random sources recording what they
+    /// return, unless it is denied them. They replay the recorded call first,
+    /// then read the host's, or in deterministic mode virtual and seeded ones.
+    pub(crate) fn sandbox(
+        &self,
+        builder: &mut WasiCtxBuilder,
+        capabilities: &Capabilities,
+        determinism: Option<&Determinism>,
+        node_id: &NodeId,
+        index: Option<u32>,
+    ) {
+        let replayed = self.replayed.as_ref().map(|replayed| &replayed.call);
+        if capabilities.clocks {
+            let (wall_clock, monotonic_clock): (
+                Box<dyn HostWallClock>,
+                Box<dyn HostMonotonicClock>,
+            ) = match determinism {
+                Some(_) => (
+                    Box::new(VirtualClock::new(VIRTUAL_EPOCH)),
+                    Box::new(VirtualClock::new(Duration::ZERO)),
+                ),
+                None => (Box::new(HostClock::new()), Box::new(HostClock::new())),
+            };
+            builder
+                .wall_clock(Traced::new(
+                    &self.call,
+                    wall_clock,
+                    replayed.map(|call| &call.wall_clock[..]),
+                    |call| &mut call.wall_clock,
+                ))
+                .monotonic_clock(Traced::new(
+                    &self.call,
+                    monotonic_clock,
+                    replayed.map(|call| &call.monotonic_clock[..]),
+                    |call| &mut call.monotonic_clock,
+                ));
+        }
+        if capabilities.random {
+            let rng = || -> Box<dyn RngCore + Send> {
+                match determinism {
+                    Some(determinism) => Box::new(determinism.rng(node_id, index)),
+                    None => Box::new(StdRng::from_entropy()),
+                }
+            };
+            let insecure_seed = match (replayed.and_then(|call| call.insecure_seed), determinism) {
+                (Some(insecure_seed), _) => insecure_seed,
+                (None, Some(determinism)) => determinism.seed(node_id, index).into(),
+                (None, None) => rand::random(),
+            };
+            self.call.lock().unwrap().insecure_seed = Some(insecure_seed);
+            builder
+                .secure_random(Traced::new(
+                    &self.call,
+                    rng(),
+                    replayed.map(|call| &call.secure_random[..]),
+                    |call| &mut call.secure_random,
+                ))
+                .insecure_random(Traced::new(
+                    &self.call,
+                    rng(),
+                    replayed.map(|call| &call.insecure_random[..]),
+                    |call| &mut call.insecure_random,
+                ))
+                .insecure_random_seed(insecure_seed);
+        }
+    }
+
+    /// Sends the request, recording the exchange once the whole response is
+    /// received. The request is answered with the recorded exchanges first,
+    /// then by fixtures in deterministic mode.
+    pub(crate) fn send_request(
+        &self,
+        request: Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+        determinism: Option<&Determinism>,
+    ) -> HostFutureIncomingResponse {
+        let method = request.method().to_string();
+        // Recorded exchanges hold redacted URIs
+        let shown_uri = self.redactor.redact(&request.uri().to_string());
+        let between_bytes_timeout = config.between_bytes_timeout;
+        let response = match (&self.replayed, determinism) {
+            (Some(replayed), _) => {
+                let mut fixtures = replayed.fixtures.lock().unwrap();
+                let fixture = fixtures.take(&method, &shown_uri).cloned();
+                deterministic::answer(request, fixture, shown_uri.clone(), between_bytes_timeout)
+            }
+            (None, Some(determinism)) => {
+                determinism.send_request(request, shown_uri.clone(), between_bytes_timeout)
+            }
+            (None, None) => default_send_request(request, config),
+        };
+
+        let call = Arc::clone(&self.call);
+        let redactor = Arc::clone(&self.redactor);
+        let handle = wasmtime_wasi::runtime::spawn(async move {
+            let response = match response {
+                HostFutureIncomingResponse::Pending(handle) => handle.await?,
+                HostFutureIncomingResponse::Ready(response) => response?,
+                HostFutureIncomingResponse::Consumed => unreachable!("the request was just sent"),
+            };
+            let IncomingResponse {
+                resp,
+                worker,
+                between_bytes_timeout,
+            } = match response {
+                Ok(response) => response,
+                Err(e) => return Ok(Err(e)),
+            };
+            let (parts, body) = resp.into_parts();
+            let body = match body.collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(e) => return Ok(Err(e)),
+            };
+            let exchange = exchange(&redactor, method, shown_uri, &parts, &body);
+            call.lock().unwrap().http.push(exchange);
+            let body = Full::new(body).map_err(|never| match never {}).boxed();
+            Ok(Ok(IncomingResponse {
+                resp: Response::from_parts(parts, body),
+                worker,
+                between_bytes_timeout,
+            }))
+        });
+        HostFutureIncomingResponse::pending(handle)
+    }
+
+    /// Records the outcome of the call.
+    pub(crate) fn settle(&self, output: Option<&Val>, error: Option<String>) {
+        let mut call = self.call.lock().unwrap();
+        call.output = output.map(|val| traced(&self.redactor, val));
+        call.error = error.map(|error| self.redactor.redact(&error));
+    }
+
+    /// Returns the fields of the call differing from the recorded one, or the
+    /// whole call as `call` when none was recorded.
+    pub(crate) fn divergences(&self) -> BTreeMap<String, Divergence> {
+        let json = |call: &Call| match serde_json::to_value(call) {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => unreachable!("calls are objects"),
+        };
+        let call = self.call.lock().unwrap();
+        let Some(replayed) = &self.replayed else {
+            return BTreeMap::from([(
+                "call".to_string(),
+                Divergence {
+                    recorded: serde_json::Value::Null,
+                    replayed: serde_json::Value::Object(json(&call)),
+                },
+            )]);
+        };
+        let recorded = json(&replayed.call);
+        let replayed = json(&call);
+        let names: BTreeSet<_> = recorded.keys().chain(replayed.keys()).collect();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let recorded = recorded.get(name).cloned().unwrap_or_default();
+                let replayed = replayed.get(name).cloned().unwrap_or_default();
+                (recorded != replayed).then(|| (name.clone(), Divergence { recorded, replayed }))
+            })
+            .collect()
+    }
+}
+
+/// Records an HTTP exchange as the fixture replaying it, redacted.
+fn exchange(
+    redactor: &Redactor,
+    method: String,
+    uri: String,
+    parts: &Parts,
+    body: &[u8],
+) -> HttpFixture {
+    let mut headers = BTreeMap::<String, String>::new();
+    for (name, value) in &parts.headers {
+        // The body may change once redacted
+        if name == CONTENT_LENGTH || name == TRANSFER_ENCODING {
+            continue;
+        }
+        let value = redactor.redact(&String::from_utf8_lossy(value.as_bytes()));
+        match headers.get_mut(name.as_str()) {
+            Some(values) => {
+                values.push_str(", ");
+                values.push_str(&value);
+            }
+            None => {
+                headers.insert(name.to_string(), value);
+            }
+        }
+    }
+    let (base64, body) = match std::str::from_utf8(body) {
+        Ok(text) => (false, redactor.redact(text)),
+        Err(_) => (true, STANDARD.encode(body)),
+    };
+    HttpFixture {
+        base64,
+        body,
+        headers,
+        method,
+        status: parts.status.as_u16(),
+        uri,
+    }
+}
+
+/// A clock or a random source of a call, returning what it returned when
+/// recorded first, then what its source returns, recording it.
+struct Traced<S: ?Sized, T> {
+    call: Arc<Mutex<Call>>,
+    field: fn(&mut Call) -> &mut Vec<T>,
+    replay: Mutex<VecDeque<T>>,
+    source: Box<S>,
+}
+
+impl<S: ?Sized, T: Copy> Traced<S, T> {
+    fn new(
+        call: &Arc<Mutex<Call>>,
+        source: Box<S>,
+        replay: Option<&[T]>,
+        field: fn(&mut Call) -> &mut Vec<T>,
+    ) -> Self {
+        Self {
+            call: Arc::clone(call),
+            field,
+            replay: Mutex::new(replay.unwrap_or_default().iter().copied().collect()),
+            source,
+        }
+    }
+
+    fn read(&self, read: impl FnOnce(&S) -> T) -> T {
+        let value = self.replay.lock().unwrap().pop_front();
+        let value = value.unwrap_or_else(|| read(&self.source));
+        self.record(&[value]);
+        value
+    }
+
+    fn record(&self, values: &[T]) {
+        (self.field)(&mut self.call.lock().unwrap()).extend_from_slice(values);
+    }
+}
+
+impl HostWallClock for Traced<dyn HostWallClock, u64> {
+    fn resolution(&self) -> Duration {
+        self.source.resolution()
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.read(|clock| clock.now().as_nanos() as u64))
+    }
+}
+
+impl HostMonotonicClock for Traced<dyn HostMonotonicClock, u64> {
+    fn resolution(&self) -> u64 {
+        self.source.resolution()
+    }
+
+    fn now(&self) -> u64 {
+        self.read(|clock| clock.now())
+    }
+}
+
+impl RngCore for Traced<dyn RngCore + Send, u8> {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let replay = self.replay.get_mut().unwrap();
+        let replayed = dest.len().min(replay.len());
+        for (byte, value) in dest.iter_mut().zip(replay.drain(..replayed)) {
+            *byte = value;
+        }
+        self.source.fill_bytes(&mut dest[replayed..]);
+        self.record(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Clocks of the host.
+struct HostClock {
+    origin: std::time::Instant,
+}
+
+impl HostClock {
+    fn new() -> Self {
+        Self {
+            origin: std::time::Instant::now(),
+        }
+    }
+}
+
+impl HostWallClock for HostClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+impl HostMonotonicClock for HostClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        self.origin.elapsed().as_nanos() as u64
+    }
+}
+
+/// Serializes bytes in base64.
+mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        STANDARD.decode(text).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serializes an optional seed as a decimal string.
+mod decimal {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        seed: &Option<u128>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match seed {
+            Some(seed) => serializer.serialize_some(&seed.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u128>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| text.parse().map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    File(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Resources cannot be replayed")]
+    Resource,
+    #[error("Invalid recorded value: {0}")]
+    Wave(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deterministic::Deterministic;
+
+    #[tokio::test]
+    async fn test_recorded_calls_replay_without_divergence() {
+        let uri = "https://api.test/?key=hunter2";
+        let send = async |trace: &CallTrace, determinism: Option<&Determinism>| {
+            let request = Request::builder()
+                .uri(uri)
+                .body(
+                    http_body_util::Empty::new()
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap();
+            let config = OutgoingRequestConfig {
+                use_tls: true,
+                connect_timeout: Duration::from_secs(1),
+                first_byte_timeout: Duration::from_secs(1),
+                between_bytes_timeout: Duration::from_secs(1),
+            };
+            let HostFutureIncomingResponse::Pending(handle) =
+                trace.send_request(request, config, determinism)
+            else {
+                unreachable!("responses are pending until the body is read");
+            };
+            let response = handle.await.unwrap().unwrap().resp;
+            response.into_body().collect().await.unwrap().to_bytes()
+        };
+        let determinism = Determinism::new(Deterministic {
+            http_fixtures: vec![HttpFixture {
+                base64: true,
+                body: STANDARD.encode([0xff, 0x00]),
+                headers: BTreeMap::new(),
+                method: "GET".to_string(),
+                status: 200,
+                uri: uri.to_string(),
+            }],
+            seed: 0,
+        });
+        let redactor = Arc::new(Redactor::new(["hunter2"]));
+        let node_id = NodeId("fetch".to_string());
+        let params = [Val::Record(vec![(
+            "tags".to_string(),
+            Val::List(vec![Val::Enum("a".to_string()), Val::Char('b')]),
+        )])];
+
+        let recorder = Recorder::new(&HashMap::new(), Arc::clone(&redactor));
+        let trace = recorder.call(node_id.clone(), None, &params, None);
+        assert_eq!(send(&trace, Some(&determinism)).await[..], [0xff, 0x00]);
+        trace.settle(Some(&Val::U8(1)), None);
+        trace.call.lock().unwrap().insecure_seed = Some(u128::MAX);
+        let recorded = recorder.trace();
+        assert_eq!(recorded.calls[0].http[0].uri, "https://api.test/?key=***");
+
+        // Read back, the trace answers the request without fixtures
+        let json = serde_json::to_string(&recorded).unwrap();
+        let mut recorded: Trace = serde_json::from_str(&json).unwrap();
+        assert_eq!(recorded.calls[0].params[0].to_val().unwrap(), params[0]);
+        let replayer = Replayer::new(std::mem::take(&mut recorded.calls));
+        let replayed = replayer.next(&node_id, None);
+        assert!(replayer.next(&node_id, None).is_none());
+        let trace = recorder.call(node_id, None, &params, replayed);
+        assert_eq!(send(&trace, None).await[..], [0xff, 0x00]);
+        trace.settle(Some(&Val::U8(2)), None);
+        trace.call.lock().unwrap().insecure_seed = Some(u128::MAX);
+        assert_eq!(trace.divergences().keys().collect::<Vec<_>>(), ["output"]);
+    }
+}
@@ -0,0 +1,234 @@
+//! Checking of the WIT types written in workflows against component types.
+//!
+//! Builtin types (`u32`, `string`, `list<T>`, `option<T>`, `result<T, E>`,
+//! `tuple<..>`, ...) are checked structurally. Components do not expose the
+//! names of their own types, so any other name matches any record, variant,
+//! enum, flags or resource.
+
+use wasmtime::{
+    Engine,
+    component::{Component, Type, types::ComponentItem},
+};
+
+/// Tells whether `ty` is an instance of the WIT type `wit`, failing when `wit`
+/// cannot be parsed.
+pub(crate) fn matches(wit: &str, ty: &Type) -> Result<bool, String> {
+    Ok(parse(wit)?.matches(ty))
+}
+
+/// Builds the component type of the WIT type `wit`, `None` when it refers to
+/// types defined by components, whose definition is unknown.
+pub(crate) fn builtin_type(wit: &str, engine: &Engine) -> Result<Option<Type>, String> {
+    let Some(wat) = parse(wit)?.to_wat() else {
+        return Ok(None);
+    };
+
+    // Types only exist within components, so we build one importing a
+    // function with a parameter of this type
+    let wat = format!(r#"(component (import "f" (func (param "p" {wat}))))"#);
+    let component = Component::new(engine, wat).map_err(|e| format!("{e:#}"))?;
+    let (_, item) = component.component_type().imports(engine).next().unwrap();
+    let ComponentItem::ComponentFunc(func) = item else {
+        unreachable!("the component only imports a function");
+    };
+    Ok(func.params().next().map(|(_, ty)| ty))
+}
+
+/// Tells whether two types have the same structure, whatever the component
+/// defining them.
+pub(crate) fn same_shape(a: &Type, b: &Type) -> bool {
+    let same_optional = |a: Option<Type>, b: Option<Type>| match (a, b) {
+        (Some(a), Some(b)) => same_shape(&a, &b),
+        (None, None) => true,
+        _ => false,
+    };
+    match (a, b) {
+        (Type::List(a), Type::List(b)) => same_shape(&a.ty(), &b.ty()),
+        (Type::Record(a), Type::Record(b)) => {
+            a.fields().len() == b.fields().len()
+                && a.fields()
+                    .zip(b.fields())
+                    .all(|(a, b)| a.name == b.name && same_shape(&a.ty, &b.ty))
+        }
+        (Type::Tuple(a), Type::Tuple(b)) => {
+            a.types().len() == b.types().len()
+                && a.types().zip(b.types()).all(|(a, b)| same_shape(&a, &b))
+        }
+        (Type::Variant(a), Type::Variant(b)) => {
+            a.cases().len() == b.cases().len()
+                && a.cases()
+                    .zip(b.cases())
+                    .all(|(a, b)| a.name == b.name && same_optional(a.ty, b.ty))
+        }
+        (Type::Enum(a), Type::Enum(b)) => a.names().eq(b.names()),
+        (Type::Option(a), Type::Option(b)) => same_shape(&a.ty(), &b.ty()),
+        (Type::Result(a), Type::Result(b)) => {
+            same_optional(a.ok(), b.ok()) && same_optional(a.err(), b.err())
+        }
+        (Type::Flags(a), Type::Flags(b)) => a.names().eq(b.names()),
+        (a, b) => a == b,
+    }
+}
+
+/// A WIT type: its name and type arguments, `_` standing for no type.
+struct WitType {
+    args: Vec<WitType>,
+    name: String,
+}
+
+impl WitType {
+    fn matches(&self, ty: &Type) -> bool {
+        let arg = |i: usize| self.args.get(i).filter(|arg| arg.name != "_");
+        let matches_optional = |wit: Option<&WitType>, ty: Option<Type>| match (wit, ty) {
+            (Some(wit), Some(ty)) => wit.matches(&ty),
+            (None, None) => true,
+            _ => false,
+        };
+        match (self.name.as_str(), self.args.len(), ty) {
+            ("bool", 0, Type::Bool)
+            | ("s8", 0, Type::S8)
+            | ("u8", 0, Type::U8)
+            | ("s16", 0, Type::S16)
+            | ("u16", 0, Type::U16)
+            | ("s32", 0, Type::S32)
+            | ("u32", 0, Type::U32)
+            | ("s64", 0, Type::S64)
+            | ("u64", 0, Type::U64)
+            | ("f32" | "float32", 0, Type::Float32)
+            | ("f64" | "float64", 0, Type::Float64)
+            | ("char", 0, Type::Char)
+            | ("string", 0, Type::String) => true,
+            ("list", 1, Type::List(list)) => self.args[0].matches(&list.ty()),
+            ("option", 1, Type::Option(option)) => self.args[0].matches(&option.ty()),
+            ("result", 0..=2, Type::Result(result)) => {
+                matches_optional(arg(0), result.ok()) && matches_optional(arg(1), result.err())
+            }
+            ("tuple", _, Type::Tuple(tuple)) => {
+                tuple.types().len() == self.args.len()
+                    && tuple
+                        .types()
+                        .zip(&self.args)
+                        .all(|(ty, wit)| wit.matches(&ty))
+            }
+            (name, 0, ty) if !is_builtin(name) => matches!(
+                ty,
+                Type::Record(_)
+                    | Type::Variant(_)
+                    | Type::Enum(_)
+                    | Type::Flags(_)
+                    | Type::Own(_)
+                    | Type::Borrow(_)
+            ),
+            _ => false,
+        }
+    }
+
+    /// Writes the type in the text format of components, `None` for types
+    /// defined by components.
+    fn to_wat(&self) -> Option<String> {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| match arg.name.as_str() {
+                "_" => Some(None),
+                _ => arg.to_wat().map(Some),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let name = match self.name.as_str() {
+            "f32" => "float32",
+            "f64" => "float64",
+            name if !is_builtin(name) => return None,
+            name => name,
+        };
+        Some(match (name, args.as_slice()) {
+            ("result", []) => "(result)".to_string(),
+            ("result", [ok]) => format!("(result {})", ok.as_deref().unwrap_or_default()),
+            ("result", [ok, Some(err)]) => {
+                format!(
+                    "(result {} (error {err}))",
+                    ok.as_deref().unwrap_or_default()
+                )
+            }
+            (name, []) => name.to_string(),
+            (name, args) => {
+                let args = args.iter().flatten().cloned().collect::<Vec<_>>();
+                format!("({name} {})", args.join(" "))
+            }
+        })
+    }
+}
+
+fn is_builtin(name: &str) -> bool {
+    matches!(
+        name,
+        "bool"
+            | "s8"
+            | "u8"
+            | "s16"
+            | "u16"
+            | "s32"
+            | "u32"
+            | "s64"
+            | "u64"
+            | "f32"
+            | "float32"
+            | "f64"
+            | "float64"
+            | "char"
+            | "string"
+            | "list"
+            | "option"
+            | "result"
+            | "tuple"
+    )
+}
+
+fn parse(wit: &str) -> Result<WitType, String> {
+    let mut parser = Parser { rest: wit };
+    let wit_type = parser.parse()?;
+    match parser.rest.trim() {
+        "" => Ok(wit_type),
+        rest => Err(format!("Unexpected {rest:?} in WIT type {wit:?}")),
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn parse(&mut self) -> Result<WitType, String> {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(format!("Expected a type name, found {:?}", self.rest));
+        }
+        let (name, rest) = self.rest.split_at(end);
+        self.rest = rest.trim_start();
+
+        let mut args = Vec::new();
+        if let Some(rest) = self.rest.strip_prefix('<') {
+            self.rest = rest;
+            loop {
+                args.push(self.parse()?);
+                self.rest = self.rest.trim_start();
+                if let Some(rest) = self.rest.strip_prefix(',') {
+                    self.rest = rest;
+                } else if let Some(rest) = self.rest.strip_prefix('>') {
+                    self.rest = rest;
+                    break;
+                } else {
+                    return Err(format!("Expected ',' or '>', found {:?}", self.rest));
+                }
+            }
+        }
+
+        Ok(WitType {
+            args,
+            name: name.to_string(),
+        })
+    }
+}
@@ -1,31 +1,88 @@
+mod argument;
+mod capabilities;
+mod dependency;
 mod edge;
+mod for_each;
+mod http;
+mod input;
+mod limits;
 mod node;
+mod repeat;
+mod retry;
 mod types;
 use std::{collections::HashMap, path::PathBuf};
 
-pub use edge::*;
 use file_source::FileSource;
+
+pub use argument::*;
+pub use capabilities::*;
+pub use dependency::*;
+pub use edge::*;
+pub use for_each::*;
+pub use http::*;
+pub use input::*;
+pub use limits::*;
 pub use node::*;
-use serde::{Deserialize, Serialize};
+pub use repeat::*;
+pub use retry::*;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 pub use types::*;
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Workflow {
-    pub dependencies: HashMap<ComponentName, FileSource>,
+    pub dependencies: HashMap<ComponentName, Dependency>,
     pub edges: Vec<Edge>,
+    /// Optional fuel budget shared by all the nodes of an execution
+    #[serde(default)]
+    pub fuel: Option<u64>,
+    /// Optional parameters of the workflow, provided to every execution
+    #[serde(default)]
+    pub inputs: HashMap<InputName, Input>,
+    /// Optional name of the workflow, scoping the buckets its components open
+    /// in the key-value store
+    #[serde(default)]
+    pub name: Option<String>,
     pub nodes: HashMap<NodeId, Node>,
+    /// Optional results of the workflow, each the output of a node
+    #[serde(default)]
+    pub outputs: HashMap<OutputName, NodeId>,
+    /// Optional secrets the workflow refers to as `${{ secrets.<name> }}`,
+    /// whose values the runtime provides and never shows
+    #[serde(default)]
+    pub secrets: Vec<SecretName>,
+    /// Optional time limit of a whole execution of the workflow
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    /// Other workflows the nodes can use like components, their inputs and
+    /// outputs forming the nodes' parameters and output
+    #[serde(default)]
+    pub workflows: HashMap<ComponentName, FileSource>,
 }
 
 impl Workflow {
     pub fn load(path: &PathBuf) -> Result<Self, Error> {
-        let source = std::fs::read_to_string(path)?;
-        let is_json = path.extension().is_some_and(|ext| ext == "json");
-        let workflow: Workflow = match is_json {
-            true => serde_json::from_str(&source)?,
-            false => serde_yaml::from_str(&source)?,
-        };
-        Ok(workflow)
+        load(path)
     }
+
+    /// Parses a workflow written in YAML, or in JSON which YAML includes.
+    pub fn parse(source: &[u8]) -> Result<Self, Error> {
+        Ok(serde_yaml::from_slice(source)?)
+    }
+}
+
+/// Loads values of workflow inputs from a JSON or YAML file mapping input
+/// names to arguments.
+pub fn load_arguments(path: &PathBuf) -> Result<HashMap<InputName, Argument>, Error> {
+    load(path)
+}
+
+fn load<T: DeserializeOwned>(path: &PathBuf) -> Result<T, Error> {
+    let source = std::fs::read_to_string(path)?;
+    let is_json = path.extension().is_some_and(|ext| ext == "json");
+    Ok(match is_json {
+        true => serde_json::from_str(&source)?,
+        false => serde_yaml::from_str(&source)?,
+    })
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -46,5 +103,87 @@ mod tests {
     fn test_deserialize_workflow() {
         let json = include_str!("../../../examples/hello-world.json");
         let _: Workflow = serde_json::from_str(json).unwrap();
+        let json = include_str!("../../../examples/http.json");
+        let workflow: Workflow = serde_json::from_str(json).unwrap();
+        let http = &workflow.dependencies[&ComponentName("http".to_string())];
+        assert_eq!(http.capabilities.http.allow[0].host, "example.com");
+    }
+
+    #[test]
+    fn test_deserialize_retry_with_defaults() {
+        let retry: Retry = serde_json::from_str(r#"{ "max_attempts": 3, "delay": "2s" }"#).unwrap();
+        assert_eq!(retry.backoff, Backoff::Exponential);
+        assert_eq!(*retry.delay, std::time::Duration::from_secs(2));
+        assert_eq!(retry.on, vec![RetryOn::Trap]);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let duration = |s: &str| s.parse::<Duration>().map(|duration| *duration);
+        assert_eq!(duration("250"), Ok(std::time::Duration::from_millis(250)));
+        assert_eq!(duration("2h"), Ok(std::time::Duration::from_secs(7_200)));
+        assert!(duration("999999999999999999h").is_err());
+        assert!(duration("3d").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_dependency_with_limits() {
+        let yaml = "
+            short: ./short.wasm
+            long:
+              source: ./long.wasm
+              limits:
+                memory_bytes: 1048576
+        ";
+        let dependencies: HashMap<ComponentName, Dependency> = serde_yaml::from_str(yaml).unwrap();
+        let short = &dependencies[&ComponentName("short".to_string())];
+        let long = &dependencies[&ComponentName("long".to_string())];
+        assert_eq!(short.limits, None);
+        assert_eq!(long.limits.as_ref().unwrap().memory_bytes, Some(1048576));
+    }
+
+    #[test]
+    fn test_deserialize_dependency_with_capabilities() {
+        let yaml = "
+            short: ./short.wasm
+            long:
+              source: ./long.wasm
+              capabilities:
+                clocks: false
+                dirs:
+                  - { guest: /data, host: ./data }
+                env: { LEVEL: debug }
+        ";
+        let dependencies: HashMap<ComponentName, Dependency> = serde_yaml::from_str(yaml).unwrap();
+        let short = &dependencies[&ComponentName("short".to_string())];
+        let long = &dependencies[&ComponentName("long".to_string())];
+        assert_eq!(short.capabilities, Capabilities::default());
+        assert!(!long.capabilities.clocks && long.capabilities.random);
+        assert_eq!(long.capabilities.dirs[0].mode, DirMode::ReadOnly);
+        assert_eq!(long.capabilities.env["LEVEL"], "debug");
+    }
+
+    #[test]
+    fn test_parse_argument() {
+        assert_eq!("42".parse(), Ok(Argument::Json(serde_json::json!(42))));
+        assert_eq!(r#""42""#.parse(), Ok(Argument::Wave(r#""42""#.to_string())));
+    }
+
+    #[test]
+    fn test_deserialize_node_arguments() {
+        let yaml = "
+            run: greet
+            use: hello
+            with:
+              name: '\"world\"'
+              options: { loud: true, times: 2 }
+        ";
+        let node: Node = serde_yaml::from_str(yaml).unwrap();
+        let argument = |name: &str| &node.with[&InputName(name.to_string())];
+        assert_eq!(argument("name"), &Argument::Wave(r#""world""#.to_string()));
+        assert_eq!(
+            argument("options"),
+            &Argument::Json(serde_json::json!({ "loud": true, "times": 2 }))
+        );
     }
 }
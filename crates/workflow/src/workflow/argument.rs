@@ -0,0 +1,30 @@
+use std::{convert::Infallible, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// A manual input of a node, written either as a WAVE-encoded string or as a
+/// native JSON/YAML value converted according to the input's type.
+///
+/// Strings are read as WAVE, so a string input is written `'"hello"'`, unless
+/// they embed expressions such as `${{ inputs.<name> }}` or
+/// `${{ nodes.<id>.output }}`, evaluated when the node runs.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Argument {
+    // Tried first, as any string is also valid JSON
+    Wave(String),
+    Json(serde_json::Value),
+}
+
+/// Reads an argument given on a command line: JSON values other than strings
+/// are read as JSON, everything else as WAVE.
+impl FromStr for Argument {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match serde_json::from_str(s) {
+            Ok(serde_json::Value::String(_)) | Err(_) => Ok(Argument::Wave(s.to_string())),
+            Ok(value) => Ok(Argument::Json(value)),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::HttpPolicy;
+
+/// What a component may access through WASI.
+///
+/// A component sees no directory, environment variable, argument nor
+/// standard stream, and sends no HTTP request, unless granted here, while the
+/// clocks and the random generators are the host's unless denied, calling them
+/// then trapping.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// Arguments the component is given, as if run from a command line
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Whether the component reads the host's clocks, otherwise reading them
+    /// traps
+    #[serde(default = "Capabilities::default_granted")]
+    pub clocks: bool,
+    /// Directories of the host the component may access
+    #[serde(default)]
+    pub dirs: Vec<PreopenedDir>,
+    /// Environment variables the component sees, whose values may embed
+    /// secrets as `${{ secrets.<name> }}`
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Outgoing HTTP requests the component may send
+    #[serde(default)]
+    pub http: HttpPolicy,
+    /// Whether the component reads the host's stdin and writes to its stdout
+    /// and stderr, otherwise its output is captured into the task's events
+    #[serde(default)]
+    pub inherit_stdio: bool,
+    /// Whether the component reads the host's random generators, otherwise
+    /// reading them traps
+    #[serde(default = "Capabilities::default_granted")]
+    pub random: bool,
+}
+
+impl Capabilities {
+    fn default_granted() -> bool {
+        true
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            args: Vec::new(),
+            clocks: true,
+            dirs: Vec::new(),
+            env: HashMap::new(),
+            http: HttpPolicy::default(),
+            inherit_stdio: false,
+            random: true,
+        }
+    }
+}
+
+/// A directory of the host made available to a component.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PreopenedDir {
+    /// Path of the directory as seen by the component, such as `/data`
+    pub guest: String,
+    /// Path of the directory on the host
+    pub host: PathBuf,
+    #[serde(default)]
+    pub mode: DirMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirMode {
+    /// Files and entries can be read, nothing can be created nor written
+    #[default]
+    ReadOnly,
+    /// Files can be read, written, created and removed
+    ReadWrite,
+}
@@ -0,0 +1,51 @@
+use file_source::FileSource;
+use serde::{Deserialize, Serialize};
+
+use super::{Capabilities, Limits};
+
+/// A component the nodes of a workflow can use.
+///
+/// It is written either as its source only, e.g. `"./hello.wasm"`, or as an
+/// object holding its source and how it is sandboxed.
+#[derive(Clone, Debug, Serialize)]
+pub struct Dependency {
+    /// What the component may access through WASI
+    pub capabilities: Capabilities,
+    /// Resource limits replacing the runtime's ones for this component
+    pub limits: Option<Limits>,
+    /// Where to load the component from
+    pub source: FileSource,
+}
+
+impl<'de> Deserialize<'de> for Dependency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Detailed {
+            #[serde(default)]
+            capabilities: Capabilities,
+            #[serde(default)]
+            limits: Option<Limits>,
+            source: String,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Source(String),
+            Detailed(Box<Detailed>),
+        }
+
+        let (source, capabilities, limits) = match Raw::deserialize(deserializer)? {
+            Raw::Source(source) => (source, Capabilities::default(), None),
+            Raw::Detailed(detailed) => (detailed.source, detailed.capabilities, detailed.limits),
+        };
+        Ok(Self {
+            capabilities,
+            limits,
+            source: FileSource::parse(&source).map_err(serde::de::Error::custom)?,
+        })
+    }
+}
@@ -0,0 +1,17 @@
+use std::num::NonZeroU32;
+
+use serde::{Deserialize, Serialize};
+
+/// Runs a node once per element of a list, collecting its outputs into a list
+/// in the same order.
+///
+/// The node's `with` templates refer to the current element as `item` and to
+/// its position as `index`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ForEach {
+    /// Maximum number of elements processed at once, all of them by default
+    #[serde(default)]
+    pub concurrency: Option<NonZeroU32>,
+    /// Expression evaluating to a list, such as `nodes.fetch.output.ids`
+    pub items: String,
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Duration;
+
+/// Outgoing HTTP requests a component may send through `wasi:http`.
+///
+/// Requests matching none of the rules are denied, so a component sends no
+/// request by default.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HttpPolicy {
+    /// Requests the component may send, any of the rules allowing one
+    #[serde(default)]
+    pub allow: Vec<HttpRule>,
+    /// Headers added to every request, replacing those of the component, such
+    /// as an `authorization` the component never sees, whose values may embed
+    /// secrets as `${{ secrets.<name> }}`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Maximum size of the body of a request, in bytes
+    #[serde(default)]
+    pub max_body_bytes: Option<u64>,
+    /// Time limit to connect, to receive the first byte of the response, and
+    /// between the next chunks of its body
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+}
+
+/// Requests allowed by an `HttpPolicy`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct HttpRule {
+    /// Host name or IP address, `*.example.com` matching the subdomains of
+    /// `example.com`
+    pub host: String,
+    /// Methods allowed, such as `GET`, all of them when empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Ports allowed, only the default port of the scheme when empty
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    /// Schemes allowed, such as `http`
+    #[serde(default = "HttpRule::default_schemes")]
+    pub schemes: Vec<String>,
+}
+
+impl HttpRule {
+    fn default_schemes() -> Vec<String> {
+        vec!["https".to_string()]
+    }
+}
@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+use super::Argument;
+
+/// A parameter of a workflow, referenced from the nodes' `with` as
+/// `${{ inputs.<name> }}`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Input {
+    /// Optional value used when the input is not provided
+    #[serde(default)]
+    pub default: Option<Argument>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The WIT type of the input, e.g. `u32` or `list<string>`
+    pub r#type: String,
+}
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds of the resources a component may allocate while it runs.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Limits {
+    /// Maximum number of core instances, a component usually creating several
+    #[serde(default)]
+    pub instances: Option<usize>,
+    /// Maximum size of each linear memory, in bytes
+    #[serde(default)]
+    pub memory_bytes: Option<usize>,
+    /// Maximum number of elements of each table
+    #[serde(default)]
+    pub table_elements: Option<usize>,
+}
+
+impl Limits {
+    /// Returns these limits, replaced by the ones set in `overrides`.
+    pub fn overridden_by(&self, overrides: &Limits) -> Limits {
+        Limits {
+            instances: overrides.instances.or(self.instances),
+            memory_bytes: overrides.memory_bytes.or(self.memory_bytes),
+            table_elements: overrides.table_elements.or(self.table_elements),
+        }
+    }
+}
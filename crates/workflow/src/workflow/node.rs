@@ -2,15 +2,60 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
-use super::{ComponentName, FunctionName, InputName};
+use super::{Argument, ComponentName, Duration, ForEach, FunctionName, InputName, Repeat, Retry};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Node {
-    /// The function to run
-    pub run: FunctionName,
-    /// The component to use from the dependencies
+    /// Whether the outputs of the function are cached, by component, function
+    /// and parameters, a call with the same parameters reusing the output of
+    /// the previous one instead of running. Only suits pure functions
+    #[serde(default)]
+    pub cache: bool,
+    /// Optional time after which a cached output expires, never by default
+    #[serde(default)]
+    pub cache_ttl: Option<Duration>,
+    /// Optional list to run the function on, once per element
+    #[serde(default)]
+    pub for_each: Option<ForEach>,
+    /// Optional fuel budget of every call of the function
+    #[serde(default)]
+    pub fuel: Option<u64>,
+    /// Optional condition on the outputs of upstream nodes and the workflow's
+    /// inputs, such as `nodes.check.output == true`: the node is skipped when
+    /// it is false
+    #[serde(default)]
+    pub r#if: Option<String>,
+    /// Optional number of bytes kept of the stdout and of the stderr of every
+    /// call of the function, replacing the runtime's one
+    #[serde(default)]
+    pub max_log_bytes: Option<usize>,
+    /// Optional condition to run the function again until it holds
+    #[serde(default)]
+    pub repeat: Option<Repeat>,
+    /// Optional retry policy applied when the function fails
+    #[serde(default)]
+    pub retry: Option<Retry>,
+    /// The function of the component to run, omitted for workflows
+    #[serde(default)]
+    pub run: Option<FunctionName>,
+    /// Whether the nodes depending on this one are skipped along with it.
+    /// Otherwise, they run with `none` as the output of this node, which
+    /// templates can replace using `default(..)`
+    #[serde(default = "Node::default_skip_dependents")]
+    pub skip_dependents: bool,
+    /// Optional time limit of every call (and of every retry) of the function
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    /// The component to use from the dependencies, or the workflow to use
+    /// from the workflows
     pub r#use: ComponentName,
-    /// Optional manual inputs to the function (wasm_wave encoded)
+    /// Optional manual inputs to the function
     #[serde(default)]
-    pub with: HashMap<InputName, String>,
+    pub with: HashMap<InputName, Argument>,
+}
+
+impl Node {
+    fn default_skip_dependents() -> bool {
+        true
+    }
 }
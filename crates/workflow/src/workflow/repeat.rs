@@ -0,0 +1,24 @@
+use std::num::NonZeroU32;
+
+use serde::{Deserialize, Serialize};
+
+use super::Duration;
+
+/// Runs a node again until its output satisfies a condition, such as polling
+/// an API until a status is ready.
+///
+/// The iterations run one after the other with the same parameters, each of
+/// them with its own retries. To repeat several nodes, the node can use a
+/// workflow holding them.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Repeat {
+    /// Delay between the end of an iteration and the start of the next one
+    #[serde(default)]
+    pub delay: Option<Duration>,
+    /// Maximum number of iterations, the node failing when its output still
+    /// does not satisfy the condition after the last one
+    pub max_iterations: NonZeroU32,
+    /// Condition on the output of the last iteration, as `nodes.<id>.output`,
+    /// and the workflow's inputs, such as `nodes.poll.output.status == "ready"`
+    pub until: String,
+}
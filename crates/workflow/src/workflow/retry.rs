@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use super::Duration;
+
+/// How a node is retried when its call fails.
+///
+/// Every attempt runs against a fresh instance of the node's component, so a
+/// trap in one attempt never leaks into the next one.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Retry {
+    /// How the delay grows from one attempt to the next
+    #[serde(default)]
+    pub backoff: Backoff,
+    /// Delay before the first retry
+    #[serde(default = "Retry::default_delay")]
+    pub delay: Duration,
+    /// Fraction of the delay randomly added or removed, between 0 and 1
+    #[serde(default)]
+    pub jitter: f64,
+    /// Maximum number of attempts, the first call included
+    pub max_attempts: u32,
+    /// Upper bound of the delay between two attempts
+    #[serde(default)]
+    pub max_delay: Option<Duration>,
+    /// Kinds of failure that trigger a retry
+    #[serde(default = "Retry::default_on")]
+    pub on: Vec<RetryOn>,
+}
+
+impl Retry {
+    fn default_delay() -> Duration {
+        Duration(std::time::Duration::from_secs(1))
+    }
+
+    fn default_on() -> Vec<RetryOn> {
+        vec![RetryOn::Trap]
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backoff {
+    /// The same delay between every attempt
+    Constant,
+    /// The delay doubles after every attempt
+    #[default]
+    Exponential,
+    /// The delay grows by its initial value after every attempt
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetryOn {
+    /// The function returned the `err` case of a `result`
+    Error,
+    /// The call trapped
+    Trap,
+}
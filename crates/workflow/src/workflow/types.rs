@@ -1,4 +1,4 @@
-use std::{fmt, ops::Deref};
+use std::{fmt, ops::Deref, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
@@ -11,6 +11,77 @@ impl fmt::Display for ComponentName {
     }
 }
 
+/// A duration written either as a number of milliseconds or as a string with a
+/// unit suffix (`ms`, `s`, `m` or `h`), e.g. `250`, `"250ms"` or `"30s"`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Duration(pub std::time::Duration);
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}ms", self.0.as_millis())
+    }
+}
+
+impl Deref for Duration {
+    type Target = std::time::Duration;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromStr for Duration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (amount, unit) = s.split_at(split);
+        let amount: u64 = amount
+            .parse()
+            .map_err(|_| format!("Invalid duration: {s:?}"))?;
+        let millis_per_unit = match unit.trim() {
+            "" | "ms" => 1,
+            "s" => 1_000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            unit => return Err(format!("Invalid duration unit: {unit:?}")),
+        };
+        let millis = amount
+            .checked_mul(millis_per_unit)
+            .ok_or_else(|| format!("Duration too long: {s:?}"))?;
+        Ok(Self(std::time::Duration::from_millis(millis)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Duration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Millis(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Millis(millis) => Ok(Self(std::time::Duration::from_millis(millis))),
+            Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for Duration {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct FunctionName(pub String);
 
@@ -45,3 +116,21 @@ impl fmt::Display for NodeId {
         write!(f, "node:{}", self.0)
     }
 }
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct OutputName(pub String);
+
+impl fmt::Display for OutputName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "output:{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct SecretName(pub String);
+
+impl fmt::Display for SecretName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "secret:{}", self.0)
+    }
+}